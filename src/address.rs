@@ -1,4 +1,5 @@
 use crate::http_client::json_client;
+use crate::metrics::{timed, Upstream};
 use crate::myffme::address::Address;
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
@@ -23,7 +24,7 @@ pub async fn city_name_by_insee(insee: &str) -> Option<String> {
     debug!("GET {}", url.as_str());
     let client = json_client();
     let request = client.get(url.as_str()).build().ok()?;
-    let response = client.execute(request).await.ok()?;
+    let response = timed(Upstream::Geo, client.execute(request)).await.ok()?;
     #[cfg(test)]
     {
         println!("GET {}", url.as_str());
@@ -54,7 +55,7 @@ pub async fn alternate_city_names(insee_code: &str) -> Option<Vec<String>> {
     debug!("GET {}", url.as_str());
     let client = json_client();
     let request = client.get(url.as_str()).build().ok()?;
-    let response = client.execute(request).await.ok()?;
+    let response = timed(Upstream::Geo, client.execute(request)).await.ok()?;
     #[derive(Deserialize)]
     struct Result {
         #[serde(rename = "nom")]
@@ -91,7 +92,7 @@ pub async fn cities_by_zip_code(zip_code: &str) -> Option<Vec<City>> {
     debug!("GET {}", url.as_str());
     let client = json_client();
     let request = client.get(url.as_str()).build().ok()?;
-    let response = client.execute(request).await.ok()?;
+    let response = timed(Upstream::Geo, client.execute(request)).await.ok()?;
     #[cfg(test)]
     {
         println!("GET {}", url.as_str());
@@ -145,7 +146,7 @@ async fn address(insee: Option<&str>, text: &str) -> Option<Vec<Address>> {
     debug!("GET {}", url.as_str());
     let client = json_client();
     let request = client.get(url.as_str()).build().ok()?;
-    let response = client.execute(request).await.ok()?;
+    let response = timed(Upstream::Geo, client.execute(request)).await.ok()?;
     #[cfg(test)]
     let features = {
         println!("GET {}", url.as_str());
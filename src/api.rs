@@ -1,36 +1,216 @@
-use crate::category::Category;
+use crate::audit::{audit_log, record_changes, Actor, AuditFilter, Change};
+use crate::conflict::{
+    pending_conflicts, pushed_phone_fields, record_pushed, resolve_conflict, ConflictError,
+    ConflictResolution, ContactField,
+};
+use crate::duplicates::{find_duplicates, merge_users, MergeRequest};
+use crate::guardian::{acting_for, children, expected_license_type};
+use crate::license_history::retention;
+use crate::lifecycle::{inactive_members, Lifecycle};
+use crate::medical_certificate::{
+    certificate_status_from_query, restricted_members, upload_medical_certificate, DocumentFormat,
+    UploadError, MAX_DOCUMENT_SIZE,
+};
+use crate::metrics::{is_scraper_authorized, record_api_request, render};
+use crate::mycompet::calendar::{to_ics, today, upcoming_competitions, CalendarScope};
+use crate::mycompet::club_results;
+use crate::mycompet::registration::{
+    registrations, update_registrations, RegistrationError, RegistrationsUpdate,
+};
 use crate::myffme::email::update_email;
+use crate::myffme::link::{link_user, search_licensees, LicenseeSearch, LinkError, LinkRequest};
+use crate::myffme::phone::{add_phone_number, update_phone_number};
 use crate::myffme::LicenseFees;
-use crate::myffme::{add_missing_users, update_users_metadata, LicenseType};
+use crate::myffme::{
+    add_missing_users, update_users_metadata, CompetitionResult, LicenseType, STRUCTURE_ID,
+};
 use crate::order::{
     BaseLicensePrice, EquipmentRental, InsuranceLevel, InsuranceOption, Keyed, Priced,
 };
+use crate::price_history::price_history;
+use crate::privacy::{
+    approve_erasure, export_data, pending_erasures, request_erasure, ErasureApproval, ErasureError,
+};
+use crate::renewal::{campaign_status, open_renewal_link};
+use crate::scheduler::{job_states, pause_job, trigger_job, JobName};
 use crate::season::{current_season, is_during_discount_period};
+use crate::status::{record_sync, status, SyncKind};
 use crate::user::Metadata;
-use http_body_util::{Either, Empty, Full};
+use http_body_util::{BodyExt, Either, Empty, Full, LengthLimitError, Limited};
 use hyper::body::{Bytes, Incoming};
-use hyper::header::{ALLOW, CONTENT_TYPE};
+use hyper::header::{HeaderValue, ALLOW, AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_TYPE};
 use hyper::{Method, Request, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
+use std::time::SystemTime;
 use tiered_server::api::{Action, Extension};
 use tiered_server::headers::{GET, GET_POST, JSON, TEXT};
 use tiered_server::session::SessionState;
-use tiered_server::store::snapshot;
-use tiered_server::totp::action::Action::{AddEmail, UpdateEmail};
-use tiered_server::totp::action::{EmailAddition, EmailUpdate};
+use tiered_server::store::{snapshot, Snapshot};
+use tiered_server::totp::action::Action::{AddEmail, AddSms, UpdateEmail, UpdateSms};
+use tiered_server::totp::action::{EmailAddition, EmailUpdate, SmsAddition, SmsUpdate};
 use tiered_server::user::{Email, IdentificationMethod, User};
 use tracing::{debug, info, warn};
 
 pub struct ApiExtension;
 
-impl Extension for ApiExtension {
-    async fn handle_api_extension(
+impl ApiExtension {
+    async fn handle_request(
         &self,
         request: Request<Incoming>,
         server_name: &Arc<String>,
     ) -> Option<Response<Either<Full<Bytes>, Empty<Bytes>>>> {
         let path = request.uri().path().strip_prefix("/api")?;
+        if path == "/metrics" {
+            if request.method() != Method::GET {
+                let mut response = Response::builder();
+                let headers = response.headers_mut().unwrap();
+                headers.insert(ALLOW, GET);
+                info!("405 https://{server_name}/api/metrics");
+                return Some(
+                    response
+                        .status(StatusCode::METHOD_NOT_ALLOWED)
+                        .body(Either::Right(Empty::new()))
+                        .unwrap(),
+                );
+            }
+            return if is_scraper_authorized(request.headers().get(AUTHORIZATION))
+                || matches!(
+                    SessionState::from_headers(request.headers(), &snapshot()),
+                    SessionState::Valid { user, .. } if user.admin
+                ) {
+                debug!("200 https://{server_name}/api/metrics");
+                Some(
+                    Response::builder()
+                        .status(StatusCode::OK)
+                        .header(CONTENT_TYPE, TEXT)
+                        .body(Either::Left(Full::from(render())))
+                        .unwrap(),
+                )
+            } else {
+                info!("403 https://{server_name}/api/metrics");
+                Some(
+                    Response::builder()
+                        .status(StatusCode::FORBIDDEN)
+                        .body(Either::Right(Empty::new()))
+                        .unwrap(),
+                )
+            };
+        }
+        if path == "/competitions/results" {
+            if request.method() != Method::GET {
+                let mut response = Response::builder();
+                let headers = response.headers_mut().unwrap();
+                headers.insert(ALLOW, GET);
+                info!("405 https://{server_name}/api/competitions/results");
+                return Some(
+                    response
+                        .status(StatusCode::METHOD_NOT_ALLOWED)
+                        .body(Either::Right(Empty::new()))
+                        .unwrap(),
+                );
+            }
+            let snapshot = snapshot();
+            let results = club_results(snapshot.list::<User>("acc/").map(|(_, it)| it));
+            info!("200 https://{server_name}/api/competitions/results");
+            return Some(
+                Response::builder()
+                    .status(StatusCode::OK)
+                    .header(CONTENT_TYPE, JSON)
+                    .body(Either::Left(Full::from(
+                        serde_json::to_vec(&results).unwrap(),
+                    )))
+                    .unwrap(),
+            );
+        }
+        if path == "/competitions/calendar" || path == "/competitions/calendar.ics" {
+            if request.method() != Method::GET {
+                let mut response = Response::builder();
+                let headers = response.headers_mut().unwrap();
+                headers.insert(ALLOW, GET);
+                info!("405 https://{server_name}/api{path}");
+                return Some(
+                    response
+                        .status(StatusCode::METHOD_NOT_ALLOWED)
+                        .body(Either::Right(Empty::new()))
+                        .unwrap(),
+                );
+            }
+            let Some(scope) = CalendarScope::from_query(request.uri().query()) else {
+                info!("400 https://{server_name}/api{path}");
+                return Some(
+                    Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(Either::Right(Empty::new()))
+                        .unwrap(),
+                );
+            };
+            let timestamp = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as u32;
+            let competitions = upcoming_competitions(&snapshot(), scope, today(timestamp));
+            info!("200 https://{server_name}/api{path}");
+            return Some(if path.ends_with(".ics") {
+                Response::builder()
+                    .status(StatusCode::OK)
+                    .header(
+                        CONTENT_TYPE,
+                        HeaderValue::from_static("text/calendar; charset=utf-8"),
+                    )
+                    .body(Either::Left(Full::from(to_ics(&competitions, timestamp))))
+                    .unwrap()
+            } else {
+                Response::builder()
+                    .status(StatusCode::OK)
+                    .header(CONTENT_TYPE, JSON)
+                    .body(Either::Left(Full::from(
+                        serde_json::to_vec(&competitions).unwrap(),
+                    )))
+                    .unwrap()
+            });
+        }
+        if path == "/renewal" {
+            if request.method() != Method::GET {
+                let mut response = Response::builder();
+                let headers = response.headers_mut().unwrap();
+                headers.insert(ALLOW, GET);
+                info!("405 https://{server_name}/api/renewal");
+                return Some(
+                    response
+                        .status(StatusCode::METHOD_NOT_ALLOWED)
+                        .body(Either::Right(Empty::new()))
+                        .unwrap(),
+                );
+            }
+            let token = request
+                .uri()
+                .query()
+                .into_iter()
+                .flat_map(|it| it.split('&'))
+                .find_map(|it| it.strip_prefix("token="))
+                .filter(|it| !it.is_empty());
+            let offer = match token {
+                Some(token) => open_renewal_link(&snapshot(), token).await,
+                None => None,
+            };
+            return Some(if let Some(offer) = offer {
+                info!("200 https://{server_name}/api/renewal");
+                Response::builder()
+                    .status(StatusCode::OK)
+                    .header(CONTENT_TYPE, JSON)
+                    .body(Either::Left(Full::from(
+                        serde_json::to_vec(&offer).unwrap(),
+                    )))
+                    .unwrap()
+            } else {
+                info!("404 https://{server_name}/api/renewal");
+                Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Either::Right(Empty::new()))
+                    .unwrap()
+            });
+        }
         if let Some(path) = path.strip_prefix("/user") {
             if let Some(path) = path.strip_prefix("/admin") {
                 if path == "/prices" {
@@ -46,153 +226,1480 @@ impl Extension for ApiExtension {
                                 .unwrap(),
                         );
                     }
-                    let snapshot = snapshot();
-                    return if matches!(
-                        SessionState::from_headers(request.headers(), &snapshot),
-                        SessionState::Valid { user, .. } if user.admin
-                    ) {
-                        let base_license_price_in_cents =
-                            snapshot.get::<u16>(BaseLicensePrice.key());
-                        let license_types = [LicenseType::Child, LicenseType::Adult]
-                            .into_iter()
-                            .map(|license_type| PricedLicenseType {
-                                license_type,
-                                fees: snapshot.get::<LicenseFees>(license_type.key()),
-                            })
-                            .collect::<Vec<_>>();
-                        let insurance_levels = [
-                            InsuranceLevel::RC,
-                            InsuranceLevel::Base,
-                            InsuranceLevel::BasePlus,
-                            InsuranceLevel::BasePlusPlus,
-                        ]
-                        .into_iter()
-                        .map(|level| {
-                            let price_in_cents = level.price_in_cents(&snapshot, false);
-                            PricedLevel {
-                                level,
-                                price_in_cents,
-                            }
-                        })
-                        .collect::<Vec<_>>();
-                        let insurance_options = [
-                            InsuranceOption::Ski,
-                            InsuranceOption::MountainBike,
-                            InsuranceOption::SlacklineAndHighline,
-                            InsuranceOption::TrailRunning,
-                        ]
-                        .into_iter()
-                        .map(|option| {
-                            let price_in_cents = option.price_in_cents(&snapshot, false);
-                            PricedAddon {
-                                option,
-                                price_in_cents,
-                            }
-                        })
-                        .collect::<Vec<_>>();
-                        let equipment_rental_price_in_cents =
-                            EquipmentRental.price_in_cents(&snapshot, false);
-                        info!("200 https://{server_name}/api/user/admin/prices");
-                        Some(
+                    let snapshot = snapshot();
+                    return if matches!(
+                        SessionState::from_headers(request.headers(), &snapshot),
+                        SessionState::Valid { user, .. } if user.admin
+                    ) {
+                        let base_license_price_in_cents =
+                            snapshot.get::<u16>(BaseLicensePrice.key());
+                        let license_types = [LicenseType::Child, LicenseType::Adult]
+                            .into_iter()
+                            .map(|license_type| PricedLicenseType {
+                                license_type,
+                                fees: snapshot.get::<LicenseFees>(license_type.key()),
+                            })
+                            .collect::<Vec<_>>();
+                        let insurance_levels = [
+                            InsuranceLevel::RC,
+                            InsuranceLevel::Base,
+                            InsuranceLevel::BasePlus,
+                            InsuranceLevel::BasePlusPlus,
+                        ]
+                        .into_iter()
+                        .map(|level| {
+                            let price_in_cents = level.price_in_cents(&snapshot, false);
+                            PricedLevel {
+                                level,
+                                price_in_cents,
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                        let insurance_options = [
+                            InsuranceOption::Ski,
+                            InsuranceOption::MountainBike,
+                            InsuranceOption::SlacklineAndHighline,
+                            InsuranceOption::TrailRunning,
+                        ]
+                        .into_iter()
+                        .map(|option| {
+                            let price_in_cents = option.price_in_cents(&snapshot, false);
+                            PricedAddon {
+                                option,
+                                price_in_cents,
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                        let equipment_rental_price_in_cents =
+                            EquipmentRental.price_in_cents(&snapshot, false);
+                        info!("200 https://{server_name}/api/user/admin/prices");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::OK)
+                                .header(CONTENT_TYPE, JSON)
+                                .body(Either::Left(Full::from(
+                                    serde_json::to_vec(&AdminPrices {
+                                        base_license_price_in_cents,
+                                        license_types,
+                                        insurance_levels,
+                                        insurance_options,
+                                        equipment_rental_price_in_cents,
+                                    })
+                                    .unwrap(),
+                                )))
+                                .unwrap(),
+                        )
+                    } else {
+                        info!("403 https://{server_name}/api/user/admin/prices");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::FORBIDDEN)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        )
+                    };
+                } else if path == "/prices/history" {
+                    if request.method() != Method::GET {
+                        let mut response = Response::builder();
+                        let headers = response.headers_mut().unwrap();
+                        headers.insert(ALLOW, GET);
+                        info!("405 https://{server_name}/api/user/admin/prices/history");
+                        return Some(
+                            response
+                                .status(StatusCode::METHOD_NOT_ALLOWED)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let snapshot = snapshot();
+                    return if matches!(
+                        SessionState::from_headers(request.headers(), &snapshot),
+                        SessionState::Valid { user, .. } if user.admin
+                    ) {
+                        info!("200 https://{server_name}/api/user/admin/prices/history");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::OK)
+                                .header(CONTENT_TYPE, JSON)
+                                .body(Either::Left(Full::from(
+                                    serde_json::to_vec(&price_history(&snapshot)).unwrap(),
+                                )))
+                                .unwrap(),
+                        )
+                    } else {
+                        info!("403 https://{server_name}/api/user/admin/prices/history");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::FORBIDDEN)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        )
+                    };
+                } else if path == "/status" {
+                    if request.method() != Method::GET {
+                        let mut response = Response::builder();
+                        let headers = response.headers_mut().unwrap();
+                        headers.insert(ALLOW, GET);
+                        info!("405 https://{server_name}/api/user/admin/status");
+                        return Some(
+                            response
+                                .status(StatusCode::METHOD_NOT_ALLOWED)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let snapshot = snapshot();
+                    return if matches!(
+                        SessionState::from_headers(request.headers(), &snapshot),
+                        SessionState::Valid { user, .. } if user.admin
+                    ) {
+                        info!("200 https://{server_name}/api/user/admin/status");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::OK)
+                                .header(CONTENT_TYPE, JSON)
+                                .body(Either::Left(Full::from(
+                                    serde_json::to_vec(&status()).unwrap(),
+                                )))
+                                .unwrap(),
+                        )
+                    } else {
+                        info!("403 https://{server_name}/api/user/admin/status");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::FORBIDDEN)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        )
+                    };
+                } else if path == "/jobs" {
+                    if request.method() != Method::GET {
+                        let mut response = Response::builder();
+                        let headers = response.headers_mut().unwrap();
+                        headers.insert(ALLOW, GET);
+                        info!("405 https://{server_name}/api/user/admin/jobs");
+                        return Some(
+                            response
+                                .status(StatusCode::METHOD_NOT_ALLOWED)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let snapshot = snapshot();
+                    return if matches!(
+                        SessionState::from_headers(request.headers(), &snapshot),
+                        SessionState::Valid { user, .. } if user.admin
+                    ) {
+                        info!("200 https://{server_name}/api/user/admin/jobs");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::OK)
+                                .header(CONTENT_TYPE, JSON)
+                                .body(Either::Left(Full::from(
+                                    serde_json::to_vec(&job_states()).unwrap(),
+                                )))
+                                .unwrap(),
+                        )
+                    } else {
+                        info!("403 https://{server_name}/api/user/admin/jobs");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::FORBIDDEN)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        )
+                    };
+                } else if let Some(path) = path.strip_prefix("/jobs/") {
+                    let (name, action) = path.split_once('/')?;
+                    let name = JobName::from_path_segment(name)?;
+                    if !matches!(action, "run" | "pause" | "resume") {
+                        return None;
+                    }
+                    if request.method() != Method::POST {
+                        let mut response = Response::builder();
+                        let headers = response.headers_mut().unwrap();
+                        headers.insert(ALLOW, HeaderValue::from_static("POST"));
+                        info!("405 https://{server_name}/api/user/admin/jobs/{path}");
+                        return Some(
+                            response
+                                .status(StatusCode::METHOD_NOT_ALLOWED)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let snapshot = snapshot();
+                    return if let Some(admin) =
+                        (match SessionState::from_headers(request.headers(), &snapshot) {
+                            SessionState::Valid { user, .. } if user.admin => Some(user),
+                            _ => None,
+                        }) {
+                        let actor = Actor::admin(&admin);
+                        let (status, state) = match action {
+                            "run" => (StatusCode::ACCEPTED, trigger_job(name)),
+                            "pause" => (StatusCode::OK, pause_job(&actor, name, true).await),
+                            _ => (StatusCode::OK, pause_job(&actor, name, false).await),
+                        };
+                        info!(
+                            "{} https://{server_name}/api/user/admin/jobs/{path}",
+                            status.as_u16()
+                        );
+                        Some(
+                            Response::builder()
+                                .status(status)
+                                .header(CONTENT_TYPE, JSON)
+                                .body(Either::Left(Full::from(
+                                    serde_json::to_vec(&state).unwrap(),
+                                )))
+                                .unwrap(),
+                        )
+                    } else {
+                        info!("403 https://{server_name}/api/user/admin/jobs/{path}");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::FORBIDDEN)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        )
+                    };
+                } else if path == "/insurance" {
+                    if request.method() != Method::GET {
+                        let mut response = Response::builder();
+                        let headers = response.headers_mut().unwrap();
+                        headers.insert(ALLOW, GET);
+                        info!("405 https://{server_name}/api/user/admin/insurance");
+                        return Some(
+                            response
+                                .status(StatusCode::METHOD_NOT_ALLOWED)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let snapshot = snapshot();
+                    return if matches!(
+                        SessionState::from_headers(request.headers(), &snapshot),
+                        SessionState::Valid { user, .. } if user.admin
+                    ) {
+                        let season = current_season(None);
+                        let members = snapshot
+                            .list::<User>("acc/")
+                            .filter_map(|(_, user)| {
+                                let metadata = user
+                                    .metadata
+                                    .and_then(|it| serde_json::from_value::<Metadata>(it).ok())
+                                    .filter(|it| it.latest_license_season == Some(season))?;
+                                Some(MemberInsurance {
+                                    user_id: user.id.to_string(),
+                                    first_name: user.first_name,
+                                    last_name: user.last_name,
+                                    license_number: metadata.license_number,
+                                    insurance: metadata.into(),
+                                })
+                            })
+                            .collect::<Vec<_>>();
+                        info!("200 https://{server_name}/api/user/admin/insurance");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::OK)
+                                .header(CONTENT_TYPE, JSON)
+                                .body(Either::Left(Full::from(
+                                    serde_json::to_vec(&members).unwrap(),
+                                )))
+                                .unwrap(),
+                        )
+                    } else {
+                        info!("403 https://{server_name}/api/user/admin/insurance");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::FORBIDDEN)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        )
+                    };
+                } else if path == "/renewals" {
+                    if request.method() != Method::GET {
+                        let mut response = Response::builder();
+                        let headers = response.headers_mut().unwrap();
+                        headers.insert(ALLOW, GET);
+                        info!("405 https://{server_name}/api/user/admin/renewals");
+                        return Some(
+                            response
+                                .status(StatusCode::METHOD_NOT_ALLOWED)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let snapshot = snapshot();
+                    return if matches!(
+                        SessionState::from_headers(request.headers(), &snapshot),
+                        SessionState::Valid { user, .. } if user.admin
+                    ) {
+                        info!("200 https://{server_name}/api/user/admin/renewals");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::OK)
+                                .header(CONTENT_TYPE, JSON)
+                                .body(Either::Left(Full::from(
+                                    serde_json::to_vec(&campaign_status(&snapshot)).unwrap(),
+                                )))
+                                .unwrap(),
+                        )
+                    } else {
+                        info!("403 https://{server_name}/api/user/admin/renewals");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::FORBIDDEN)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        )
+                    };
+                } else if path == "/medical-certificates" {
+                    if request.method() != Method::GET {
+                        let mut response = Response::builder();
+                        let headers = response.headers_mut().unwrap();
+                        headers.insert(ALLOW, GET);
+                        info!("405 https://{server_name}/api/user/admin/medical-certificates");
+                        return Some(
+                            response
+                                .status(StatusCode::METHOD_NOT_ALLOWED)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let snapshot = snapshot();
+                    return if matches!(
+                        SessionState::from_headers(request.headers(), &snapshot),
+                        SessionState::Valid { user, .. } if user.admin
+                    ) {
+                        let timestamp = SystemTime::now()
+                            .duration_since(SystemTime::UNIX_EPOCH)
+                            .unwrap()
+                            .as_secs() as u32;
+                        let members = restricted_members(&snapshot, today(timestamp));
+                        info!("200 https://{server_name}/api/user/admin/medical-certificates");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::OK)
+                                .header(CONTENT_TYPE, JSON)
+                                .body(Either::Left(Full::from(
+                                    serde_json::to_vec(&members).unwrap(),
+                                )))
+                                .unwrap(),
+                        )
+                    } else {
+                        info!("403 https://{server_name}/api/user/admin/medical-certificates");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::FORBIDDEN)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        )
+                    };
+                } else if path == "/duplicates" {
+                    if request.method() != Method::GET {
+                        let mut response = Response::builder();
+                        let headers = response.headers_mut().unwrap();
+                        headers.insert(ALLOW, GET);
+                        info!("405 https://{server_name}/api/user/admin/duplicates");
+                        return Some(
+                            response
+                                .status(StatusCode::METHOD_NOT_ALLOWED)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let snapshot = snapshot();
+                    return if matches!(
+                        SessionState::from_headers(request.headers(), &snapshot),
+                        SessionState::Valid { user, .. } if user.admin
+                    ) {
+                        let users = snapshot
+                            .list::<User>("acc/")
+                            .map(|(_, it)| it)
+                            .collect::<Vec<_>>();
+                        info!("200 https://{server_name}/api/user/admin/duplicates");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::OK)
+                                .header(CONTENT_TYPE, JSON)
+                                .body(Either::Left(Full::from(
+                                    serde_json::to_vec(&find_duplicates(&users)).unwrap(),
+                                )))
+                                .unwrap(),
+                        )
+                    } else {
+                        info!("403 https://{server_name}/api/user/admin/duplicates");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::FORBIDDEN)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        )
+                    };
+                } else if path == "/duplicates/merge" {
+                    if request.method() != Method::POST {
+                        let mut response = Response::builder();
+                        let headers = response.headers_mut().unwrap();
+                        headers.insert(ALLOW, HeaderValue::from_static("POST"));
+                        info!("405 https://{server_name}/api/user/admin/duplicates/merge");
+                        return Some(
+                            response
+                                .status(StatusCode::METHOD_NOT_ALLOWED)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let snapshot = snapshot();
+                    let Some(admin) =
+                        (match SessionState::from_headers(request.headers(), &snapshot) {
+                            SessionState::Valid { user, .. } if user.admin => Some(user),
+                            _ => None,
+                        })
+                    else {
+                        info!("403 https://{server_name}/api/user/admin/duplicates/merge");
+                        return Some(
+                            Response::builder()
+                                .status(StatusCode::FORBIDDEN)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    };
+                    let merge_request = Limited::new(request.into_body(), 64 * 1024)
+                        .collect()
+                        .await
+                        .ok()
+                        .and_then(|it| serde_json::from_slice::<MergeRequest>(&it.to_bytes()).ok());
+                    let Some(user) = (match merge_request {
+                        Some(merge_request) => merge_users(&snapshot, merge_request, &admin).await,
+                        None => None,
+                    }) else {
+                        info!("400 https://{server_name}/api/user/admin/duplicates/merge");
+                        return Some(
+                            Response::builder()
+                                .status(StatusCode::BAD_REQUEST)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    };
+                    info!("200 https://{server_name}/api/user/admin/duplicates/merge");
+                    return Some(
+                        Response::builder()
+                            .status(StatusCode::OK)
+                            .header(CONTENT_TYPE, JSON)
+                            .body(Either::Left(Full::from(serde_json::to_vec(&user).unwrap())))
+                            .unwrap(),
+                    );
+                } else if path == "/myffme/search" {
+                    if request.method() != Method::GET {
+                        let mut response = Response::builder();
+                        let headers = response.headers_mut().unwrap();
+                        headers.insert(ALLOW, GET);
+                        info!("405 https://{server_name}/api/user/admin/myffme/search");
+                        return Some(
+                            response
+                                .status(StatusCode::METHOD_NOT_ALLOWED)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let snapshot = snapshot();
+                    if !matches!(
+                        SessionState::from_headers(request.headers(), &snapshot),
+                        SessionState::Valid { user, .. } if user.admin
+                    ) {
+                        info!("403 https://{server_name}/api/user/admin/myffme/search");
+                        return Some(
+                            Response::builder()
+                                .status(StatusCode::FORBIDDEN)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let Some(search) = LicenseeSearch::from_query(request.uri().query()) else {
+                        info!("400 https://{server_name}/api/user/admin/myffme/search");
+                        return Some(
+                            Response::builder()
+                                .status(StatusCode::BAD_REQUEST)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    };
+                    return if let Some(matches) = search_licensees(&snapshot, &search).await {
+                        info!("200 https://{server_name}/api/user/admin/myffme/search");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::OK)
+                                .header(CONTENT_TYPE, JSON)
+                                .body(Either::Left(Full::from(
+                                    serde_json::to_vec(&matches).unwrap(),
+                                )))
+                                .unwrap(),
+                        )
+                    } else {
+                        info!("502 https://{server_name}/api/user/admin/myffme/search");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::BAD_GATEWAY)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        )
+                    };
+                } else if path == "/myffme/link" {
+                    if request.method() != Method::POST {
+                        let mut response = Response::builder();
+                        let headers = response.headers_mut().unwrap();
+                        headers.insert(ALLOW, HeaderValue::from_static("POST"));
+                        info!("405 https://{server_name}/api/user/admin/myffme/link");
+                        return Some(
+                            response
+                                .status(StatusCode::METHOD_NOT_ALLOWED)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let snapshot = snapshot();
+                    let Some(admin) =
+                        (match SessionState::from_headers(request.headers(), &snapshot) {
+                            SessionState::Valid { user, .. } if user.admin => Some(user),
+                            _ => None,
+                        })
+                    else {
+                        info!("403 https://{server_name}/api/user/admin/myffme/link");
+                        return Some(
+                            Response::builder()
+                                .status(StatusCode::FORBIDDEN)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    };
+                    let Some(link_request) = Limited::new(request.into_body(), 64 * 1024)
+                        .collect()
+                        .await
+                        .ok()
+                        .and_then(|it| serde_json::from_slice::<LinkRequest>(&it.to_bytes()).ok())
+                    else {
+                        info!("400 https://{server_name}/api/user/admin/myffme/link");
+                        return Some(
+                            Response::builder()
+                                .status(StatusCode::BAD_REQUEST)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    };
+                    let status_code = match link_user(&snapshot, link_request, &admin).await {
+                        Ok(user) => {
+                            info!("200 https://{server_name}/api/user/admin/myffme/link");
+                            return Some(
+                                Response::builder()
+                                    .status(StatusCode::OK)
+                                    .header(CONTENT_TYPE, JSON)
+                                    .body(Either::Left(Full::from(
+                                        serde_json::to_vec(&user).unwrap(),
+                                    )))
+                                    .unwrap(),
+                            );
+                        }
+                        Err(LinkError::UnknownUser) | Err(LinkError::UnknownLicensee) => {
+                            StatusCode::NOT_FOUND
+                        }
+                        Err(LinkError::AlreadyLinked) | Err(LinkError::Archived) => {
+                            StatusCode::CONFLICT
+                        }
+                        Err(LinkError::Storage) => StatusCode::INTERNAL_SERVER_ERROR,
+                    };
+                    info!(
+                        "{} https://{server_name}/api/user/admin/myffme/link",
+                        status_code.as_u16()
+                    );
+                    return Some(
+                        Response::builder()
+                            .status(status_code)
+                            .body(Either::Right(Empty::new()))
+                            .unwrap(),
+                    );
+                } else if path == "/lifecycle" {
+                    if request.method() != Method::GET {
+                        let mut response = Response::builder();
+                        let headers = response.headers_mut().unwrap();
+                        headers.insert(ALLOW, GET);
+                        info!("405 https://{server_name}/api/user/admin/lifecycle");
+                        return Some(
+                            response
+                                .status(StatusCode::METHOD_NOT_ALLOWED)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let snapshot = snapshot();
+                    return if matches!(
+                        SessionState::from_headers(request.headers(), &snapshot),
+                        SessionState::Valid { user, .. } if user.admin
+                    ) {
+                        info!("200 https://{server_name}/api/user/admin/lifecycle");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::OK)
+                                .header(CONTENT_TYPE, JSON)
+                                .body(Either::Left(Full::from(
+                                    serde_json::to_vec(&inactive_members(&snapshot)).unwrap(),
+                                )))
+                                .unwrap(),
+                        )
+                    } else {
+                        info!("403 https://{server_name}/api/user/admin/lifecycle");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::FORBIDDEN)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        )
+                    };
+                } else if path == "/erasures" {
+                    if request.method() != Method::GET {
+                        let mut response = Response::builder();
+                        let headers = response.headers_mut().unwrap();
+                        headers.insert(ALLOW, GET);
+                        info!("405 https://{server_name}/api/user/admin/erasures");
+                        return Some(
+                            response
+                                .status(StatusCode::METHOD_NOT_ALLOWED)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let snapshot = snapshot();
+                    return if matches!(
+                        SessionState::from_headers(request.headers(), &snapshot),
+                        SessionState::Valid { user, .. } if user.admin
+                    ) {
+                        info!("200 https://{server_name}/api/user/admin/erasures");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::OK)
+                                .header(CONTENT_TYPE, JSON)
+                                .body(Either::Left(Full::from(
+                                    serde_json::to_vec(&pending_erasures(&snapshot)).unwrap(),
+                                )))
+                                .unwrap(),
+                        )
+                    } else {
+                        info!("403 https://{server_name}/api/user/admin/erasures");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::FORBIDDEN)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        )
+                    };
+                } else if path == "/erasures/approve" {
+                    if request.method() != Method::POST {
+                        let mut response = Response::builder();
+                        let headers = response.headers_mut().unwrap();
+                        headers.insert(ALLOW, HeaderValue::from_static("POST"));
+                        info!("405 https://{server_name}/api/user/admin/erasures/approve");
+                        return Some(
+                            response
+                                .status(StatusCode::METHOD_NOT_ALLOWED)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let snapshot = snapshot();
+                    let Some(admin) =
+                        (match SessionState::from_headers(request.headers(), &snapshot) {
+                            SessionState::Valid { user, .. } if user.admin => Some(user),
+                            _ => None,
+                        })
+                    else {
+                        info!("403 https://{server_name}/api/user/admin/erasures/approve");
+                        return Some(
+                            Response::builder()
+                                .status(StatusCode::FORBIDDEN)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    };
+                    let Some(approval) = Limited::new(request.into_body(), 64 * 1024)
+                        .collect()
+                        .await
+                        .ok()
+                        .and_then(|it| {
+                            serde_json::from_slice::<ErasureApproval>(&it.to_bytes()).ok()
+                        })
+                    else {
+                        info!("400 https://{server_name}/api/user/admin/erasures/approve");
+                        return Some(
+                            Response::builder()
+                                .status(StatusCode::BAD_REQUEST)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    };
+                    let timestamp = SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap()
+                        .as_secs() as u32;
+                    let status_code =
+                        match approve_erasure(&snapshot, approval, &admin, timestamp).await {
+                            Ok(erasure) => {
+                                info!("200 https://{server_name}/api/user/admin/erasures/approve");
+                                return Some(
+                                    Response::builder()
+                                        .status(StatusCode::OK)
+                                        .header(CONTENT_TYPE, JSON)
+                                        .body(Either::Left(Full::from(
+                                            serde_json::to_vec(&erasure).unwrap(),
+                                        )))
+                                        .unwrap(),
+                                );
+                            }
+                            Err(ErasureError::UnknownRequest) => StatusCode::NOT_FOUND,
+                            Err(ErasureError::ActiveMember) => StatusCode::CONFLICT,
+                            Err(ErasureError::Storage) => StatusCode::INTERNAL_SERVER_ERROR,
+                        };
+                    info!(
+                        "{} https://{server_name}/api/user/admin/erasures/approve",
+                        status_code.as_u16()
+                    );
+                    return Some(
+                        Response::builder()
+                            .status(status_code)
+                            .body(Either::Right(Empty::new()))
+                            .unwrap(),
+                    );
+                } else if path == "/audit" {
+                    if request.method() != Method::GET {
+                        let mut response = Response::builder();
+                        let headers = response.headers_mut().unwrap();
+                        headers.insert(ALLOW, GET);
+                        info!("405 https://{server_name}/api/user/admin/audit");
+                        return Some(
+                            response
+                                .status(StatusCode::METHOD_NOT_ALLOWED)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let snapshot = snapshot();
+                    if !matches!(
+                        SessionState::from_headers(request.headers(), &snapshot),
+                        SessionState::Valid { user, .. } if user.admin
+                    ) {
+                        info!("403 https://{server_name}/api/user/admin/audit");
+                        return Some(
+                            Response::builder()
+                                .status(StatusCode::FORBIDDEN)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let Some(filter) = AuditFilter::from_query(request.uri().query()) else {
+                        info!("400 https://{server_name}/api/user/admin/audit");
+                        return Some(
+                            Response::builder()
+                                .status(StatusCode::BAD_REQUEST)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    };
+                    info!("200 https://{server_name}/api/user/admin/audit");
+                    return Some(
+                        Response::builder()
+                            .status(StatusCode::OK)
+                            .header(CONTENT_TYPE, JSON)
+                            .body(Either::Left(Full::from(
+                                serde_json::to_vec(&audit_log(&snapshot, &filter)).unwrap(),
+                            )))
+                            .unwrap(),
+                    );
+                } else if path == "/conflicts" {
+                    if request.method() != Method::GET {
+                        let mut response = Response::builder();
+                        let headers = response.headers_mut().unwrap();
+                        headers.insert(ALLOW, GET);
+                        info!("405 https://{server_name}/api/user/admin/conflicts");
+                        return Some(
+                            response
+                                .status(StatusCode::METHOD_NOT_ALLOWED)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let snapshot = snapshot();
+                    return if matches!(
+                        SessionState::from_headers(request.headers(), &snapshot),
+                        SessionState::Valid { user, .. } if user.admin
+                    ) {
+                        info!("200 https://{server_name}/api/user/admin/conflicts");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::OK)
+                                .header(CONTENT_TYPE, JSON)
+                                .body(Either::Left(Full::from(
+                                    serde_json::to_vec(&pending_conflicts(&snapshot)).unwrap(),
+                                )))
+                                .unwrap(),
+                        )
+                    } else {
+                        info!("403 https://{server_name}/api/user/admin/conflicts");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::FORBIDDEN)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        )
+                    };
+                } else if path == "/conflicts/resolve" {
+                    if request.method() != Method::POST {
+                        let mut response = Response::builder();
+                        let headers = response.headers_mut().unwrap();
+                        headers.insert(ALLOW, HeaderValue::from_static("POST"));
+                        info!("405 https://{server_name}/api/user/admin/conflicts/resolve");
+                        return Some(
+                            response
+                                .status(StatusCode::METHOD_NOT_ALLOWED)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let snapshot = snapshot();
+                    let Some(admin) =
+                        (match SessionState::from_headers(request.headers(), &snapshot) {
+                            SessionState::Valid { user, .. } if user.admin => Some(user),
+                            _ => None,
+                        })
+                    else {
+                        info!("403 https://{server_name}/api/user/admin/conflicts/resolve");
+                        return Some(
+                            Response::builder()
+                                .status(StatusCode::FORBIDDEN)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    };
+                    let Some(resolution) = Limited::new(request.into_body(), 64 * 1024)
+                        .collect()
+                        .await
+                        .ok()
+                        .and_then(|it| {
+                            serde_json::from_slice::<ConflictResolution>(&it.to_bytes()).ok()
+                        })
+                    else {
+                        info!("400 https://{server_name}/api/user/admin/conflicts/resolve");
+                        return Some(
+                            Response::builder()
+                                .status(StatusCode::BAD_REQUEST)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    };
+                    let timestamp = SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap()
+                        .as_secs() as u32;
+                    let status_code =
+                        match resolve_conflict(&snapshot, resolution, &admin, timestamp).await {
+                            Ok(conflict) => {
+                                info!("200 https://{server_name}/api/user/admin/conflicts/resolve");
+                                return Some(
+                                    Response::builder()
+                                        .status(StatusCode::OK)
+                                        .header(CONTENT_TYPE, JSON)
+                                        .body(Either::Left(Full::from(
+                                            serde_json::to_vec(&conflict).unwrap(),
+                                        )))
+                                        .unwrap(),
+                                );
+                            }
+                            Err(ConflictError::UnknownConflict) => StatusCode::NOT_FOUND,
+                            Err(ConflictError::MissingValue) => StatusCode::CONFLICT,
+                            Err(ConflictError::MyFFME) => StatusCode::BAD_GATEWAY,
+                            Err(ConflictError::Storage) => StatusCode::INTERNAL_SERVER_ERROR,
+                        };
+                    info!(
+                        "{} https://{server_name}/api/user/admin/conflicts/resolve",
+                        status_code.as_u16()
+                    );
+                    return Some(
+                        Response::builder()
+                            .status(status_code)
+                            .body(Either::Right(Empty::new()))
+                            .unwrap(),
+                    );
+                } else if path == "/licenses/retention" {
+                    if request.method() != Method::GET {
+                        let mut response = Response::builder();
+                        let headers = response.headers_mut().unwrap();
+                        headers.insert(ALLOW, GET);
+                        info!("405 https://{server_name}/api/user/admin/licenses/retention");
+                        return Some(
+                            response
+                                .status(StatusCode::METHOD_NOT_ALLOWED)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let snapshot = snapshot();
+                    return if matches!(
+                        SessionState::from_headers(request.headers(), &snapshot),
+                        SessionState::Valid { user, .. } if user.admin
+                    ) {
+                        let retention = retention(
+                            snapshot.list::<User>("acc/").map(|(_, it)| it),
+                            *STRUCTURE_ID,
+                        );
+                        info!("200 https://{server_name}/api/user/admin/licenses/retention");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::OK)
+                                .header(CONTENT_TYPE, JSON)
+                                .body(Either::Left(Full::from(
+                                    serde_json::to_vec(&retention).unwrap(),
+                                )))
+                                .unwrap(),
+                        )
+                    } else {
+                        info!("403 https://{server_name}/api/user/admin/licenses/retention");
+                        Some(
+                            Response::builder()
+                                .status(StatusCode::FORBIDDEN)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        )
+                    };
+                } else if path == "/competitions/registrations" {
+                    if request.method() != Method::GET && request.method() != Method::POST {
+                        let mut response = Response::builder();
+                        let headers = response.headers_mut().unwrap();
+                        headers.insert(ALLOW, GET_POST);
+                        info!(
+                            "405 https://{server_name}/api/user/admin/competitions/registrations"
+                        );
+                        return Some(
+                            response
+                                .status(StatusCode::METHOD_NOT_ALLOWED)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let snapshot = snapshot();
+                    let Some(admin) =
+                        (match SessionState::from_headers(request.headers(), &snapshot) {
+                            SessionState::Valid { user, .. } if user.admin => Some(user),
+                            _ => None,
+                        })
+                    else {
+                        info!(
+                            "403 https://{server_name}/api/user/admin/competitions/registrations"
+                        );
+                        return Some(
+                            Response::builder()
+                                .status(StatusCode::FORBIDDEN)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    };
+                    let timestamp = SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap()
+                        .as_secs() as u32;
+                    let body = if request.method() == Method::GET {
+                        serde_json::to_vec(&registrations(&snapshot, today(timestamp))).unwrap()
+                    } else {
+                        let update = Limited::new(request.into_body(), 64 * 1024)
+                            .collect()
+                            .await
+                            .ok()
+                            .and_then(|it| {
+                                serde_json::from_slice::<RegistrationsUpdate>(&it.to_bytes()).ok()
+                            });
+                        let result = match update {
+                            Some(update) => {
+                                update_registrations(&snapshot, update, &admin, today(timestamp))
+                                    .await
+                            }
+                            None => Err(RegistrationError::InvalidRequest),
+                        };
+                        match result {
+                            Ok(status) => serde_json::to_vec(&status).unwrap(),
+                            Err(err) => {
+                                let status_code = match err {
+                                    RegistrationError::InvalidRequest => StatusCode::BAD_REQUEST,
+                                    RegistrationError::Storage => StatusCode::INTERNAL_SERVER_ERROR,
+                                };
+                                info!(
+                                    "{} https://{server_name}/api/user/admin/competitions/registrations",
+                                    status_code.as_u16()
+                                );
+                                return Some(
+                                    Response::builder()
+                                        .status(status_code)
+                                        .body(Either::Right(Empty::new()))
+                                        .unwrap(),
+                                );
+                            }
+                        }
+                    };
+                    info!("200 https://{server_name}/api/user/admin/competitions/registrations");
+                    return Some(
+                        Response::builder()
+                            .status(StatusCode::OK)
+                            .header(CONTENT_TYPE, JSON)
+                            .body(Either::Left(Full::from(body)))
+                            .unwrap(),
+                    );
+                } else if path == "/add-missing-users" {
+                    if request.method() != Method::GET {
+                        let mut response = Response::builder();
+                        let headers = response.headers_mut().unwrap();
+                        headers.insert(ALLOW, GET);
+                        info!("405 https://{server_name}/api/user/admin/add-missing-users");
+                        return Some(
+                            response
+                                .status(StatusCode::METHOD_NOT_ALLOWED)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let result = add_missing_users(&snapshot(), true).await;
+                    record_sync(SyncKind::AddMissingUsers, &result);
+                    match result {
+                        Ok(Some(output)) => {
+                            info!("200 https://{server_name}/api/user/admin/add-missing-users");
+                            let mut response = Response::builder();
+                            let headers = response.headers_mut().unwrap();
+                            headers.insert(CONTENT_TYPE, TEXT);
+                            return Some(
+                                response
+                                    .status(StatusCode::OK)
+                                    .body(Either::Left(Full::from(output)))
+                                    .unwrap(),
+                            );
+                        }
+                        Err(err) => {
+                            info!("500 https://{server_name}/api/user/admin/add-missing-users");
+                            return Some(
+                                Response::builder()
+                                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                                    .body(Either::Left(Full::from(err)))
+                                    .unwrap(),
+                            );
+                        }
+                        _ => unreachable!(),
+                    }
+                } else if path == "/update-users-metadata" {
+                    if request.method() != Method::GET {
+                        let mut response = Response::builder();
+                        let headers = response.headers_mut().unwrap();
+                        headers.insert(ALLOW, GET);
+                        info!("405 https://{server_name}/api/user/admin/update-users-metadata");
+                        return Some(
+                            response
+                                .status(StatusCode::METHOD_NOT_ALLOWED)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                    let result = update_users_metadata(&snapshot(), true).await;
+                    record_sync(SyncKind::UpdateUsersMetadata, &result);
+                    match result {
+                        Ok(Some(output)) => {
+                            info!("200 https://{server_name}/api/user/admin/update-users-metadata");
+                            let mut response = Response::builder();
+                            let headers = response.headers_mut().unwrap();
+                            headers.insert(CONTENT_TYPE, TEXT);
+                            return Some(
+                                response
+                                    .status(StatusCode::OK)
+                                    .body(Either::Left(Full::from(output)))
+                                    .unwrap(),
+                            );
+                        }
+                        Err(err) => {
+                            info!("500 https://{server_name}/api/user/admin/update-users-metadata");
+                            return Some(
+                                Response::builder()
+                                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                                    .body(Either::Left(Full::from(err)))
+                                    .unwrap(),
+                            );
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+            } else if path == "/results" {
+                if request.method() != Method::GET {
+                    let mut response = Response::builder();
+                    let headers = response.headers_mut().unwrap();
+                    headers.insert(ALLOW, GET);
+                    info!("405 https://{server_name}/api/user/results");
+                    return Some(
+                        response
+                            .status(StatusCode::METHOD_NOT_ALLOWED)
+                            .body(Either::Right(Empty::new()))
+                            .unwrap(),
+                    );
+                }
+                let snapshot = snapshot();
+                if let Some((_, user)) = acting_member(&request, &snapshot) {
+                    let mut results = user
+                        .metadata
+                        .and_then(|it| serde_json::from_value::<Metadata>(it).ok())
+                        .and_then(|it| it.competition_results)
+                        .unwrap_or_default();
+                    results.sort_by_key(|it| {
+                        (
+                            it.competition.season,
+                            it.competition.date.unwrap_or_default(),
+                        )
+                    });
+                    let history = results
+                        .into_iter()
+                        .map(|result| RankedResult {
+                            relative_rank: result.relative_rank(),
+                            result,
+                        })
+                        .collect::<Vec<_>>();
+                    info!("200 https://{server_name}/api/user/results");
+                    return Some(
+                        Response::builder()
+                            .status(StatusCode::OK)
+                            .header(CONTENT_TYPE, JSON)
+                            .body(Either::Left(Full::from(
+                                serde_json::to_vec(&history).unwrap(),
+                            )))
+                            .unwrap(),
+                    );
+                } else {
+                    info!("403 https://{server_name}/api/user/results");
+                    return Some(
+                        Response::builder()
+                            .status(StatusCode::FORBIDDEN)
+                            .body(Either::Right(Empty::new()))
+                            .unwrap(),
+                    );
+                }
+            } else if path == "/children" {
+                if request.method() != Method::GET {
+                    let mut response = Response::builder();
+                    let headers = response.headers_mut().unwrap();
+                    headers.insert(ALLOW, GET);
+                    info!("405 https://{server_name}/api/user/children");
+                    return Some(
+                        response
+                            .status(StatusCode::METHOD_NOT_ALLOWED)
+                            .body(Either::Right(Empty::new()))
+                            .unwrap(),
+                    );
+                }
+                let snapshot = snapshot();
+                if let SessionState::Valid { user, .. } =
+                    SessionState::from_headers(request.headers(), &snapshot)
+                {
+                    let children = children(&snapshot, &user);
+                    info!("200 https://{server_name}/api/user/children");
+                    return Some(
+                        Response::builder()
+                            .status(StatusCode::OK)
+                            .header(CONTENT_TYPE, JSON)
+                            .body(Either::Left(Full::from(
+                                serde_json::to_vec(&children).unwrap(),
+                            )))
+                            .unwrap(),
+                    );
+                } else {
+                    info!("403 https://{server_name}/api/user/children");
+                    return Some(
+                        Response::builder()
+                            .status(StatusCode::FORBIDDEN)
+                            .body(Either::Right(Empty::new()))
+                            .unwrap(),
+                    );
+                }
+            } else if path == "/licenses" {
+                if request.method() != Method::GET {
+                    let mut response = Response::builder();
+                    let headers = response.headers_mut().unwrap();
+                    headers.insert(ALLOW, GET);
+                    info!("405 https://{server_name}/api/user/licenses");
+                    return Some(
+                        response
+                            .status(StatusCode::METHOD_NOT_ALLOWED)
+                            .body(Either::Right(Empty::new()))
+                            .unwrap(),
+                    );
+                }
+                let snapshot = snapshot();
+                if let Some((_, user)) = acting_member(&request, &snapshot) {
+                    let history = user
+                        .metadata
+                        .and_then(|it| serde_json::from_value::<Metadata>(it).ok())
+                        .and_then(|it| it.license_history)
+                        .unwrap_or_default();
+                    info!("200 https://{server_name}/api/user/licenses");
+                    return Some(
+                        Response::builder()
+                            .status(StatusCode::OK)
+                            .header(CONTENT_TYPE, JSON)
+                            .body(Either::Left(Full::from(
+                                serde_json::to_vec(&history).unwrap(),
+                            )))
+                            .unwrap(),
+                    );
+                } else {
+                    info!("403 https://{server_name}/api/user/licenses");
+                    return Some(
+                        Response::builder()
+                            .status(StatusCode::FORBIDDEN)
+                            .body(Either::Right(Empty::new()))
+                            .unwrap(),
+                    );
+                }
+            } else if path == "/insurance" {
+                if request.method() != Method::GET {
+                    let mut response = Response::builder();
+                    let headers = response.headers_mut().unwrap();
+                    headers.insert(ALLOW, GET);
+                    info!("405 https://{server_name}/api/user/insurance");
+                    return Some(
+                        response
+                            .status(StatusCode::METHOD_NOT_ALLOWED)
+                            .body(Either::Right(Empty::new()))
+                            .unwrap(),
+                    );
+                }
+                let snapshot = snapshot();
+                // restricted members still see their own insurance, e.g. after an accident
+                if let Some((_, user)) = acting_member(&request, &snapshot) {
+                    let metadata = user
+                        .metadata
+                        .and_then(|it| serde_json::from_value::<Metadata>(it).ok())
+                        .unwrap_or_default();
+                    let insurance = Insurance::from(metadata);
+                    info!("200 https://{server_name}/api/user/insurance");
+                    return Some(
+                        Response::builder()
+                            .status(StatusCode::OK)
+                            .header(CONTENT_TYPE, JSON)
+                            .body(Either::Left(Full::from(
+                                serde_json::to_vec(&insurance).unwrap(),
+                            )))
+                            .unwrap(),
+                    );
+                } else {
+                    info!("403 https://{server_name}/api/user/insurance");
+                    return Some(
+                        Response::builder()
+                            .status(StatusCode::FORBIDDEN)
+                            .body(Either::Right(Empty::new()))
+                            .unwrap(),
+                    );
+                }
+            } else if path == "/medical-certificate" {
+                if request.method() != Method::POST {
+                    let mut response = Response::builder();
+                    let headers = response.headers_mut().unwrap();
+                    headers.insert(ALLOW, HeaderValue::from_static("POST"));
+                    info!("405 https://{server_name}/api/user/medical-certificate");
+                    return Some(
+                        response
+                            .status(StatusCode::METHOD_NOT_ALLOWED)
+                            .body(Either::Right(Empty::new()))
+                            .unwrap(),
+                    );
+                }
+                let snapshot = snapshot();
+                // the guardian is the one acting for their child
+                let Some((actor, user)) = acting_member(&request, &snapshot) else {
+                    info!("403 https://{server_name}/api/user/medical-certificate");
+                    return Some(
+                        Response::builder()
+                            .status(StatusCode::FORBIDDEN)
+                            .body(Either::Right(Empty::new()))
+                            .unwrap(),
+                    );
+                };
+                if user
+                    .metadata
+                    .as_ref()
+                    .and_then(|it| serde_json::from_value::<Metadata>(it.clone()).ok())
+                    .is_some_and(|it| Lifecycle::is_restricted(&it))
+                {
+                    info!("403 https://{server_name}/api/user/medical-certificate");
+                    return Some(
+                        Response::builder()
+                            .status(StatusCode::FORBIDDEN)
+                            .body(Either::Right(Empty::new()))
+                            .unwrap(),
+                    );
+                }
+                let Some(status) = certificate_status_from_query(request.uri().query()) else {
+                    info!("400 https://{server_name}/api/user/medical-certificate");
+                    return Some(
+                        Response::builder()
+                            .status(StatusCode::BAD_REQUEST)
+                            .body(Either::Right(Empty::new()))
+                            .unwrap(),
+                    );
+                };
+                let content_type = request
+                    .headers()
+                    .get(CONTENT_TYPE)
+                    .and_then(|it| it.to_str().ok())
+                    .map(|it| it.to_string());
+                let body = match Limited::new(request.into_body(), MAX_DOCUMENT_SIZE)
+                    .collect()
+                    .await
+                {
+                    Ok(it) => it.to_bytes(),
+                    Err(err) => {
+                        let status_code = if err.downcast_ref::<LengthLimitError>().is_some() {
+                            StatusCode::PAYLOAD_TOO_LARGE
+                        } else {
+                            StatusCode::BAD_REQUEST
+                        };
+                        info!(
+                            "{} https://{server_name}/api/user/medical-certificate",
+                            status_code.as_u16()
+                        );
+                        return Some(
+                            Response::builder()
+                                .status(status_code)
+                                .body(Either::Right(Empty::new()))
+                                .unwrap(),
+                        );
+                    }
+                };
+                let Some(format) = DocumentFormat::detect(content_type.as_deref(), &body) else {
+                    info!("415 https://{server_name}/api/user/medical-certificate");
+                    return Some(
+                        Response::builder()
+                            .status(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+                            .body(Either::Right(Empty::new()))
+                            .unwrap(),
+                    );
+                };
+                let status_code = match upload_medical_certificate(
+                    &snapshot, &actor, user, status, format, &body,
+                )
+                .await
+                {
+                    Ok(document) => {
+                        info!("200 https://{server_name}/api/user/medical-certificate");
+                        return Some(
                             Response::builder()
                                 .status(StatusCode::OK)
                                 .header(CONTENT_TYPE, JSON)
                                 .body(Either::Left(Full::from(
-                                    serde_json::to_vec(&AdminPrices {
-                                        base_license_price_in_cents,
-                                        license_types,
-                                        insurance_levels,
-                                        insurance_options,
-                                        equipment_rental_price_in_cents,
-                                    })
-                                    .unwrap(),
+                                    serde_json::to_vec(&document).unwrap(),
                                 )))
                                 .unwrap(),
-                        )
-                    } else {
-                        info!("403 https://{server_name}/api/user/admin/prices");
-                        Some(
-                            Response::builder()
-                                .status(StatusCode::FORBIDDEN)
-                                .body(Either::Right(Empty::new()))
-                                .unwrap(),
-                        )
-                    };
-                } else if path == "/add-missing-users" {
-                    if request.method() != Method::GET {
-                        let mut response = Response::builder();
-                        let headers = response.headers_mut().unwrap();
-                        headers.insert(ALLOW, GET);
-                        info!("405 https://{server_name}/api/user/admin/add-missing-users");
-                        return Some(
-                            response
-                                .status(StatusCode::METHOD_NOT_ALLOWED)
-                                .body(Either::Right(Empty::new()))
-                                .unwrap(),
-                        );
-                    }
-                    match add_missing_users(&snapshot(), true).await {
-                        Ok(Some(output)) => {
-                            info!("200 https://{server_name}/api/user/admin/add-missing-users");
-                            let mut response = Response::builder();
-                            let headers = response.headers_mut().unwrap();
-                            headers.insert(CONTENT_TYPE, TEXT);
-                            return Some(
-                                response
-                                    .status(StatusCode::OK)
-                                    .body(Either::Left(Full::from(output)))
-                                    .unwrap(),
-                            );
-                        }
-                        Err(err) => {
-                            info!("500 https://{server_name}/api/user/admin/add-missing-users");
-                            return Some(
-                                Response::builder()
-                                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                                    .body(Either::Left(Full::from(err)))
-                                    .unwrap(),
-                            );
-                        }
-                        _ => unreachable!(),
-                    }
-                } else if path == "/update-users-metadata" {
-                    if request.method() != Method::GET {
-                        let mut response = Response::builder();
-                        let headers = response.headers_mut().unwrap();
-                        headers.insert(ALLOW, GET);
-                        info!("405 https://{server_name}/api/user/admin/update-users-metadata");
-                        return Some(
-                            response
-                                .status(StatusCode::METHOD_NOT_ALLOWED)
-                                .body(Either::Right(Empty::new()))
-                                .unwrap(),
                         );
                     }
-                    match update_users_metadata(&snapshot(), true).await {
-                        Ok(Some(output)) => {
-                            info!("200 https://{server_name}/api/user/admin/update-users-metadata");
-                            let mut response = Response::builder();
-                            let headers = response.headers_mut().unwrap();
-                            headers.insert(CONTENT_TYPE, TEXT);
-                            return Some(
-                                response
-                                    .status(StatusCode::OK)
-                                    .body(Either::Left(Full::from(output)))
-                                    .unwrap(),
-                            );
-                        }
-                        Err(err) => {
-                            info!("500 https://{server_name}/api/user/admin/update-users-metadata");
-                            return Some(
-                                Response::builder()
-                                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                                    .body(Either::Left(Full::from(err)))
-                                    .unwrap(),
-                            );
-                        }
-                        _ => unreachable!(),
-                    }
+                    Err(UploadError::NotLicensed) => StatusCode::CONFLICT,
+                    Err(UploadError::Storage) => StatusCode::INTERNAL_SERVER_ERROR,
+                    // the document is kept and forwarded again by the daily job
+                    Err(UploadError::Upstream) => StatusCode::ACCEPTED,
+                };
+                info!(
+                    "{} https://{server_name}/api/user/medical-certificate",
+                    status_code.as_u16()
+                );
+                return Some(
+                    Response::builder()
+                        .status(status_code)
+                        .body(Either::Right(Empty::new()))
+                        .unwrap(),
+                );
+            } else if path == "/data" {
+                if request.method() != Method::GET {
+                    let mut response = Response::builder();
+                    let headers = response.headers_mut().unwrap();
+                    headers.insert(ALLOW, GET);
+                    info!("405 https://{server_name}/api/user/data");
+                    return Some(
+                        response
+                            .status(StatusCode::METHOD_NOT_ALLOWED)
+                            .body(Either::Right(Empty::new()))
+                            .unwrap(),
+                    );
+                }
+                let snapshot = snapshot();
+                if let Some((_, user)) = acting_member(&request, &snapshot) {
+                    let export = export_data(&snapshot, user);
+                    info!("200 https://{server_name}/api/user/data");
+                    return Some(
+                        Response::builder()
+                            .status(StatusCode::OK)
+                            .header(CONTENT_TYPE, JSON)
+                            .header(
+                                CONTENT_DISPOSITION,
+                                HeaderValue::from_static("attachment; filename=\"data.json\""),
+                            )
+                            .body(Either::Left(Full::from(
+                                serde_json::to_vec(&export).unwrap(),
+                            )))
+                            .unwrap(),
+                    );
+                } else {
+                    info!("403 https://{server_name}/api/user/data");
+                    return Some(
+                        Response::builder()
+                            .status(StatusCode::FORBIDDEN)
+                            .body(Either::Right(Empty::new()))
+                            .unwrap(),
+                    );
+                }
+            } else if path == "/erasure" {
+                if request.method() != Method::POST {
+                    let mut response = Response::builder();
+                    let headers = response.headers_mut().unwrap();
+                    headers.insert(ALLOW, HeaderValue::from_static("POST"));
+                    info!("405 https://{server_name}/api/user/erasure");
+                    return Some(
+                        response
+                            .status(StatusCode::METHOD_NOT_ALLOWED)
+                            .body(Either::Right(Empty::new()))
+                            .unwrap(),
+                    );
                 }
+                let snapshot = snapshot();
+                // the guardian is the one acting for their child
+                let Some((actor, user)) = acting_member(&request, &snapshot) else {
+                    info!("403 https://{server_name}/api/user/erasure");
+                    return Some(
+                        Response::builder()
+                            .status(StatusCode::FORBIDDEN)
+                            .body(Either::Right(Empty::new()))
+                            .unwrap(),
+                    );
+                };
+                let timestamp = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs() as u32;
+                return if let Some(erasure) =
+                    request_erasure(&snapshot, &actor, &user, timestamp).await
+                {
+                    // applied once an admin approves it
+                    info!("202 https://{server_name}/api/user/erasure");
+                    Some(
+                        Response::builder()
+                            .status(StatusCode::ACCEPTED)
+                            .header(CONTENT_TYPE, JSON)
+                            .body(Either::Left(Full::from(
+                                serde_json::to_vec(&erasure).unwrap(),
+                            )))
+                            .unwrap(),
+                    )
+                } else {
+                    info!("500 https://{server_name}/api/user/erasure");
+                    Some(
+                        Response::builder()
+                            .status(StatusCode::INTERNAL_SERVER_ERROR)
+                            .body(Either::Right(Empty::new()))
+                            .unwrap(),
+                    )
+                };
             } else if path == "/prices" {
                 if request.method() != Method::GET {
                     let mut response = Response::builder();
@@ -207,17 +1714,10 @@ impl Extension for ApiExtension {
                     );
                 }
                 let snapshot = snapshot();
-                if let SessionState::Valid { user, .. } =
-                    SessionState::from_headers(request.headers(), &snapshot)
-                {
+                if let Some((_, user)) = acting_member(&request, &snapshot) {
                     let is_during_discount_period = is_during_discount_period(None);
                     let season = current_season(None);
-                    let license_type =
-                        if Category::from_dob(user.date_of_birth, season) < Category::U18 {
-                            LicenseType::Child
-                        } else {
-                            LicenseType::Adult
-                        };
+                    let license_type = expected_license_type(user.date_of_birth, season);
                     let license_price =
                         license_type.price_in_cents(&snapshot, is_during_discount_period);
                     let base_level = InsuranceLevel::Base;
@@ -284,6 +1784,22 @@ impl Extension for ApiExtension {
         }
         None
     }
+}
+
+impl Extension for ApiExtension {
+    async fn handle_api_extension(
+        &self,
+        request: Request<Incoming>,
+        server_name: &Arc<String>,
+    ) -> Option<Response<Either<Full<Bytes>, Empty<Bytes>>>> {
+        let path = request.uri().path().to_string();
+        let response = self.handle_request(request, server_name).await;
+        if let Some(response) = response.as_ref() {
+            record_api_request(&path, response.status().as_u16());
+        }
+        response
+    }
+
     async fn perform_action(&self, user: &User, action: Action) -> Option<()> {
         match action {
             Action::Totp(UpdateEmail(EmailUpdate {
@@ -292,14 +1808,16 @@ impl Extension for ApiExtension {
                 ..
             })) => {
                 // only update if the old email address is the first email address
-                // as the first one should be the one attached to the license.
-                if Some(&normalized_old_address)
-                    == user.identification.iter().find_map(|it| match it {
-                        IdentificationMethod::Email(Email {
-                            normalized_address, ..
-                        }) => Some(normalized_address),
-                        _ => None,
-                    })
+                // as the first one should be the one attached to the license,
+                // and MyFFME is not the source of truth of the email.
+                if ContactField::Email.is_pushed()
+                    && Some(&normalized_old_address)
+                        == user.identification.iter().find_map(|it| match it {
+                            IdentificationMethod::Email(Email {
+                                normalized_address, ..
+                            }) => Some(normalized_address),
+                            _ => None,
+                        })
                 {
                     debug!("email update");
                     if let Some(ref myffme_user_id) = user.metadata.as_ref().and_then(|value| {
@@ -322,6 +1840,25 @@ impl Extension for ApiExtension {
                             .await
                             .is_some()
                         {
+                            let actor = Actor::member(user);
+                            record_pushed(
+                                &snapshot(),
+                                &actor,
+                                &user.id.to_string(),
+                                ContactField::Email,
+                                &normalized_new_address,
+                            )
+                            .await;
+                            record_changes(
+                                &actor,
+                                &format!("myffme/{myffme_user_id}"),
+                                vec![Change {
+                                    path: "/email".to_string(),
+                                    before: Some(normalized_old_address.into()),
+                                    after: Some(normalized_new_address.into()),
+                                }],
+                            )
+                            .await;
                             Some(())
                         } else {
                             warn!("failed to update email");
@@ -334,14 +1871,18 @@ impl Extension for ApiExtension {
                 normalized_new_address,
                 ..
             })) => {
-                // update alternate email if it's the second email address.
+                // update alternate email if it's the second email address,
+                // and MyFFME is not the source of truth of the alternate email.
                 let mut iter = user.identification.iter().filter_map(|it| match it {
                     IdentificationMethod::Email(Email {
                         normalized_address, ..
                     }) => Some(normalized_address),
                     _ => None,
                 });
-                if let Some(email) = iter.next() {
+                if let Some(email) = iter
+                    .next()
+                    .filter(|_| ContactField::AlternateEmail.is_pushed())
+                {
                     // make sure the new email is not already the primary email.
                     if email != &normalized_new_address && iter.next().is_none() {
                         debug!("alternate email update");
@@ -359,6 +1900,25 @@ impl Extension for ApiExtension {
                             .await
                             .is_some()
                             {
+                                let actor = Actor::member(user);
+                                record_pushed(
+                                    &snapshot(),
+                                    &actor,
+                                    &user.id.to_string(),
+                                    ContactField::AlternateEmail,
+                                    &normalized_new_address,
+                                )
+                                .await;
+                                record_changes(
+                                    &actor,
+                                    &format!("myffme/{myffme_user_id}"),
+                                    vec![Change {
+                                        path: "/secondaryEmail".to_string(),
+                                        before: None,
+                                        after: Some(normalized_new_address.into()),
+                                    }],
+                                )
+                                .await;
                                 Some(())
                             } else {
                                 warn!("failed to update alternate email");
@@ -368,12 +1928,163 @@ impl Extension for ApiExtension {
                     }
                 }
             }
+            Action::Totp(UpdateSms(SmsUpdate {
+                normalized_new_number,
+                normalized_old_number,
+                ..
+            })) => {
+                if let Some(ref myffme_user_id) = user.metadata.as_ref().and_then(|value| {
+                    Metadata::deserialize(value)
+                        .inspect_err(|err| warn!("failed to deserialize metadata: {err:?}"))
+                        .ok()
+                        .and_then(|it| it.myffme_user_id)
+                }) {
+                    debug!("phone number update");
+                    // only the field that holds the old number is updated,
+                    // unless MyFFME is its source of truth
+                    return match update_phone_number(
+                        myffme_user_id,
+                        &normalized_old_number,
+                        &normalized_new_number,
+                        &pushed_phone_fields(),
+                    )
+                    .await
+                    {
+                        Some(Some(field)) => {
+                            let actor = Actor::member(user);
+                            record_pushed(
+                                &snapshot(),
+                                &actor,
+                                &user.id.to_string(),
+                                field.into(),
+                                &normalized_new_number,
+                            )
+                            .await;
+                            record_changes(
+                                &actor,
+                                &format!("myffme/{myffme_user_id}"),
+                                vec![Change {
+                                    path: format!("/{}", field.as_str()),
+                                    before: Some(normalized_old_number.into()),
+                                    after: Some(normalized_new_number.into()),
+                                }],
+                            )
+                            .await;
+                            Some(())
+                        }
+                        Some(None) => Some(()),
+                        None => {
+                            warn!("failed to update phone number");
+                            None
+                        }
+                    };
+                }
+            }
+            Action::Totp(AddSms(SmsAddition {
+                normalized_new_number,
+                ..
+            })) => {
+                if let Some(ref myffme_user_id) = user.metadata.as_ref().and_then(|value| {
+                    Metadata::deserialize(value)
+                        .inspect_err(|err| warn!("failed to deserialize metadata: {err:?}"))
+                        .ok()
+                        .and_then(|it| it.myffme_user_id)
+                }) {
+                    debug!("phone number addition");
+                    // the numbers already in MyFFME are kept
+                    return match add_phone_number(
+                        myffme_user_id,
+                        &normalized_new_number,
+                        &pushed_phone_fields(),
+                    )
+                    .await
+                    {
+                        Some(Some(field)) => {
+                            let actor = Actor::member(user);
+                            record_pushed(
+                                &snapshot(),
+                                &actor,
+                                &user.id.to_string(),
+                                field.into(),
+                                &normalized_new_number,
+                            )
+                            .await;
+                            record_changes(
+                                &actor,
+                                &format!("myffme/{myffme_user_id}"),
+                                vec![Change {
+                                    path: format!("/{}", field.as_str()),
+                                    before: None,
+                                    after: Some(normalized_new_number.into()),
+                                }],
+                            )
+                            .await;
+                            Some(())
+                        }
+                        Some(None) => Some(()),
+                        None => {
+                            warn!("failed to add phone number");
+                            None
+                        }
+                    };
+                }
+            }
             _ => {}
         }
         Some(())
     }
 }
 
+/// The member a request is about and the user acting for them, see `acting_for`.
+///
+/// Returns None without a valid session, or if the user is not a guardian of the child.
+fn acting_member(request: &Request<Incoming>, snapshot: &Arc<Snapshot>) -> Option<(Actor, User)> {
+    let SessionState::Valid { user, .. } = SessionState::from_headers(request.headers(), snapshot)
+    else {
+        return None;
+    };
+    let actor = Actor::member(&user);
+    acting_for(snapshot, user, request.uri().query()).map(|it| (actor, it))
+}
+
+#[derive(Serialize)]
+struct Insurance {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    season: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    level: Option<InsuranceLevel>,
+    options: Vec<InsuranceOption>,
+}
+
+impl From<Metadata> for Insurance {
+    fn from(metadata: Metadata) -> Self {
+        Self {
+            season: metadata.latest_license_season,
+            level: metadata.insurance_level,
+            options: metadata.insurance_options.unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct MemberInsurance {
+    user_id: String,
+    first_name: String,
+    last_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    license_number: Option<u32>,
+    #[serde(flatten)]
+    insurance: Insurance,
+}
+
+#[derive(Serialize)]
+struct RankedResult {
+    #[serde(flatten)]
+    result: CompetitionResult,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    relative_rank: Option<f32>,
+}
+
 #[derive(Serialize)]
 struct Prices {
     base_price_in_cents: u16,
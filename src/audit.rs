@@ -0,0 +1,431 @@
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::SystemTime;
+use tiered_server::store::Snapshot;
+use tiered_server::user::User;
+use tracing::warn;
+
+/// Maximum number of entries returned to the admins at once.
+const MAX_ENTRIES: usize = 1_000;
+
+/// Distinguishes the entries recorded during the same second.
+static SEQUENCE: AtomicU32 = AtomicU32::new(0);
+
+/// Who made a change.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Actor {
+    /// The member sync with MyFFME and MyCompet.
+    Sync,
+    /// A scheduled job other than the member sync.
+    Job,
+    /// An admin, by user id.
+    Admin(String),
+    /// A member or their guardian, by user id.
+    Member(String),
+}
+
+impl Actor {
+    pub(crate) fn admin(user: &User) -> Self {
+        Actor::Admin(user.id.to_string())
+    }
+
+    pub(crate) fn member(user: &User) -> Self {
+        Actor::Member(user.id.to_string())
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Actor::Sync => "sync",
+            Actor::Job => "job",
+            Actor::Admin(_) => "admin",
+            Actor::Member(_) => "member",
+        }
+    }
+}
+
+/// A value that changed, by JSON pointer.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct Change {
+    pub(crate) path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) before: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) after: Option<Value>,
+}
+
+/// An entry of the audit log.
+///
+/// Entries are never modified, except to remove the values of an erased or archived member (see
+/// [`record_anonymisation`]).
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct AuditEntry {
+    timestamp: u32,
+    actor: Actor,
+    /// Snapshot key of the entity that changed, `myffme/{id}` for the contacts of a MyFFME user and
+    /// `job/{name}` for a scheduled job.
+    key: String,
+    changes: Vec<Change>,
+}
+
+impl AuditEntry {
+    fn key(timestamp: u32, sequence: u32) -> String {
+        format!("aud/{timestamp:010}_{sequence:06}")
+    }
+}
+
+fn diff_into(
+    path: String,
+    before: Option<&Value>,
+    after: Option<&Value>,
+    changes: &mut Vec<Change>,
+) {
+    match (before, after) {
+        (Some(Value::Object(before)), Some(Value::Object(after))) => {
+            for (key, value) in before {
+                diff_into(
+                    format!("{path}/{key}"),
+                    Some(value),
+                    after.get(key),
+                    changes,
+                );
+            }
+            for (key, value) in after {
+                if !before.contains_key(key) {
+                    diff_into(format!("{path}/{key}"), None, Some(value), changes);
+                }
+            }
+        }
+        // arrays are compared as a whole, and a missing value is the same as a null
+        (before, after)
+            if before.filter(|it| !it.is_null()) != after.filter(|it| !it.is_null()) =>
+        {
+            changes.push(Change {
+                path,
+                before: before.filter(|it| !it.is_null()).cloned(),
+                after: after.filter(|it| !it.is_null()).cloned(),
+            })
+        }
+        _ => {}
+    }
+}
+
+/// Lists the values that differ between two versions of an entity.
+pub(crate) fn diff(before: Option<&Value>, after: Option<&Value>) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_into(String::new(), before, after, &mut changes);
+    changes
+}
+
+/// Appends the changes to the audit log, if there are any.
+///
+/// A failure to record is logged but doesn't fail the change itself.
+pub(crate) async fn record_changes(actor: &Actor, key: &str, changes: Vec<Change>) {
+    if changes.is_empty() {
+        return;
+    }
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32;
+    let entry = AuditEntry {
+        timestamp,
+        actor: actor.clone(),
+        key: key.to_string(),
+        changes,
+    };
+    let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed) % 1_000_000;
+    if Snapshot::set_and_return_before_update(&AuditEntry::key(timestamp, sequence), &entry)
+        .await
+        .is_none()
+    {
+        warn!("failed to record the changes of {key}");
+    }
+}
+
+/// Appends the difference between two versions of an entity to the audit log.
+pub(crate) async fn record<T: Serialize>(actor: &Actor, key: &str, before: Option<&T>, after: &T) {
+    let before = before.and_then(|it| serde_json::to_value(it).ok());
+    let after = serde_json::to_value(after).ok();
+    record_changes(actor, key, diff(before.as_ref(), after.as_ref())).await
+}
+
+/// Deletes an entity and records its removal.
+pub(crate) async fn remove<T: Serialize>(actor: &Actor, key: &str, before: &T) -> Option<()> {
+    Snapshot::delete_and_wait_for_update(key).await?;
+    let before = serde_json::to_value(before).ok();
+    record_changes(actor, key, diff(before.as_ref(), None)).await;
+    Some(())
+}
+
+/// Deletes an erased or archived entity, and removes the values from its audit log the same way
+/// as [`record_anonymisation`].
+pub(crate) async fn erase<T: Serialize>(
+    snapshot: &Snapshot,
+    actor: &Actor,
+    key: &str,
+    before: &T,
+) -> Option<()> {
+    Snapshot::delete_and_wait_for_update(key).await?;
+    let before = serde_json::to_value(before).ok()?;
+    record_anonymisation(snapshot, actor, key, &before, &Value::Null).await;
+    Some(())
+}
+
+/// Removes the values from the entries of an entity, only the changed paths are kept.
+///
+/// This is the only case where past entries are rewritten: the log must not keep the data of the
+/// members whose data was erased.
+pub(crate) async fn redact_history(snapshot: &Snapshot, key: &str) {
+    for (entry_key, mut entry) in snapshot.list::<AuditEntry>("aud/") {
+        if entry.key != key
+            || entry
+                .changes
+                .iter()
+                .all(|it| it.before.is_none() && it.after.is_none())
+        {
+            continue;
+        }
+        for change in entry.changes.iter_mut() {
+            change.before = None;
+            change.after = None;
+        }
+        if Snapshot::set_and_wait_for_update(&entry_key, &entry)
+            .await
+            .is_none()
+        {
+            warn!("failed to redact the audit log of {key}");
+        }
+    }
+}
+
+/// Removes the objects with the user id from the arrays in the value.
+fn remove_member(value: &mut Value, user_id: &str) -> bool {
+    match value {
+        Value::Array(items) => {
+            let count = items.len();
+            items.retain(|it| it.get("user_id").and_then(Value::as_str) != Some(user_id));
+            let mut removed = items.len() != count;
+            for item in items.iter_mut() {
+                removed |= remove_member(item, user_id);
+            }
+            removed
+        }
+        Value::Object(fields) => fields
+            .values_mut()
+            .fold(false, |removed, it| remove_member(it, user_id) || removed),
+        _ => false,
+    }
+}
+
+/// Removes the objects with the user id from the arrays of the entries under the key prefix, for
+/// the entities that list members, such as the competition registrations.
+pub(crate) async fn redact_member(snapshot: &Snapshot, prefix: &str, user_id: &str) {
+    for (entry_key, mut entry) in snapshot.list::<AuditEntry>("aud/") {
+        if !entry.key.starts_with(prefix) {
+            continue;
+        }
+        let mut removed = false;
+        for change in entry.changes.iter_mut() {
+            for value in [change.before.as_mut(), change.after.as_mut()]
+                .into_iter()
+                .flatten()
+            {
+                removed |= remove_member(value, user_id);
+            }
+        }
+        if removed
+            && Snapshot::set_and_wait_for_update(&entry_key, &entry)
+                .await
+                .is_none()
+        {
+            warn!("failed to redact the audit log of {}", entry.key);
+        }
+    }
+}
+
+/// Removes the values from the entries of an erased or archived entity with [`redact_history`],
+/// and records the anonymisation itself the same way.
+pub(crate) async fn record_anonymisation<T: Serialize>(
+    snapshot: &Snapshot,
+    actor: &Actor,
+    key: &str,
+    before: &T,
+    after: &T,
+) {
+    redact_history(snapshot, key).await;
+    let before = serde_json::to_value(before).ok();
+    let after = serde_json::to_value(after).ok();
+    let changes = diff(before.as_ref(), after.as_ref())
+        .into_iter()
+        .map(|it| Change {
+            path: it.path,
+            before: None,
+            after: None,
+        })
+        .collect();
+    record_changes(actor, key, changes).await
+}
+
+/// Filters of the audit log, from
+/// `?key=...&actor=sync|job|admin|member&user_id=...&since=...&until=...&offset=...`.
+///
+/// `key` is a prefix of the snapshot key, `user_id` the admin or member that made the change,
+/// `since` and `until` are timestamps, and `offset` the number of matching entries to skip.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct AuditFilter {
+    key: Option<String>,
+    actor: Option<String>,
+    user_id: Option<String>,
+    since: Option<u32>,
+    until: Option<u32>,
+    offset: usize,
+}
+
+impl AuditFilter {
+    pub(crate) fn from_query(query: Option<&str>) -> Option<Self> {
+        let mut filter = Self::default();
+        let Some(query) = query else {
+            return Some(filter);
+        };
+        let url = Url::parse(&format!("https://localhost/?{query}")).ok()?;
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "key" => filter.key = Some(value.to_string()),
+                "actor" => {
+                    if !["sync", "job", "admin", "member"].contains(&value.as_ref()) {
+                        return None;
+                    }
+                    filter.actor = Some(value.to_string())
+                }
+                "user_id" => filter.user_id = Some(value.to_string()),
+                "since" => filter.since = Some(value.parse().ok()?),
+                "until" => filter.until = Some(value.parse().ok()?),
+                "offset" => filter.offset = value.parse().ok()?,
+                _ => {}
+            }
+        }
+        Some(filter)
+    }
+
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.key.as_ref().is_none_or(|it| entry.key.starts_with(it))
+            && self
+                .actor
+                .as_ref()
+                .is_none_or(|it| entry.actor.kind() == it)
+            && self.user_id.as_ref().is_none_or(|it| match &entry.actor {
+                Actor::Admin(user_id) | Actor::Member(user_id) => user_id == it,
+                _ => false,
+            })
+            && self.since.is_none_or(|it| entry.timestamp >= it)
+            && self.until.is_none_or(|it| entry.timestamp <= it)
+    }
+}
+
+/// Lists the entries of the audit log that match the filter, latest first, a page at a time.
+pub(crate) fn audit_log(snapshot: &Snapshot, filter: &AuditFilter) -> Vec<AuditEntry> {
+    let mut entries = snapshot
+        .list::<AuditEntry>("aud/")
+        .map(|(_, it)| it)
+        .filter(|it| filter.matches(it))
+        .collect::<Vec<_>>();
+    entries.reverse();
+    entries
+        .into_iter()
+        .skip(filter.offset)
+        .take(MAX_ENTRIES)
+        .collect()
+}
+
+/// The changes made to the entities, oldest first.
+pub(crate) fn history_of(snapshot: &Snapshot, keys: &[&str]) -> Vec<AuditEntry> {
+    snapshot
+        .list::<AuditEntry>("aud/")
+        .map(|(_, it)| it)
+        .filter(|it| keys.contains(&it.key.as_str()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff() {
+        let before = json!({
+            "first_name": "Léa",
+            "metadata": {"license_number": 123, "gender": "female", "address": null},
+            "identification": [{"email": "lea@example.com"}],
+        });
+        let after = json!({
+            "first_name": "Léa",
+            "metadata": {"license_number": 456, "gender": "female", "lifecycle": "active"},
+            "identification": [],
+        });
+        assert_eq!(
+            vec![
+                Change {
+                    path: "/metadata/license_number".to_string(),
+                    before: Some(json!(123)),
+                    after: Some(json!(456)),
+                },
+                Change {
+                    path: "/metadata/lifecycle".to_string(),
+                    before: None,
+                    after: Some(json!("active")),
+                },
+                Change {
+                    path: "/identification".to_string(),
+                    before: Some(json!([{"email": "lea@example.com"}])),
+                    after: Some(json!([])),
+                },
+            ],
+            diff(Some(&before), Some(&after))
+        );
+        assert!(diff(Some(&before), Some(&before)).is_empty());
+        assert_eq!(1, diff(None, Some(&json!({"a": 1}))).len());
+        assert_eq!("aud/1800000000_000042", AuditEntry::key(1_800_000_000, 42));
+        let mut athletes = json!([
+            {"user_id": "1", "alerted": false},
+            {"user_id": "2", "alerted": true},
+        ]);
+        assert!(remove_member(&mut athletes, "1"));
+        assert_eq!(json!([{"user_id": "2", "alerted": true}]), athletes);
+        assert!(!remove_member(&mut athletes, "1"));
+    }
+
+    #[test]
+    fn test_audit_filter() {
+        let filter =
+            AuditFilter::from_query(Some("key=acc/&actor=admin&since=1800000000")).unwrap();
+        let entry = AuditEntry {
+            timestamp: 1_800_000_000,
+            actor: Actor::Admin("1".to_string()),
+            key: "acc/2".to_string(),
+            changes: vec![],
+        };
+        assert!(filter.matches(&entry));
+        assert!(!filter.matches(&AuditEntry {
+            actor: Actor::Sync,
+            ..entry.clone()
+        }));
+        assert!(!filter.matches(&AuditEntry {
+            key: "crg/20260101_bloc".to_string(),
+            ..entry.clone()
+        }));
+        assert_eq!(None, AuditFilter::from_query(Some("actor=robot")));
+        assert_eq!(None, AuditFilter::from_query(Some("since=yesterday")));
+        assert_eq!(
+            Some(AuditFilter {
+                offset: 1000,
+                ..Default::default()
+            }),
+            AuditFilter::from_query(Some("offset=1000"))
+        );
+        assert_eq!(Some(AuditFilter::default()), AuditFilter::from_query(None));
+    }
+}
@@ -1,4 +1,6 @@
-#[derive(PartialEq, PartialOrd, Ord, Eq, Copy, Clone)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, PartialOrd, Ord, Eq, Copy, Clone)]
 pub enum Category {
     Baby,
     U8,
@@ -1,6 +1,8 @@
 use crate::http_client::json_client;
+use crate::status::FAILURES;
 use pinboard::Pinboard;
 use serde::Deserialize;
+use std::sync::atomic::Ordering;
 use std::sync::LazyLock;
 use tracing::warn;
 
@@ -29,6 +31,7 @@ pub(crate) async fn update_chrome_version(timestamp: u32) -> bool {
             Ok(it) => {
                 if it.is_empty() {
                     warn!("failed to get chrome version");
+                    FAILURES.chrome_version.fetch_add(1, Ordering::Relaxed);
                     return false;
                 }
                 CHROME_VERSION.set(ChromeVersion {
@@ -39,11 +42,13 @@ pub(crate) async fn update_chrome_version(timestamp: u32) -> bool {
             }
             Err(err) => {
                 warn!("failed to get chrome version:\n{err:?}");
+                FAILURES.chrome_version.fetch_add(1, Ordering::Relaxed);
                 false
             }
         },
         Err(err) => {
             warn!("failed to get response from chromiumdash for the latest chrome version:\n{err:?}");
+            FAILURES.chrome_version.fetch_add(1, Ordering::Relaxed);
             false
         }
     }
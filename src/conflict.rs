@@ -0,0 +1,846 @@
+use crate::audit::{record, record_anonymisation, record_changes, Actor, Change};
+use crate::myffme::email::update_email;
+use crate::myffme::is_mobile_number;
+use crate::myffme::licensee::{user_data, UserData};
+use crate::myffme::phone::{update_phone_field, PhoneField};
+use crate::user::Metadata;
+use serde::{Deserialize, Serialize};
+use std::sync::LazyLock;
+use tiered_server::env::{secret_value, ConfigurationKey};
+use tiered_server::norm::{normalize_email, normalize_phone_number};
+use tiered_server::store::Snapshot;
+use tiered_server::user::{Email, IdentificationMethod, Sms, User};
+use tracing::{info, warn};
+
+const CONTACT_SYNC_POLICY_KEY: ConfigurationKey = ConfigurationKey::Other {
+    variable_name: "CONTACT_SYNC_POLICY",
+};
+
+/// Source of truth of each contact field, `newest_wins` unless configured otherwise.
+static POLICIES: LazyLock<[SyncPolicy; 4]> = LazyLock::new(|| {
+    secret_value(CONTACT_SYNC_POLICY_KEY)
+        .and_then(|it| {
+            parse_policies(&it).or_else(|| {
+                warn!("invalid contact sync policy: {it}");
+                None
+            })
+        })
+        .unwrap_or([SyncPolicy::NewestWins; 4])
+});
+
+/// Which side wins when the local and MyFFME values of a contact field differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SyncPolicy {
+    /// The local value is pushed to MyFFME.
+    LocalWins,
+    /// The MyFFME value replaces the local one.
+    RemoteWins,
+    /// The side that changed since the last sync wins, changes on both sides are queued for the
+    /// admins.
+    NewestWins,
+}
+
+impl SyncPolicy {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "local_wins" => Some(SyncPolicy::LocalWins),
+            "remote_wins" => Some(SyncPolicy::RemoteWins),
+            "newest_wins" => Some(SyncPolicy::NewestWins),
+            _ => None,
+        }
+    }
+}
+
+/// `email=local_wins,mobile=remote_wins`, the fields that are not listed are `newest_wins`.
+fn parse_policies(value: &str) -> Option<[SyncPolicy; 4]> {
+    let mut policies = [SyncPolicy::NewestWins; 4];
+    for it in value.split(',').map(str::trim).filter(|it| !it.is_empty()) {
+        let (field, policy) = it.split_once('=')?;
+        let field = ContactField::parse(field.trim())?;
+        policies[field as usize] = SyncPolicy::parse(policy.trim())?;
+    }
+    Some(policies)
+}
+
+/// The contact fields of a member in MyFFME.
+///
+/// Locally, the first two email addresses and the first two phone numbers of the identification.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ContactField {
+    Email,
+    AlternateEmail,
+    Mobile,
+    Phone,
+}
+
+impl ContactField {
+    const ALL: [ContactField; 4] = [
+        ContactField::Email,
+        ContactField::AlternateEmail,
+        ContactField::Mobile,
+        ContactField::Phone,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            ContactField::Email => "email",
+            ContactField::AlternateEmail => "alternate_email",
+            ContactField::Mobile => "mobile",
+            ContactField::Phone => "phone",
+        }
+    }
+
+    /// JSON pointer of the field in the MyFFME user data, for the audit log.
+    fn myffme_pointer(self) -> &'static str {
+        match self {
+            ContactField::Email => "/email",
+            ContactField::AlternateEmail => "/secondaryEmail",
+            ContactField::Mobile => "/mobile",
+            ContactField::Phone => "/phone",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|it| it.as_str() == value)
+    }
+
+    fn is_email(self) -> bool {
+        matches!(self, ContactField::Email | ContactField::AlternateEmail)
+    }
+
+    fn policy(self) -> SyncPolicy {
+        POLICIES[self as usize]
+    }
+
+    /// Whether the changes members make to the field are pushed to MyFFME, MyFFME is the source
+    /// of truth of the `remote_wins` fields.
+    pub(crate) fn is_pushed(self) -> bool {
+        self.policy() != SyncPolicy::RemoteWins
+    }
+}
+
+impl From<PhoneField> for ContactField {
+    fn from(value: PhoneField) -> Self {
+        match value {
+            PhoneField::Mobile => ContactField::Mobile,
+            PhoneField::Phone => ContactField::Phone,
+        }
+    }
+}
+
+/// The phone fields that members can update in MyFFME.
+pub(crate) fn pushed_phone_fields() -> Vec<PhoneField> {
+    [PhoneField::Mobile, PhoneField::Phone]
+        .into_iter()
+        .filter(|it| ContactField::from(*it).is_pushed())
+        .collect()
+}
+
+/// Normalized email addresses and phone numbers of a member.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct Contacts {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alternate_email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mobile: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phone: Option<String>,
+}
+
+impl Contacts {
+    fn get(&self, field: ContactField) -> Option<&str> {
+        match field {
+            ContactField::Email => self.email.as_deref(),
+            ContactField::AlternateEmail => self.alternate_email.as_deref(),
+            ContactField::Mobile => self.mobile.as_deref(),
+            ContactField::Phone => self.phone.as_deref(),
+        }
+    }
+
+    fn set(&mut self, field: ContactField, value: Option<String>) {
+        match field {
+            ContactField::Email => self.email = value,
+            ContactField::AlternateEmail => self.alternate_email = value,
+            ContactField::Mobile => self.mobile = value,
+            ContactField::Phone => self.phone = value,
+        }
+    }
+
+    pub(crate) fn remote(user_data: &UserData) -> Self {
+        let value = |it: Option<&str>| it.map(str::trim).filter(|it| !it.is_empty());
+        Self {
+            email: value(user_data.email.as_deref()).map(normalize_email),
+            alternate_email: value(user_data.alternate_email.as_deref()).map(normalize_email),
+            mobile: value(user_data.phone_number.as_deref())
+                .map(|it| normalize_phone_number(it, 33)),
+            phone: value(user_data.alternate_phone_number.as_deref())
+                .map(|it| normalize_phone_number(it, 33)),
+        }
+    }
+
+    /// Landlines can't be used to log in, the phone fields that hold one are left alone.
+    fn is_synced(&self, field: ContactField) -> bool {
+        field.is_email() || self.get(field).is_none_or(is_mobile_number)
+    }
+
+    /// The values of the synced fields, the baseline of the first sync.
+    fn baseline(&self) -> Self {
+        let mut baseline = Self::default();
+        for field in ContactField::ALL {
+            if self.is_synced(field) {
+                baseline.set(field, self.get(field).map(str::to_string));
+            }
+        }
+        baseline
+    }
+
+    /// The local values, without the identification of the emergency contacts copied by earlier
+    /// syncs.
+    ///
+    /// A value that is in MyFFME or was synced stays with its field, the other values fill the
+    /// remaining fields in the order of the identification, the fields empty in MyFFME first.
+    fn local(
+        user: &User,
+        remote: &Contacts,
+        synced: Option<&Contacts>,
+        excluded: &[IdentificationMethod],
+    ) -> Self {
+        let is_own = |it: &IdentificationMethod, normalized: &str| {
+            ContactField::ALL
+                .into_iter()
+                .any(|field| remote.get(field) == Some(normalized))
+                || !excluded.contains(it)
+        };
+        let emails = user
+            .identification
+            .iter()
+            .filter_map(|it| match it {
+                IdentificationMethod::Email(email) if is_own(it, &email.normalized_address) => {
+                    Some(email.normalized_address.clone())
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let numbers = user
+            .identification
+            .iter()
+            .filter_map(|it| match it {
+                IdentificationMethod::Sms(sms) if is_own(it, &sms.normalized_number) => {
+                    Some(sms.normalized_number.clone())
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let mut local = Self::default();
+        for (fields, mut values) in [
+            ([ContactField::Email, ContactField::AlternateEmail], emails),
+            ([ContactField::Mobile, ContactField::Phone], numbers),
+        ] {
+            let fields = fields.into_iter().filter(|it| remote.is_synced(*it));
+            for field in fields.clone() {
+                let known = [Some(remote), synced]
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|it| it.get(field))
+                    .find_map(|value| values.iter().position(|it| it == value));
+                if let Some(i) = known {
+                    local.set(field, Some(values.remove(i)));
+                }
+            }
+            // the fields that are empty in MyFFME first, so that a new value is added to MyFFME
+            // rather than replacing one
+            let mut fields = fields.collect::<Vec<_>>();
+            fields.sort_by_key(|it| remote.get(*it).is_some());
+            let mut values = values.into_iter();
+            for field in fields {
+                if local.get(field).is_none() {
+                    local.set(field, values.next());
+                }
+            }
+        }
+        local
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Resolution {
+    InSync,
+    KeepLocal,
+    KeepRemote,
+    Conflict,
+}
+
+/// Compares the local and MyFFME values with the value both sides had after the last sync.
+fn resolve(
+    policy: SyncPolicy,
+    synced: Option<&str>,
+    local: Option<&str>,
+    remote: Option<&str>,
+) -> Resolution {
+    if local == remote {
+        return Resolution::InSync;
+    }
+    match policy {
+        SyncPolicy::LocalWins => Resolution::KeepLocal,
+        SyncPolicy::RemoteWins => Resolution::KeepRemote,
+        SyncPolicy::NewestWins if synced == local => Resolution::KeepRemote,
+        SyncPolicy::NewestWins if synced == remote => Resolution::KeepLocal,
+        SyncPolicy::NewestWins => Resolution::Conflict,
+    }
+}
+
+/// Replaces the local value of a field, the new value is removed from the other positions.
+fn replace_local(user: &mut User, field: ContactField, old: Option<&str>, new: &str) {
+    let is_value = |it: &IdentificationMethod, value: &str| match it {
+        IdentificationMethod::Email(it) if field.is_email() => it.normalized_address == value,
+        IdentificationMethod::Sms(it) if !field.is_email() => it.normalized_number == value,
+        _ => false,
+    };
+    let identification = if field.is_email() {
+        IdentificationMethod::Email(Email::from(new.to_string()))
+    } else {
+        IdentificationMethod::Sms(Sms {
+            number: new.to_string(),
+            normalized_number: new.to_string(),
+        })
+    };
+    user.identification.retain(|it| !is_value(it, new));
+    match old.and_then(|old| user.identification.iter().position(|it| is_value(it, old))) {
+        Some(i) => user.identification[i] = identification,
+        None => user.identification.push(identification),
+    }
+}
+
+/// Updates the field in MyFFME, `remote` holds the current MyFFME values.
+async fn push(
+    actor: &Actor,
+    myffme_user_id: &str,
+    field: ContactField,
+    value: &str,
+    remote: &Contacts,
+) -> Option<()> {
+    match field {
+        ContactField::Email => {
+            update_email(myffme_user_id, value, remote.alternate_email.as_deref()).await
+        }
+        ContactField::AlternateEmail => {
+            update_email(
+                myffme_user_id,
+                remote.email.as_deref().unwrap_or_default(),
+                Some(value),
+            )
+            .await
+        }
+        ContactField::Mobile => update_phone_field(myffme_user_id, PhoneField::Mobile, value).await,
+        ContactField::Phone => update_phone_field(myffme_user_id, PhoneField::Phone, value).await,
+    }?;
+    record_changes(
+        actor,
+        &format!("myffme/{myffme_user_id}"),
+        vec![Change {
+            path: field.myffme_pointer().to_string(),
+            before: remote.get(field).map(Into::into),
+            after: Some(value.into()),
+        }],
+    )
+    .await;
+    Some(())
+}
+
+/// Records a value a member pushed to MyFFME as the synced value of the field, so that the next
+/// sync doesn't take it for a change on both sides.
+pub(crate) async fn record_pushed(
+    snapshot: &Snapshot,
+    actor: &Actor,
+    user_id: &str,
+    field: ContactField,
+    value: &str,
+) {
+    let key = format!("acc/{user_id}");
+    let Some(mut user) = snapshot.get::<User>(&key) else {
+        return;
+    };
+    let Some(mut metadata) = user
+        .metadata
+        .as_ref()
+        .and_then(|it| serde_json::from_value::<Metadata>(it.clone()).ok())
+    else {
+        return;
+    };
+    let before = user.clone();
+    let mut synced = metadata.synced_contacts.take().unwrap_or_default();
+    synced.set(field, Some(value.to_string()));
+    metadata.synced_contacts = Some(synced);
+    let Ok(metadata) = serde_json::to_value(metadata) else {
+        return;
+    };
+    user.metadata = Some(metadata);
+    if Snapshot::set_and_wait_for_update(&key, &user)
+        .await
+        .is_none()
+    {
+        warn!("failed to record the synced {} of {key}", field.as_str());
+        return;
+    }
+    record(actor, &key, Some(&before), &user).await;
+}
+
+/// Which value an admin kept.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Side {
+    Local,
+    Remote,
+}
+
+/// A contact field that changed both locally and in MyFFME since the last sync.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct Conflict {
+    user_id: String,
+    first_name: String,
+    last_name: String,
+    field: ContactField,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    local: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remote: Option<String>,
+    detected: u32,
+    /// When an admin chose a value, or the sync found both sides equal again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resolved: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resolved_by: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kept: Option<Side>,
+}
+
+impl Conflict {
+    fn key(user_id: &str, field: ContactField) -> String {
+        format!("cfl/{}_{user_id}", field.as_str())
+    }
+}
+
+async fn queue_conflict(
+    snapshot: &Snapshot,
+    user: &User,
+    field: ContactField,
+    local: Option<&str>,
+    remote: Option<&str>,
+    now: u32,
+) {
+    let user_id = user.id.to_string();
+    let key = Conflict::key(&user_id, field);
+    let before = snapshot.get::<Conflict>(&key);
+    if before.as_ref().is_some_and(|it| {
+        it.resolved.is_none() && it.local.as_deref() == local && it.remote.as_deref() == remote
+    }) {
+        return;
+    }
+    info!(
+        "conflicting {} for user {} {}",
+        field.as_str(),
+        user.first_name,
+        user.last_name
+    );
+    let conflict = Conflict {
+        user_id,
+        first_name: user.first_name.clone(),
+        last_name: user.last_name.clone(),
+        field,
+        local: local.map(str::to_string),
+        remote: remote.map(str::to_string),
+        detected: now,
+        resolved: None,
+        resolved_by: None,
+        kept: None,
+    };
+    if Snapshot::set_and_wait_for_update(&key, &conflict)
+        .await
+        .is_none()
+    {
+        warn!("failed to queue the conflict {key}");
+        return;
+    }
+    record(&Actor::Sync, &key, before.as_ref(), &conflict).await;
+}
+
+/// Closes the pending conflict of the field, once both sides have the same value.
+async fn close_conflict(snapshot: &Snapshot, user: &User, field: ContactField, now: u32) {
+    let key = Conflict::key(&user.id.to_string(), field);
+    let Some(conflict) = snapshot
+        .get::<Conflict>(&key)
+        .filter(|it| it.resolved.is_none())
+    else {
+        return;
+    };
+    let resolved = Conflict {
+        resolved: Some(now),
+        ..conflict.clone()
+    };
+    if Snapshot::set_and_wait_for_update(&key, &resolved)
+        .await
+        .is_none()
+    {
+        warn!("failed to close the conflict {key}");
+        return;
+    }
+    record(&Actor::Sync, &key, Some(&conflict), &resolved).await;
+}
+
+pub(crate) struct Reconciliation {
+    /// The values both sides have now, for the next sync.
+    pub(crate) synced: Contacts,
+    /// Whether the identification of the user changed.
+    pub(crate) modified: bool,
+    pub(crate) conflicts: usize,
+}
+
+/// Reconciles the local contacts of a member with their MyFFME contacts, following the policy of
+/// each field.
+///
+/// The first sync only records the MyFFME values as the baseline, nothing is pushed or queued.
+///
+/// An empty value never clears the other side: members keep a way to log in, and MyFFME keeps a
+/// way to reach them. A local value replaced by the MyFFME one is removed from the identification.
+pub(crate) async fn reconcile(
+    snapshot: &Snapshot,
+    user: &mut User,
+    myffme_user_id: &str,
+    synced: Option<&Contacts>,
+    remote: &Contacts,
+    excluded: &[IdentificationMethod],
+    now: u32,
+) -> Reconciliation {
+    let Some(synced) = synced else {
+        return Reconciliation {
+            synced: remote.baseline(),
+            modified: false,
+            conflicts: 0,
+        };
+    };
+    let local = Contacts::local(user, remote, Some(synced), excluded);
+    let mut reconciliation = Reconciliation {
+        synced: synced.clone(),
+        modified: false,
+        conflicts: 0,
+    };
+    // the MyFFME values, after the updates already pushed
+    let mut pushed = remote.clone();
+    for field in ContactField::ALL {
+        if !remote.is_synced(field) {
+            continue;
+        }
+        let local_value = local.get(field);
+        let remote_value = remote.get(field);
+        let value = match resolve(field.policy(), synced.get(field), local_value, remote_value) {
+            Resolution::InSync => local_value,
+            Resolution::KeepLocal => {
+                let Some(value) = local_value else {
+                    continue;
+                };
+                info!(
+                    "updating {} of user {} {} in MyFFME",
+                    field.as_str(),
+                    user.first_name,
+                    user.last_name
+                );
+                if push(&Actor::Sync, myffme_user_id, field, value, &pushed)
+                    .await
+                    .is_none()
+                {
+                    warn!("failed to update {} in MyFFME", field.as_str());
+                    continue;
+                }
+                pushed.set(field, Some(value.to_string()));
+                Some(value)
+            }
+            Resolution::KeepRemote => {
+                let Some(value) = remote_value else {
+                    continue;
+                };
+                info!(
+                    "updating {} of user {} {}",
+                    field.as_str(),
+                    user.first_name,
+                    user.last_name
+                );
+                replace_local(user, field, local_value, value);
+                reconciliation.modified = true;
+                Some(value)
+            }
+            Resolution::Conflict => {
+                queue_conflict(snapshot, user, field, local_value, remote_value, now).await;
+                reconciliation.conflicts += 1;
+                continue;
+            }
+        };
+        reconciliation.synced.set(field, value.map(str::to_string));
+        close_conflict(snapshot, user, field, now).await;
+    }
+    reconciliation
+}
+
+/// Lists the conflicts waiting for an admin, the oldest first.
+pub(crate) fn pending_conflicts(snapshot: &Snapshot) -> Vec<Conflict> {
+    let mut conflicts = snapshot
+        .list::<Conflict>("cfl/")
+        .map(|(_, it)| it)
+        .filter(|it| it.resolved.is_none())
+        .collect::<Vec<_>>();
+    conflicts.sort_by_key(|it| it.detected);
+    conflicts
+}
+
+/// Request body for an admin to choose the value of a conflicting field.
+#[derive(Debug, Deserialize)]
+pub(crate) struct ConflictResolution {
+    user_id: String,
+    field: ContactField,
+    keep: Side,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ConflictError {
+    UnknownConflict,
+    /// The chosen side has no value, values are never removed.
+    MissingValue,
+    MyFFME,
+    Storage,
+}
+
+/// Applies the value chosen by an admin to the other side.
+pub(crate) async fn resolve_conflict(
+    snapshot: &Snapshot,
+    resolution: ConflictResolution,
+    admin: &User,
+    now: u32,
+) -> Result<Conflict, ConflictError> {
+    let ConflictResolution {
+        user_id,
+        field,
+        keep,
+    } = resolution;
+    let key = Conflict::key(&user_id, field);
+    let conflict = snapshot
+        .get::<Conflict>(&key)
+        .filter(|it| it.resolved.is_none())
+        .ok_or(ConflictError::UnknownConflict)?;
+    let value = match keep {
+        Side::Local => conflict.local.clone(),
+        Side::Remote => conflict.remote.clone(),
+    }
+    .ok_or(ConflictError::MissingValue)?;
+    let user_key = format!("acc/{user_id}");
+    let mut user = snapshot
+        .get::<User>(&user_key)
+        .ok_or(ConflictError::UnknownConflict)?;
+    let mut metadata = user
+        .metadata
+        .as_ref()
+        .and_then(|it| serde_json::from_value::<Metadata>(it.clone()).ok())
+        .ok_or(ConflictError::UnknownConflict)?;
+    let myffme_user_id = metadata
+        .myffme_user_id
+        .clone()
+        .ok_or(ConflictError::UnknownConflict)?;
+    let actor = Actor::admin(admin);
+    let before = user.clone();
+    match keep {
+        Side::Local => {
+            let remote = user_data(&myffme_user_id)
+                .await
+                .map(|it| Contacts::remote(&it))
+                .ok_or(ConflictError::MyFFME)?;
+            push(&actor, &myffme_user_id, field, &value, &remote)
+                .await
+                .ok_or(ConflictError::MyFFME)?;
+        }
+        Side::Remote => replace_local(&mut user, field, conflict.local.as_deref(), &value),
+    }
+    let mut synced = metadata.synced_contacts.take().unwrap_or_default();
+    synced.set(field, Some(value));
+    metadata.synced_contacts = Some(synced);
+    user.metadata = Some(serde_json::to_value(metadata).map_err(|_| ConflictError::Storage)?);
+    Snapshot::set_and_wait_for_update(&user_key, &user)
+        .await
+        .ok_or(ConflictError::Storage)?;
+    record(&actor, &user_key, Some(&before), &user).await;
+    let resolved = Conflict {
+        resolved: Some(now),
+        resolved_by: Some(admin.id.to_string()),
+        kept: Some(keep),
+        ..conflict.clone()
+    };
+    Snapshot::set_and_wait_for_update(&key, &resolved)
+        .await
+        .ok_or(ConflictError::Storage)?;
+    record(&actor, &key, Some(&conflict), &resolved).await;
+    info!("resolved {key}");
+    Ok(resolved)
+}
+
+/// Removes the values and names from the conflicts of an erased or archived member.
+pub(crate) async fn anonymise_conflicts(
+    snapshot: &Snapshot,
+    actor: &Actor,
+    user_id: &str,
+    now: u32,
+) {
+    for (key, conflict) in snapshot.list::<Conflict>("cfl/") {
+        if conflict.user_id != user_id {
+            continue;
+        }
+        let anonymised = Conflict {
+            first_name: String::new(),
+            last_name: String::new(),
+            local: None,
+            remote: None,
+            resolved: conflict.resolved.or(Some(now)),
+            ..conflict.clone()
+        };
+        if Snapshot::set_and_wait_for_update(key, &anonymised)
+            .await
+            .is_none()
+        {
+            warn!("failed to anonymise the conflict {key}");
+            continue;
+        }
+        record_anonymisation(snapshot, actor, key, &conflict, &anonymised).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user::test_user;
+
+    #[test]
+    fn test_resolve() {
+        use SyncPolicy::*;
+        let (a, b, c) = (Some("a"), Some("b"), Some("c"));
+        assert_eq!(Resolution::InSync, resolve(NewestWins, a, b, b));
+        assert_eq!(Resolution::KeepRemote, resolve(NewestWins, a, a, b));
+        assert_eq!(Resolution::KeepLocal, resolve(NewestWins, a, b, a));
+        assert_eq!(Resolution::Conflict, resolve(NewestWins, a, b, c));
+        assert_eq!(Resolution::Conflict, resolve(NewestWins, None, b, c));
+        assert_eq!(Resolution::KeepRemote, resolve(NewestWins, None, None, b));
+        assert_eq!(Resolution::KeepLocal, resolve(LocalWins, a, a, b));
+        assert_eq!(Resolution::KeepRemote, resolve(RemoteWins, a, b, a));
+    }
+
+    #[test]
+    fn test_parse_policies() {
+        use SyncPolicy::*;
+        assert_eq!(Some([NewestWins; 4]), parse_policies(""));
+        assert_eq!(
+            Some([LocalWins, NewestWins, RemoteWins, NewestWins]),
+            parse_policies("email=local_wins, mobile=remote_wins")
+        );
+        assert_eq!(None, parse_policies("email=oldest_wins"));
+        assert_eq!(None, parse_policies("fax=local_wins"));
+    }
+
+    #[test]
+    fn test_contacts() {
+        let email = |it: &str| IdentificationMethod::Email(Email::from(it.to_string()));
+        let sms = |it: &str| {
+            IdentificationMethod::Sms(Sms {
+                number: it.to_string(),
+                normalized_number: it.to_string(),
+            })
+        };
+        let mut user = User {
+            identification: vec![
+                email("parent@example.com"),
+                email("lea@example.com"),
+                sms("+33612345678"),
+            ],
+            metadata: None,
+            ..test_user("Léa", "Martin", 20100101, Metadata::default())
+        };
+        let remote = Contacts {
+            email: Some("lea@example.com".to_string()),
+            mobile: Some("+33123456789".to_string()),
+            ..Default::default()
+        };
+        assert!(!remote.is_synced(ContactField::Mobile));
+        assert!(remote.is_synced(ContactField::Phone));
+        let local = Contacts::local(&user, &remote, None, &[email("parent@example.com")]);
+        assert_eq!(
+            Contacts {
+                email: Some("lea@example.com".to_string()),
+                phone: Some("+33612345678".to_string()),
+                ..Default::default()
+            },
+            local
+        );
+        user.identification.push(email("lea.martin@example.com"));
+        replace_local(
+            &mut user,
+            ContactField::Email,
+            Some("lea@example.com"),
+            "lea.martin@example.com",
+        );
+        assert_eq!(
+            vec![
+                email("parent@example.com"),
+                email("lea.martin@example.com"),
+                sms("+33612345678"),
+            ],
+            user.identification
+        );
+        assert_eq!(
+            "cfl/alternate_email_1",
+            Conflict::key("1", ContactField::AlternateEmail)
+        );
+    }
+
+    #[test]
+    fn test_signup_email() {
+        let email = |it: &str| IdentificationMethod::Email(Email::from(it.to_string()));
+        let mut user = User {
+            identification: vec![email("lea@example.com")],
+            metadata: None,
+            ..test_user("Léa", "Martin", 20100101, Metadata::default())
+        };
+        let remote = Contacts {
+            email: Some("lea.martin@example.com".to_string()),
+            ..Default::default()
+        };
+        // the first sync only records the MyFFME values
+        let synced = remote.baseline();
+        assert_eq!(remote, synced);
+        let local = Contacts::local(&user, &remote, Some(&synced), &[]);
+        assert_eq!(
+            Contacts {
+                alternate_email: Some("lea@example.com".to_string()),
+                ..Default::default()
+            },
+            local
+        );
+        // the signup email is added to MyFFME, without replacing the MyFFME email
+        assert_eq!(
+            Resolution::KeepLocal,
+            resolve(
+                SyncPolicy::NewestWins,
+                synced.alternate_email.as_deref(),
+                local.alternate_email.as_deref(),
+                remote.alternate_email.as_deref()
+            )
+        );
+        // once both are known locally, they stay with their field whatever their order
+        user.identification.push(email("lea.martin@example.com"));
+        let local = Contacts::local(&user, &remote, Some(&synced), &[]);
+        assert_eq!(
+            Contacts {
+                email: Some("lea.martin@example.com".to_string()),
+                alternate_email: Some("lea@example.com".to_string()),
+                ..Default::default()
+            },
+            local
+        );
+    }
+}
@@ -0,0 +1,390 @@
+use crate::audit::{record, remove, Actor};
+use crate::guardian::same_method;
+use crate::medical_certificate::move_documents;
+use crate::mycompet::registration::CompetitionRegistrations;
+use crate::user::Metadata;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tiered_server::store::Snapshot;
+use tiered_server::user::{IdentificationMethod, User};
+use tracing::{info, warn};
+
+/// Names of accounts with the same date of birth are considered similar up to this many edits.
+const MAX_NAME_DISTANCE: usize = 2;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum DuplicateReason {
+    SameLicenseNumber,
+    SharedEmail,
+    SharedPhone,
+    SimilarName,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub(crate) struct DuplicateAccount {
+    user_id: String,
+    first_name: String,
+    last_name: String,
+    date_of_birth: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    license_number: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Duplicate {
+    accounts: [DuplicateAccount; 2],
+    reasons: Vec<DuplicateReason>,
+}
+
+/// Request body to merge an account into another one.
+#[derive(Debug, Deserialize)]
+pub(crate) struct MergeRequest {
+    /// The account that is kept.
+    keep: String,
+    /// The account merged into the other one.
+    merge: String,
+}
+
+fn metadata(user: &User) -> Metadata {
+    user.metadata
+        .as_ref()
+        .and_then(|it| serde_json::from_value::<Metadata>(it.clone()).ok())
+        .unwrap_or_default()
+}
+
+fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            current[j + 1] = (previous[j] + usize::from(ca != *cb))
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn reasons(
+    a: &User,
+    a_metadata: &Metadata,
+    b: &User,
+    b_metadata: &Metadata,
+) -> Vec<DuplicateReason> {
+    let mut reasons = Vec::new();
+    if a_metadata.license_number.is_some() && a_metadata.license_number == b_metadata.license_number
+    {
+        reasons.push(DuplicateReason::SameLicenseNumber);
+    }
+    let shared = |email: bool| {
+        a.identification.iter().any(|it| {
+            matches!(it, IdentificationMethod::Email(_)) == email
+                && b.identification.iter().any(|other| same_method(it, other))
+        })
+    };
+    if shared(true) {
+        reasons.push(DuplicateReason::SharedEmail);
+    }
+    if shared(false) {
+        reasons.push(DuplicateReason::SharedPhone);
+    }
+    if a.date_of_birth == b.date_of_birth
+        && distance(
+            &format!("{} {}", a.normalized_first_name, a.normalized_last_name),
+            &format!("{} {}", b.normalized_first_name, b.normalized_last_name),
+        ) <= MAX_NAME_DISTANCE
+    {
+        reasons.push(DuplicateReason::SimilarName);
+    }
+    reasons
+}
+
+/// Lists the pairs of accounts that probably belong to the same person.
+///
+/// Sharing an email or a phone alone is not enough, as siblings often share the ones of their
+/// parents.
+pub(crate) fn find_duplicates(users: &[User]) -> Vec<Duplicate> {
+    let users = users
+        .iter()
+        .map(|it| (it, metadata(it)))
+        .filter(|(_, metadata)| metadata.archived.is_none())
+        .collect::<Vec<_>>();
+    let mut duplicates = Vec::new();
+    for (i, (a, a_metadata)) in users.iter().enumerate() {
+        for (b, b_metadata) in users.iter().skip(i + 1) {
+            let reasons = reasons(a, a_metadata, b, b_metadata);
+            let is_duplicate = reasons.iter().any(|it| {
+                matches!(
+                    it,
+                    DuplicateReason::SameLicenseNumber | DuplicateReason::SimilarName
+                )
+            }) || (reasons.len() > 1
+                && a.normalized_first_name == b.normalized_first_name);
+            if !is_duplicate {
+                continue;
+            }
+            let account = |user: &User, metadata: &Metadata| DuplicateAccount {
+                user_id: user.id.to_string(),
+                first_name: user.first_name.clone(),
+                last_name: user.last_name.clone(),
+                date_of_birth: user.date_of_birth,
+                license_number: metadata.license_number,
+            };
+            duplicates.push(Duplicate {
+                accounts: [account(a, a_metadata), account(b, b_metadata)],
+                reasons,
+            });
+        }
+    }
+    duplicates
+}
+
+/// Fills the metadata fields that the kept account is missing with the ones of the merged account.
+fn merge_metadata(keep: Option<Value>, merge: Option<Value>) -> Option<Value> {
+    match (keep, merge) {
+        (Some(Value::Object(mut keep)), Some(Value::Object(merge))) => {
+            for (key, value) in merge {
+                if keep.get(&key).is_none_or(Value::is_null) {
+                    keep.insert(key, value);
+                }
+            }
+            Some(Value::Object(keep))
+        }
+        (None, merge) => merge,
+        (keep, _) => keep,
+    }
+}
+
+/// Moves the entries of the merged account, the ones with keys ending with its id, to the kept
+/// account. The entries the kept account already has are deleted instead.
+async fn move_entries(
+    snapshot: &Snapshot,
+    actor: &Actor,
+    prefix: &str,
+    merge: &str,
+    keep: &str,
+) -> Option<()> {
+    let suffix = format!("_{merge}");
+    for (key, entry) in snapshot.list::<Value>(prefix) {
+        let Some(start) = key.strip_suffix(&suffix) else {
+            continue;
+        };
+        let moved_key = format!("{start}_{keep}");
+        if snapshot.get::<Value>(&moved_key).is_none() {
+            let mut moved = entry.clone();
+            if let Some(user_id) = moved.get_mut("user_id") {
+                *user_id = Value::String(keep.to_string());
+            }
+            Snapshot::set_and_wait_for_update(&moved_key, &moved).await?;
+            record(actor, &moved_key, None, &moved).await;
+        }
+        remove(actor, &key, &entry).await?;
+    }
+    Some(())
+}
+
+/// Replaces the merged id in a list of ids, without duplicating the kept one.
+fn replace_id(ids: &mut Vec<String>, merge: &str, keep: &str) -> bool {
+    if !ids.iter().any(|it| it == merge) {
+        return false;
+    }
+    if ids.iter().any(|it| it == keep) {
+        ids.retain(|it| it != merge);
+    } else {
+        for id in ids.iter_mut() {
+            if id == merge {
+                *id = keep.to_string();
+            }
+        }
+    }
+    true
+}
+
+/// Merges an account into another one: identification methods, missing metadata, reminders,
+/// uploaded documents, guardianships and competition registrations are moved to the kept account,
+/// and the merged account is deleted.
+///
+/// The admin rights of the merged account are not carried over.
+pub(crate) async fn merge_users(
+    snapshot: &Snapshot,
+    request: MergeRequest,
+    admin: &User,
+) -> Option<User> {
+    if request.keep == request.merge {
+        return None;
+    }
+    let keep_key = format!("acc/{}", request.keep);
+    let merge_key = format!("acc/{}", request.merge);
+    let mut keep = snapshot.get::<User>(&keep_key)?;
+    let mut merge = snapshot.get::<User>(&merge_key)?;
+    let kept_metadata = metadata(&keep);
+    let merged_metadata = metadata(&merge);
+    if [&kept_metadata, &merged_metadata]
+        .iter()
+        .any(|it| it.archived.is_some())
+    {
+        return None;
+    }
+    let actor = Actor::admin(admin);
+    let keep_before = keep.clone();
+    let merge_before = merge.clone();
+    for identification in merge.identification.drain(..) {
+        if !keep
+            .identification
+            .iter()
+            .any(|it| same_method(it, &identification))
+        {
+            keep.identification.push(identification);
+        }
+    }
+    keep.metadata = merge_metadata(keep.metadata.take(), merge.metadata.take());
+    let mut guardian_of = kept_metadata.guardian_of.unwrap_or_default();
+    for child in merged_metadata.guardian_of.unwrap_or_default() {
+        if !guardian_of.contains(&child) {
+            guardian_of.push(child);
+        }
+    }
+    if !guardian_of.is_empty() {
+        if let Some(Value::Object(metadata)) = keep.metadata.as_mut() {
+            metadata.insert(
+                "guardian_of".to_string(),
+                serde_json::to_value(guardian_of).ok()?,
+            );
+        }
+    }
+    // the merged account is deleted first and restored if the kept account can't be saved, so
+    // that its identification methods are never on both accounts
+    remove(&actor, &merge_key, &merge_before).await?;
+    if Snapshot::set_and_wait_for_update(&keep_key, &keep)
+        .await
+        .is_none()
+    {
+        warn!(
+            "failed to save account {}, restoring {}",
+            request.keep, request.merge
+        );
+        if Snapshot::set_and_wait_for_update(&merge_key, &merge_before)
+            .await
+            .is_some()
+        {
+            record(&actor, &merge_key, None, &merge_before).await;
+        }
+        return None;
+    }
+    record(&actor, &keep_key, Some(&keep_before), &keep).await;
+    move_documents(snapshot, &request.merge, &request.keep);
+    for prefix in ["rnw/", "mcr/", "mcd/", "ago/", "cfl/"] {
+        move_entries(snapshot, &actor, prefix, &request.merge, &request.keep).await?;
+    }
+    for (key, mut user) in snapshot.list::<User>("acc/") {
+        if key == keep_key || key == merge_key {
+            continue;
+        }
+        let mut user_metadata = metadata(&user);
+        let Some(children) = user_metadata.guardian_of.as_mut() else {
+            continue;
+        };
+        if !replace_id(children, &request.merge, &request.keep) {
+            continue;
+        }
+        let before = user.clone();
+        user.metadata = Some(serde_json::to_value(user_metadata).ok()?);
+        Snapshot::set_and_wait_for_update(&key, &user).await?;
+        record(&actor, &key, Some(&before), &user).await;
+    }
+    for (key, mut registrations) in snapshot.list::<CompetitionRegistrations>("crg/") {
+        let before = registrations.clone();
+        let is_registered = |user_id: &str| {
+            registrations
+                .athletes
+                .iter()
+                .any(|it| it.user_id == user_id)
+        };
+        let merged_is_registered = is_registered(&request.merge);
+        let kept_is_registered = is_registered(&request.keep);
+        if merged_is_registered {
+            if kept_is_registered {
+                registrations
+                    .athletes
+                    .retain(|it| it.user_id != request.merge);
+            } else {
+                for athlete in registrations.athletes.iter_mut() {
+                    if athlete.user_id == request.merge {
+                        athlete.user_id = request.keep.clone();
+                    }
+                }
+            }
+        }
+        let is_coach = replace_id(&mut registrations.coaches, &request.merge, &request.keep);
+        if !merged_is_registered && !is_coach {
+            continue;
+        }
+        Snapshot::set_and_wait_for_update(&key, &registrations).await?;
+        record(&actor, &key, Some(&before), &registrations).await;
+    }
+    info!(
+        "merged account {} into {} ({} {})",
+        request.merge, request.keep, keep.first_name, keep.last_name
+    );
+    Some(keep)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user::{test_email, test_user};
+    use serde_json::json;
+
+    fn user(first_name: &str, last_name: &str, email: &str, license_number: Option<u32>) -> User {
+        let mut user = test_user(
+            first_name,
+            last_name,
+            20100101,
+            Metadata {
+                license_number,
+                ..Default::default()
+            },
+        );
+        user.identification.push(test_email(email));
+        user
+    }
+
+    #[test]
+    fn test_duplicates() {
+        assert_eq!(0, distance("martin", "martin"));
+        assert_eq!(1, distance("martin", "martins"));
+        assert_eq!(2, distance("lea martin", "leo martn"));
+        let users = vec![
+            user("Léa", "Martin", "parent@example.com", Some(123)),
+            user("Lea", "Martins", "lea@example.com", None),
+            // sibling, same parent email
+            user("Hugo", "Martin", "parent@example.com", Some(456)),
+            user("Hugo", "Durand", "hugo@example.com", Some(456)),
+        ];
+        let duplicates = find_duplicates(&users);
+        assert_eq!(2, duplicates.len());
+        assert_eq!(vec![DuplicateReason::SimilarName], duplicates[0].reasons);
+        assert_eq!("Lea", duplicates[0].accounts[1].first_name);
+        assert_eq!(
+            vec![DuplicateReason::SameLicenseNumber],
+            duplicates[1].reasons
+        );
+        assert_eq!("Durand", duplicates[1].accounts[1].last_name);
+        let mut ids = vec!["1".to_string(), "2".to_string()];
+        assert!(!replace_id(&mut ids, "3", "1"));
+        assert!(replace_id(&mut ids, "2", "3"));
+        assert_eq!(vec!["1", "3"], ids);
+        assert!(replace_id(&mut ids, "3", "1"));
+        assert_eq!(vec!["1"], ids);
+        assert_eq!(
+            Some(json!({"license_number": 123, "gender": "female", "myffme_user_id": "abc"})),
+            merge_metadata(
+                Some(json!({"license_number": 123, "gender": null})),
+                Some(json!({"license_number": 456, "gender": "female", "myffme_user_id": "abc"})),
+            )
+        );
+    }
+}
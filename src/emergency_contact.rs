@@ -8,10 +8,18 @@ pub enum Relationship {
     Father,
     Mother,
     GrandParent,
+    LegalGuardian,
     #[default]
     Other,
 }
 
+impl Relationship {
+    /// Whether the contact has parental authority over the member.
+    pub fn is_guardian(self) -> bool {
+        matches!(self, Self::Father | Self::Mother | Self::LegalGuardian)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct EmergencyContact {
     pub id: Option<u32>,
@@ -0,0 +1,587 @@
+use crate::audit::{record, Actor};
+use crate::category::Category;
+use crate::emergency_contact::EmergencyContact;
+use crate::medical_certificate::MedicalCertificate;
+use crate::mycompet::calendar::today;
+use crate::myffme::address::Address;
+use crate::myffme::LicenseType;
+use crate::notification::notify_user;
+use crate::season::current_season;
+use crate::user::Metadata;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use tiered_server::store::Snapshot;
+use tiered_server::user::{IdentificationMethod, User};
+use tracing::{info, warn};
+
+/// Time given to members who turn 18 before their guardians lose access to their account.
+const AGE_OUT_NOTICE_SECONDS: u32 = 30 * 86_400;
+
+/// Members under 18 during the season.
+pub(crate) fn is_minor(date_of_birth: u32, season: u16) -> bool {
+    season.saturating_sub((date_of_birth / 1_00_00) as u16) < 18
+}
+
+/// Members are charged the child licence until the season they turn 16.
+pub(crate) fn expected_license_type(date_of_birth: u32, season: u16) -> LicenseType {
+    if Category::from_dob(date_of_birth, season) < Category::U18 {
+        LicenseType::Child
+    } else {
+        LicenseType::Adult
+    }
+}
+
+/// A member that turned 18 this season, their guardians keep access until the end of the notice.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub(crate) struct AgeOut {
+    user_id: String,
+    season: u16,
+    /// When the member and their guardians were told.
+    notified: u32,
+    /// When the guardians lost access.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    completed: Option<u32>,
+}
+
+impl AgeOut {
+    fn key(season: u16, user_id: &str) -> String {
+        format!("ago/{season}_{user_id}")
+    }
+
+    /// The notice only starts once the member or one of their guardians was reached.
+    fn after_notice(user_id: String, season: u16, now: u32, reached: bool) -> Option<Self> {
+        reached.then_some(Self {
+            user_id,
+            season,
+            notified: now,
+            completed: None,
+        })
+    }
+
+    fn is_over(&self, now: u32) -> bool {
+        now >= self.notified + AGE_OUT_NOTICE_SECONDS
+    }
+}
+
+fn turns_adult(date_of_birth: u32, season: u16) -> bool {
+    is_minor(date_of_birth, season - 1) && !is_minor(date_of_birth, season)
+}
+
+/// The members that turned 18 this season, by user id.
+pub(crate) fn age_outs(snapshot: &Snapshot, season: u16) -> BTreeMap<String, AgeOut> {
+    snapshot
+        .list::<AgeOut>(&format!("ago/{season}_"))
+        .map(|(_, it)| (it.user_id.clone(), it))
+        .collect()
+}
+
+/// Whether guardians act for the member: minors, and members that turned 18 this season until
+/// the end of the notice.
+pub(crate) fn is_under_guardianship(
+    user: &User,
+    season: u16,
+    age_outs: &BTreeMap<String, AgeOut>,
+    now: u32,
+) -> bool {
+    is_minor(user.date_of_birth, season)
+        || (turns_adult(user.date_of_birth, season)
+            && age_outs
+                .get(&user.id.to_string())
+                .is_none_or(|it| !it.is_over(now)))
+}
+
+/// Whether the member turned 18 this season and the notice given to their guardians ended.
+pub(crate) fn has_aged_out(
+    user: &User,
+    season: u16,
+    age_outs: &BTreeMap<String, AgeOut>,
+    now: u32,
+) -> bool {
+    turns_adult(user.date_of_birth, season)
+        && age_outs
+            .get(&user.id.to_string())
+            .is_some_and(|it| it.is_over(now))
+}
+
+pub(crate) fn same_method(a: &IdentificationMethod, b: &IdentificationMethod) -> bool {
+    match (a, b) {
+        (IdentificationMethod::Email(a), IdentificationMethod::Email(b)) => {
+            a.normalized_address == b.normalized_address
+        }
+        (IdentificationMethod::Sms(a), IdentificationMethod::Sms(b)) => {
+            a.normalized_number == b.normalized_number
+        }
+        _ => false,
+    }
+}
+
+fn is_identified_by(user: &User, contact: &EmergencyContact) -> bool {
+    user.identification.iter().any(|method| {
+        contact
+            .identification
+            .iter()
+            .any(|it| same_method(method, it))
+    })
+}
+
+fn metadata(user: &User) -> Metadata {
+    user.metadata
+        .as_ref()
+        .and_then(|it| serde_json::from_value::<Metadata>(it.clone()).ok())
+        .unwrap_or_default()
+}
+
+/// Computes the children of every guardian account from the emergency contacts of the minors.
+///
+/// Members who turned 18 this season keep their guardians until the end of the notice.
+/// Only parents and legal guardians are guardians, not every emergency contact. Contacts are
+/// matched with the existing accounts (other than members under guardianship) by email or mobile
+/// number, new accounts are returned for the contacts that don't have one yet.
+fn guardianships(
+    users: &[User],
+    season: u16,
+    age_outs: &BTreeMap<String, AgeOut>,
+    now: u32,
+) -> (BTreeMap<String, BTreeSet<String>>, Vec<User>) {
+    let mut children = BTreeMap::<String, BTreeSet<String>>::new();
+    let mut new_guardians = Vec::<User>::new();
+    for child in users
+        .iter()
+        .filter(|it| is_under_guardianship(it, season, age_outs, now))
+    {
+        let Some(contacts) = metadata(child).emergency_contacts else {
+            continue;
+        };
+        for contact in contacts
+            .iter()
+            .filter(|it| it.relationship.is_guardian() && !it.identification.is_empty())
+        {
+            let guardian_id = if let Some(guardian) = users.iter().find(|it| {
+                !is_under_guardianship(it, season, age_outs, now) && is_identified_by(it, contact)
+            }) {
+                guardian.id.to_string()
+            } else if let Some(guardian) = new_guardians
+                .iter()
+                .find(|it| is_identified_by(it, contact))
+            {
+                guardian.id.to_string()
+            } else {
+                let guardian = User {
+                    id: User::new_id(0),
+                    identification: contact.identification.clone(),
+                    last_name: contact.last_name.clone(),
+                    normalized_last_name: contact.normalized_last_name.clone(),
+                    first_name: contact.first_name.clone(),
+                    normalized_first_name: contact.normalized_first_name.clone(),
+                    date_of_birth: 0,
+                    admin: false,
+                    metadata: None,
+                };
+                let id = guardian.id.to_string();
+                new_guardians.push(guardian);
+                id
+            };
+            children
+                .entry(guardian_id)
+                .or_default()
+                .insert(child.id.to_string());
+        }
+    }
+    (children, new_guardians)
+}
+
+/// Links the guardian accounts to the minors that list them as emergency contacts.
+///
+/// The users are updated as they are saved, and the new guardian accounts are added to them.
+/// Returns the number of accounts that were created or updated.
+pub(crate) async fn update_guardians(
+    users: &mut Vec<User>,
+    season: u16,
+    age_outs: &BTreeMap<String, AgeOut>,
+    now: u32,
+) -> Result<usize, String> {
+    let (mut children, new_guardians) = guardianships(users, season, age_outs, now);
+    let new_guardian_ids = new_guardians
+        .iter()
+        .map(|it| it.id.to_string())
+        .collect::<BTreeSet<_>>();
+    users.extend(new_guardians);
+    let mut updated = 0;
+    for user in users.iter_mut() {
+        let metadata = metadata(user);
+        let guardian_of = children
+            .remove(&user.id.to_string())
+            .map(|it| it.into_iter().collect::<Vec<_>>());
+        if metadata.guardian_of == guardian_of {
+            continue;
+        }
+        info!(
+            "updating the children of {} {}",
+            user.first_name, user.last_name
+        );
+        let before = (!new_guardian_ids.contains(&user.id.to_string())).then(|| user.clone());
+        user.metadata = Some(
+            serde_json::to_value(Metadata {
+                guardian_of,
+                ..metadata
+            })
+            .map_err(|_| "failed to serialize metadata".to_string())?,
+        );
+        let key = format!("acc/{}", user.id);
+        Snapshot::set_and_return_before_update(&key, &*user)
+            .await
+            .ok_or("failed to update guardian".to_string())?;
+        record(&Actor::Sync, &key, before.as_ref(), &*user).await;
+        updated += 1;
+    }
+    Ok(updated)
+}
+
+/// Removes the emails and mobile numbers of the emergency contacts from the identification of
+/// the member, unless MyFFME lists them as the member's own (normalized).
+///
+/// Returns whether any was removed.
+pub(crate) fn remove_guardian_identification(
+    user: &mut User,
+    own_identification: &[String],
+) -> bool {
+    let Some(emergency_contacts) = metadata(user).emergency_contacts else {
+        return false;
+    };
+    let count = user.identification.len();
+    user.identification.retain(|identification| {
+        let normalized = match identification {
+            IdentificationMethod::Email(it) => &it.normalized_address,
+            IdentificationMethod::Sms(it) => &it.normalized_number,
+            _ => return true,
+        };
+        own_identification.contains(normalized)
+            || !emergency_contacts
+                .iter()
+                .any(|it| it.identification.contains(identification))
+    });
+    user.identification.len() != count
+}
+
+/// The guardian accounts of a member.
+pub(crate) fn guardians(snapshot: &Snapshot, user_id: &str) -> Vec<User> {
+    snapshot
+        .list::<User>("acc/")
+        .map(|(_, it)| it)
+        .filter(|it| {
+            metadata(it)
+                .guardian_of
+                .is_some_and(|children| children.iter().any(|it| it == user_id))
+        })
+        .collect()
+}
+
+/// The member a request is about: the user themselves, or one of their children with
+/// `?child={user_id}`.
+///
+/// Returns None if the user is not a guardian of the child.
+pub(crate) fn acting_for(snapshot: &Snapshot, user: User, query: Option<&str>) -> Option<User> {
+    let Some(child_id) = query
+        .into_iter()
+        .flat_map(|it| it.split('&'))
+        .find_map(|it| it.strip_prefix("child="))
+    else {
+        return Some(user);
+    };
+    if !metadata(&user)
+        .guardian_of
+        .is_some_and(|children| children.iter().any(|it| it == child_id))
+    {
+        return None;
+    }
+    snapshot.get::<User>(&format!("acc/{child_id}"))
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Child {
+    user_id: String,
+    first_name: String,
+    last_name: String,
+    date_of_birth: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latest_license_season: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    medical_certificate: Option<MedicalCertificate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address: Option<Address>,
+}
+
+/// Lists the children of a guardian.
+pub(crate) fn children(snapshot: &Snapshot, guardian: &User) -> Vec<Child> {
+    metadata(guardian)
+        .guardian_of
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|id| snapshot.get::<User>(&format!("acc/{id}")))
+        .map(|child| {
+            let metadata = metadata(&child);
+            Child {
+                user_id: child.id.to_string(),
+                first_name: child.first_name,
+                last_name: child.last_name,
+                date_of_birth: child.date_of_birth,
+                latest_license_season: metadata.latest_license_season,
+                medical_certificate: metadata.medical_certificate,
+                address: metadata.address,
+            }
+        })
+        .collect()
+}
+
+fn format_date(timestamp: u32) -> String {
+    let date = today(timestamp);
+    format!(
+        "{:02}/{:02}/{}",
+        date % 1_00,
+        date / 1_00 % 1_00,
+        date / 1_00_00
+    )
+}
+
+fn notice_texts(user: &User, end: &str, guardian: bool) -> (String, String, String) {
+    let subject = format!("Majorité de {}", user.first_name);
+    if guardian {
+        (
+            subject,
+            format!(
+                "Bonjour,\n\n\
+                {} a 18 ans cette saison.\n\
+                À partir du {end}, vous n'aurez plus accès à son compte \
+                et vos coordonnées en seront retirées.\n",
+                user.first_name
+            ),
+            format!(
+                "{} a 18 ans cette saison, vous n'aurez plus accès à son compte à partir du {end}.",
+                user.first_name
+            ),
+        )
+    } else {
+        (
+            subject,
+            format!(
+                "Bonjour {},\n\n\
+                Vous avez 18 ans cette saison.\n\
+                À partir du {end}, vos parents n'auront plus accès à votre compte \
+                et leurs adresses email et numéros de téléphone en seront retirés.\n\
+                Pensez à ajouter votre propre adresse email à votre compte.\n",
+                user.first_name
+            ),
+            format!(
+                "Vous avez 18 ans cette saison, pensez à ajouter votre propre adresse email \
+                à votre compte avant le {end}."
+            ),
+        )
+    }
+}
+
+/// Tells the members that turned 18 this season and their guardians that the guardians will
+/// lose access to the account, and reminds them to add their own email once they did.
+pub async fn run_age_out(snapshot: &Arc<Snapshot>, now: u32) -> Option<()> {
+    let season = current_season(None);
+    let age_outs = age_outs(snapshot, season);
+    let mut notified = 0;
+    for (_, user) in snapshot.list::<User>("acc/") {
+        if !turns_adult(user.date_of_birth, season) {
+            continue;
+        }
+        let user_id = user.id.to_string();
+        let key = AgeOut::key(season, &user_id);
+        match age_outs.get(&user_id) {
+            None => {
+                let guardians = guardians(snapshot, &user_id);
+                if guardians.is_empty() && metadata(&user).emergency_contacts.is_none() {
+                    continue;
+                }
+                let end = format_date(now + AGE_OUT_NOTICE_SECONDS);
+                let (subject, email, sms) = notice_texts(&user, &end, false);
+                let mut reached = notify_user(&user, &subject, &email, &sms).await;
+                let (subject, email, sms) = notice_texts(&user, &end, true);
+                for guardian in guardians.iter() {
+                    reached |= notify_user(guardian, &subject, &email, &sms).await;
+                }
+                let Some(age_out) = AgeOut::after_notice(user_id, season, now, reached) else {
+                    // tried again on the next run
+                    warn!(
+                        "failed to notify {} {} about their majority",
+                        user.first_name, user.last_name
+                    );
+                    continue;
+                };
+                Snapshot::set_and_wait_for_update(&key, &age_out).await?;
+                record(&Actor::Job, &key, None, &age_out).await;
+                notified += 1;
+            }
+            Some(age_out) if age_out.completed.is_none() && age_out.is_over(now) => {
+                if user.email().is_none() {
+                    let _ = notify_user(
+                        &user,
+                        "Ajoutez votre adresse email",
+                        &format!(
+                            "Bonjour {},\n\n\
+                            Vos parents n'ont plus accès à votre compte.\n\
+                            Pensez à y ajouter votre propre adresse email.\n",
+                            user.first_name
+                        ),
+                        "Vos parents n'ont plus accès à votre compte, \
+                        pensez à y ajouter votre propre adresse email.",
+                    )
+                    .await;
+                }
+                let completed = AgeOut {
+                    completed: Some(now),
+                    ..age_out.clone()
+                };
+                Snapshot::set_and_wait_for_update(&key, &completed).await?;
+                record(&Actor::Job, &key, Some(age_out), &completed).await;
+            }
+            Some(_) => {}
+        }
+    }
+    info!("age out: {notified} member(s) notified");
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emergency_contact::Relationship;
+    use crate::user::{test_email, test_user};
+
+    fn user(first_name: &str, date_of_birth: u32, email: &str, metadata: Metadata) -> User {
+        let mut user = test_user(first_name, "Martin", date_of_birth, metadata);
+        if !email.is_empty() {
+            user.identification.push(test_email(email));
+        }
+        user
+    }
+
+    fn contact(first_name: &str, email: &str, relationship: Relationship) -> EmergencyContact {
+        EmergencyContact {
+            id: None,
+            relationship,
+            last_name: "Martin".to_string(),
+            normalized_last_name: "martin".to_string(),
+            first_name: first_name.to_string(),
+            normalized_first_name: first_name.to_lowercase(),
+            identification: vec![test_email(email)],
+        }
+    }
+
+    #[test]
+    fn test_guardianships() {
+        assert!(is_minor(20100101, 2026));
+        assert!(!is_minor(20080101, 2026));
+        assert!(!is_minor(0, 2026));
+        let children_metadata = || Metadata {
+            emergency_contacts: Some(vec![
+                contact("Alice", "alice@example.com", Relationship::Mother),
+                contact("Bob", "bob@example.com", Relationship::Father),
+                contact("Franck", "franck@example.com", Relationship::GrandParent),
+            ]),
+            ..Default::default()
+        };
+        let users = vec![
+            user("Alice", 19800101, "alice@example.com", Metadata::default()),
+            user("Carole", 20120101, "", children_metadata()),
+            // still has the email of a parent from before guardian accounts
+            user("David", 20140101, "bob@example.com", children_metadata()),
+        ];
+        let (children, new_guardians) = guardianships(&users, 2026, &BTreeMap::new(), 0);
+        assert_eq!(1, new_guardians.len());
+        assert_eq!("Bob", new_guardians[0].first_name);
+        assert_eq!(0, new_guardians[0].date_of_birth);
+        let expected = [users[1].id.to_string(), users[2].id.to_string()]
+            .into_iter()
+            .collect::<BTreeSet<_>>();
+        assert_eq!(Some(&expected), children.get(&users[0].id.to_string()));
+        assert_eq!(
+            Some(&expected),
+            children.get(&new_guardians[0].id.to_string())
+        );
+        assert_eq!(2, children.len());
+    }
+
+    #[test]
+    fn test_age_out() {
+        assert_eq!(LicenseType::Child, expected_license_type(20110101, 2026));
+        assert_eq!(LicenseType::Adult, expected_license_type(20090101, 2026));
+        assert_eq!(LicenseType::Adult, expected_license_type(20080101, 2026));
+        assert!(turns_adult(20081231, 2026));
+        assert!(!turns_adult(20071231, 2026));
+        let adult = user("Emma", 20080601, "", Metadata::default());
+        let mut age_outs = BTreeMap::new();
+        assert!(is_under_guardianship(
+            &adult,
+            2026,
+            &age_outs,
+            1_760_000_000
+        ));
+        age_outs.insert(
+            adult.id.to_string(),
+            AgeOut {
+                user_id: adult.id.to_string(),
+                season: 2026,
+                notified: 1_760_000_000,
+                completed: None,
+            },
+        );
+        assert!(is_under_guardianship(
+            &adult,
+            2026,
+            &age_outs,
+            1_760_000_000 + 86_400
+        ));
+        assert!(!is_under_guardianship(
+            &adult,
+            2026,
+            &age_outs,
+            1_760_000_000 + AGE_OUT_NOTICE_SECONDS
+        ));
+        assert!(has_aged_out(
+            &adult,
+            2026,
+            &age_outs,
+            1_760_000_000 + AGE_OUT_NOTICE_SECONDS
+        ));
+        assert!(!has_aged_out(&adult, 2026, &age_outs, 1_760_000_000));
+        assert!(!is_under_guardianship(&adult, 2027, &BTreeMap::new(), 0));
+        assert!(!has_aged_out(&adult, 2027, &age_outs, 1_760_000_000));
+        // adults that never were under guardianship don't age out
+        let parent = user("Alice", 19800101, "alice@example.com", Metadata::default());
+        assert!(!is_under_guardianship(&parent, 2026, &age_outs, 0));
+        assert!(!has_aged_out(&parent, 2026, &age_outs, u32::MAX / 2));
+        assert_eq!("ago/2026_1", AgeOut::key(2026, "1"));
+    }
+
+    #[test]
+    fn test_age_out_notice_not_sent() {
+        let adult = user("Emma", 20080601, "", Metadata::default());
+        let user_id = adult.id.to_string();
+        // nobody was reached, the notice doesn't start and the guardians keep access
+        assert_eq!(
+            None,
+            AgeOut::after_notice(user_id.clone(), 2026, 1_760_000_000, false)
+        );
+        assert!(is_under_guardianship(
+            &adult,
+            2026,
+            &BTreeMap::new(),
+            1_760_000_000 + 2 * AGE_OUT_NOTICE_SECONDS
+        ));
+        let age_out = AgeOut::after_notice(user_id.clone(), 2026, 1_760_000_000, true).unwrap();
+        assert_eq!(1_760_000_000, age_out.notified);
+        let age_outs = [(user_id, age_out)].into_iter().collect::<BTreeMap<_, _>>();
+        assert!(!is_under_guardianship(
+            &adult,
+            2026,
+            &age_outs,
+            1_760_000_000 + AGE_OUT_NOTICE_SECONDS
+        ));
+    }
+}
@@ -1,15 +1,18 @@
 #![allow(unused_imports, dead_code)]
 
 use crate::http_client::json_client;
+use crate::metrics::{timed, Upstream};
 use crate::myffme::address::{user_address, Address};
 use crate::order::{Order, Priced};
 use crate::season::is_during_discount_period;
+use crate::status::FAILURES;
 use crate::user::Metadata;
 use hyper::header::{HeaderValue, AUTHORIZATION};
 use pinboard::Pinboard;
 use serde::Deserialize;
 use serde_json::json;
 use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
 use std::sync::LazyLock;
 use tiered_server::env::{secret_value, ConfigurationKey};
 use tiered_server::server::DOMAIN_APEX;
@@ -86,11 +89,14 @@ pub async fn update_hello_asso_bearer_token(timestamp: u32) -> Option<String> {
         params.insert("client_secret", *CLIENT_SECRET);
     }
     let client = json_client();
-    match client
-        .post(format!("{}/token", *OAUTH_ENDPOINT))
-        .form(&params)
-        .send()
-        .await
+    match timed(
+        Upstream::HelloAsso,
+        client
+            .post(format!("{}/token", *OAUTH_ENDPOINT))
+            .form(&params)
+            .send(),
+    )
+    .await
     {
         Ok(response) => match response.json::<Token>().await {
             Ok(token) => {
@@ -108,11 +114,17 @@ pub async fn update_hello_asso_bearer_token(timestamp: u32) -> Option<String> {
             }
             Err(err) => {
                 debug!("failed to parse oauth2 response:\n{err:?}");
+                FAILURES
+                    .hello_asso_authorization
+                    .fetch_add(1, Ordering::Relaxed);
                 None
             }
         },
         Err(err) => {
             debug!("failed to get oauth2 response:\n{err:?}");
+            FAILURES
+                .hello_asso_authorization
+                .fetch_add(1, Ordering::Relaxed);
             None
         }
     }
@@ -140,7 +152,7 @@ pub async fn init_transaction(snapshot: &Snapshot, user: &User, order: &Order) -
         Address::default()
     };
     let dob_str = user.date_of_birth.to_string();
-    match client
+    let request = client
         .post(format!(
             "{}/organizations/{}/checkout-intents",
             *API_ENDPOINT, *ORG_SLUG
@@ -169,13 +181,9 @@ pub async fn init_transaction(snapshot: &Snapshot, user: &User, order: &Order) -
                 "country": "fra",
                 "dateOfBirth": format!("{}-{}-{}", dob_str.get(..2).unwrap(), dob_str.get(2..4).unwrap(), dob_str.get(4..).unwrap()),
             }
-        }))
-        .send()
-        .await
-    {
-        Ok(response) => {
-            response.json().await.ok()?
-        }
+        }));
+    match timed(Upstream::HelloAsso, request.send()).await {
+        Ok(response) => response.json().await.ok()?,
         Err(err) => {
             eprintln!("err: {err:?}");
             None
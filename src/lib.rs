@@ -1,15 +1,28 @@
 pub mod address;
 pub mod api;
+mod audit;
 mod category;
 mod chrome;
+mod conflict;
+mod duplicates;
 mod emergency_contact;
+mod guardian;
 mod hello_asso;
 mod http_client;
+mod license_history;
+mod lifecycle;
+mod medical_certificate;
+mod metrics;
 pub mod mycompet;
 pub mod myffme;
+mod notification;
 mod order;
+mod price_history;
+mod privacy;
+mod renewal;
+pub mod scheduler;
 mod season;
-pub mod update;
+mod status;
 pub mod user;
 
 #[cfg(test)]
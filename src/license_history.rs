@@ -0,0 +1,168 @@
+use crate::myffme::LicenseRecord;
+use crate::user::Metadata;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use tiered_server::user::User;
+
+#[derive(Debug, Serialize, PartialEq)]
+pub(crate) struct SeasonRetention {
+    season: u16,
+    /// Members of the previous season.
+    previous_members: u32,
+    /// Members of the previous season that renewed their licence with us.
+    renewed: u32,
+    /// Members of the previous season that are not licensed anywhere this season (yet).
+    lapsed: u32,
+    /// Members of the previous season that licensed with another structure.
+    licensed_elsewhere: Vec<FormerMember>,
+    /// Members that were not licensed with us the previous season.
+    new_members: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retention_rate: Option<f32>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub(crate) struct FormerMember {
+    first_name: String,
+    last_name: String,
+    structure_name: String,
+}
+
+/// Computes, for each season (latest first), how many members of the previous season
+/// renewed, left for another structure or stopped.
+pub(crate) fn retention(
+    users: impl Iterator<Item = User>,
+    structure_id: u32,
+) -> Vec<SeasonRetention> {
+    let members = users
+        .filter_map(|user| {
+            let history = user
+                .metadata
+                .and_then(|it| serde_json::from_value::<Metadata>(it).ok())
+                .and_then(|it| it.license_history)?;
+            let seasons = history
+                .into_iter()
+                .map(|it| (it.season, it))
+                .collect::<BTreeMap<u16, LicenseRecord>>();
+            Some((user.first_name, user.last_name, seasons))
+        })
+        .collect::<Vec<_>>();
+    let seasons = members
+        .iter()
+        .flat_map(|(_, _, seasons)| {
+            seasons
+                .values()
+                .filter(|it| it.structure_id == structure_id)
+                .map(|it| it.season)
+        })
+        .collect::<BTreeSet<_>>();
+    seasons
+        .iter()
+        .rev()
+        .filter(|&&season| seasons.contains(&(season - 1)))
+        .map(|&season| {
+            let mut previous_members = 0;
+            let mut renewed = 0;
+            let mut lapsed = 0;
+            let mut licensed_elsewhere = Vec::new();
+            let mut new_members = 0;
+            for (first_name, last_name, seasons) in members.iter() {
+                let was_member = seasons
+                    .get(&(season - 1))
+                    .is_some_and(|it| it.structure_id == structure_id);
+                let current = seasons.get(&season);
+                if was_member {
+                    previous_members += 1;
+                    match current {
+                        Some(it) if it.structure_id == structure_id => renewed += 1,
+                        Some(it) => licensed_elsewhere.push(FormerMember {
+                            first_name: first_name.clone(),
+                            last_name: last_name.clone(),
+                            structure_name: it.structure_name.clone(),
+                        }),
+                        None => lapsed += 1,
+                    }
+                } else if current.is_some_and(|it| it.structure_id == structure_id) {
+                    new_members += 1;
+                }
+            }
+            SeasonRetention {
+                season,
+                previous_members,
+                renewed,
+                lapsed,
+                licensed_elsewhere,
+                new_members,
+                retention_rate: if previous_members == 0 {
+                    None
+                } else {
+                    Some(renewed as f32 / previous_members as f32)
+                },
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::myffme::{LicenseType, MedicalCertificateStatus};
+    use crate::user::test_user;
+
+    fn user(first_name: &str, licenses: &[(u16, u32)]) -> User {
+        let history = licenses
+            .iter()
+            .map(|&(season, structure_id)| LicenseRecord {
+                id: format!("{first_name}{season}"),
+                season,
+                structure_id,
+                structure_name: format!("Club {structure_id}"),
+                license_type: LicenseType::Adult,
+                insurance_level: None,
+                insurance_options: vec![],
+                medical_certificate_status: MedicalCertificateStatus::Recreational,
+            })
+            .collect();
+        test_user(
+            first_name,
+            "Martin",
+            19900101,
+            Metadata {
+                license_history: Some(history),
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_retention() {
+        let users = vec![
+            user("Alice", &[(2023, 1), (2024, 1), (2025, 1)]),
+            user("Bob", &[(2024, 1), (2025, 2)]),
+            user("Carole", &[(2024, 1)]),
+            user("David", &[(2023, 2), (2025, 1)]),
+        ];
+        let seasons = retention(users.into_iter(), 1);
+        assert_eq!(2, seasons.len());
+        let latest = &seasons[0];
+        assert_eq!(2025, latest.season);
+        assert_eq!(3, latest.previous_members);
+        assert_eq!(1, latest.renewed);
+        assert_eq!(1, latest.lapsed);
+        assert_eq!(
+            vec![FormerMember {
+                first_name: "Bob".to_string(),
+                last_name: "Martin".to_string(),
+                structure_name: "Club 2".to_string(),
+            }],
+            latest.licensed_elsewhere
+        );
+        assert_eq!(1, latest.new_members);
+        assert_eq!(Some(1.0 / 3.0), latest.retention_rate);
+        let previous = &seasons[1];
+        assert_eq!(2024, previous.season);
+        assert_eq!(1, previous.previous_members);
+        assert_eq!(1, previous.renewed);
+        assert_eq!(2, previous.new_members);
+    }
+}
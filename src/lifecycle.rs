@@ -0,0 +1,384 @@
+use crate::audit::{erase, record, record_anonymisation, redact_history, redact_member, Actor};
+use crate::conflict::anonymise_conflicts;
+use crate::medical_certificate::remove_documents;
+use crate::mycompet::registration::CompetitionRegistrations;
+use crate::myffme::LicenseRecord;
+use crate::privacy::forget_myffme_user;
+use crate::season::current_season;
+use crate::user::Metadata;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::{Arc, LazyLock};
+use tiered_server::env::{secret_value, ConfigurationKey};
+use tiered_server::store::Snapshot;
+use tiered_server::user::User;
+use tracing::{info, warn};
+
+/// Members who didn't renew are lapsed for this many seasons, then alumni.
+const LAPSED_SEASONS: u16 = 2;
+
+const ARCHIVE_AFTER_SEASONS_KEY: ConfigurationKey = ConfigurationKey::Other {
+    variable_name: "ARCHIVE_AFTER_SEASONS",
+};
+
+/// Alumni accounts are anonymised this many seasons after their latest licence, only when it is
+/// configured.
+static ARCHIVE_AFTER_SEASONS: LazyLock<Option<u16>> = LazyLock::new(|| {
+    secret_value(ARCHIVE_AFTER_SEASONS_KEY).and_then(|it| {
+        it.parse::<u16>()
+            .ok()
+            .filter(|it| *it > LAPSED_SEASONS)
+            .or_else(|| {
+                warn!("invalid number of seasons before archiving: {it}");
+                None
+            })
+    })
+});
+
+const RENEWAL_DEADLINE_KEY: ConfigurationKey = ConfigurationKey::Other {
+    variable_name: "RENEWAL_DEADLINE",
+};
+
+/// Last day (mmdd), in the first year of the season, for the members of the previous season to
+/// renew before they are lapsed.
+static RENEWAL_DEADLINE: LazyLock<u32> = LazyLock::new(|| {
+    secret_value(RENEWAL_DEADLINE_KEY)
+        .and_then(|it| {
+            it.parse::<u32>()
+                .ok()
+                .filter(|it| (8_01..=12_31).contains(it))
+                .or_else(|| {
+                    warn!("invalid renewal deadline: {it}");
+                    None
+                })
+        })
+        .unwrap_or(10_31)
+});
+
+/// Where a member stands with the club, computed from their licences during the member sync.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Lifecycle {
+    /// Licensed with the club this season, or the previous one until the renewal deadline.
+    Active,
+    /// Licensed with the club a recent season, but not this one (yet).
+    Lapsed,
+    /// Their latest licence, from a recent season, is with another structure.
+    Transferred,
+    /// Not licensed for several seasons.
+    Alumni,
+}
+
+impl Lifecycle {
+    /// Lapsed, transferred and alumni members keep access to their history, their insurance and
+    /// the renewal, but not to the features that require a licence with the club.
+    pub(crate) fn is_restricted(metadata: &Metadata) -> bool {
+        metadata.lifecycle.is_some_and(|it| it != Lifecycle::Active)
+    }
+}
+
+/// Computes the state of a member from the history of their licences, on the given day
+/// (yyyymmdd).
+///
+/// Members of the previous season are still active at the start of the season, so that nothing
+/// prevents them from renewing until the renewal deadline.
+pub(crate) fn lifecycle(
+    history: &[LicenseRecord],
+    season: u16,
+    structure_id: u32,
+    today: u32,
+) -> Option<Lifecycle> {
+    let latest = history.iter().max_by_key(|it| it.season)?;
+    let seasons_since = season.saturating_sub(latest.season);
+    let can_renew =
+        seasons_since == 1 && today <= (season as u32 - 1) * 1_00_00 + *RENEWAL_DEADLINE;
+    Some(if seasons_since > LAPSED_SEASONS {
+        Lifecycle::Alumni
+    } else if latest.structure_id != structure_id {
+        Lifecycle::Transferred
+    } else if seasons_since == 0 || can_renew {
+        Lifecycle::Active
+    } else {
+        Lifecycle::Lapsed
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct MemberLifecycle {
+    user_id: String,
+    first_name: String,
+    last_name: String,
+    lifecycle: Lifecycle,
+    latest_license_season: u16,
+    structure_name: String,
+}
+
+/// Lists the members that are not active, the most recently licensed first.
+pub(crate) fn inactive_members(snapshot: &Snapshot) -> Vec<MemberLifecycle> {
+    let mut members = snapshot
+        .list::<User>("acc/")
+        .filter_map(|(_, user)| {
+            let metadata = user
+                .metadata
+                .and_then(|it| serde_json::from_value::<Metadata>(it).ok())?;
+            let lifecycle = metadata.lifecycle.filter(|it| *it != Lifecycle::Active)?;
+            if metadata.archived.is_some() {
+                return None;
+            }
+            let latest = metadata
+                .license_history?
+                .into_iter()
+                .max_by_key(|it| it.season)?;
+            Some(MemberLifecycle {
+                user_id: user.id.to_string(),
+                first_name: user.first_name,
+                last_name: user.last_name,
+                lifecycle,
+                latest_license_season: latest.season,
+                structure_name: latest.structure_name,
+            })
+        })
+        .collect::<Vec<_>>();
+    members.sort_by(|a, b| {
+        b.latest_license_season
+            .cmp(&a.latest_license_season)
+            .then_with(|| a.last_name.cmp(&b.last_name))
+    });
+    members
+}
+
+/// Only the licence history is kept, without the MyFFME licence ids, for the statistics.
+pub(crate) fn anonymise(user: &mut User, metadata: Metadata, now: u32) -> Option<()> {
+    user.identification.clear();
+    user.first_name = String::new();
+    user.normalized_first_name = String::new();
+    user.last_name = String::new();
+    user.normalized_last_name = String::new();
+    user.date_of_birth = 0;
+    user.admin = false;
+    let license_history = metadata.license_history.map(|history| {
+        history
+            .into_iter()
+            .map(|it| LicenseRecord {
+                id: String::new(),
+                ..it
+            })
+            .collect()
+    });
+    user.metadata = Some(
+        serde_json::to_value(Metadata {
+            lifecycle: metadata.lifecycle,
+            license_history,
+            archived: Some(now),
+            ..Default::default()
+        })
+        .ok()?,
+    );
+    Some(())
+}
+
+/// Removes what refers to an anonymised member besides their account: uploaded documents,
+/// reminders and age outs are deleted, contact conflicts and the changes pushed to MyFFME are
+/// anonymised, and the member is removed from the guardianships and the competition registrations,
+/// including their audit log.
+pub(crate) async fn erase_related_entries(
+    snapshot: &Snapshot,
+    actor: &Actor,
+    user_id: &str,
+    myffme_user_id: Option<&str>,
+    now: u32,
+) -> Option<()> {
+    remove_documents(snapshot, user_id);
+    let suffix = format!("_{user_id}");
+    for prefix in ["rnw/", "mcr/", "mcd/", "ago/"] {
+        for (key, entry) in snapshot.list::<Value>(prefix) {
+            if key.ends_with(&suffix) {
+                erase(snapshot, actor, key, &entry).await?;
+            }
+        }
+    }
+    anonymise_conflicts(snapshot, actor, user_id, now).await;
+    if let Some(myffme_user_id) = myffme_user_id {
+        redact_history(snapshot, &format!("myffme/{myffme_user_id}")).await;
+    }
+    for (key, mut user) in snapshot.list::<User>("acc/") {
+        let Some(mut metadata) = user
+            .metadata
+            .as_ref()
+            .and_then(|it| serde_json::from_value::<Metadata>(it.clone()).ok())
+        else {
+            continue;
+        };
+        let Some(children) = metadata.guardian_of.as_mut() else {
+            continue;
+        };
+        if !children.iter().any(|it| it == user_id) {
+            continue;
+        }
+        children.retain(|it| it != user_id);
+        if children.is_empty() {
+            metadata.guardian_of = None;
+        }
+        let before = user.clone();
+        user.metadata = Some(serde_json::to_value(metadata).ok()?);
+        Snapshot::set_and_wait_for_update(key, &user).await?;
+        record(actor, key, Some(&before), &user).await;
+    }
+    for (key, mut registrations) in snapshot.list::<CompetitionRegistrations>("crg/") {
+        let before = registrations.clone();
+        registrations.athletes.retain(|it| it.user_id != user_id);
+        registrations.coaches.retain(|it| it != user_id);
+        if registrations.athletes.len() == before.athletes.len()
+            && registrations.coaches.len() == before.coaches.len()
+        {
+            continue;
+        }
+        Snapshot::set_and_wait_for_update(key, &registrations).await?;
+        record(actor, key, Some(&before), &registrations).await;
+    }
+    redact_member(snapshot, "crg/", user_id).await;
+    Some(())
+}
+
+/// Anonymises the accounts of alumni whose latest licence is older than the configured number of
+/// seasons, and removes what refers to them. Their MyFFME user is forgotten, so that the sync
+/// doesn't create their account again.
+///
+/// Nothing is archived unless the number of seasons is configured.
+pub async fn archive_alumni(snapshot: &Arc<Snapshot>, now: u32) -> Option<()> {
+    let Some(archive_after_seasons) = *ARCHIVE_AFTER_SEASONS else {
+        info!("archiving is disabled");
+        return Some(());
+    };
+    let season = current_season(None);
+    let mut archived = 0;
+    for (key, mut user) in snapshot.list::<User>("acc/") {
+        let Some(metadata) = user
+            .metadata
+            .as_ref()
+            .and_then(|it| serde_json::from_value::<Metadata>(it.clone()).ok())
+        else {
+            continue;
+        };
+        if metadata.archived.is_some() || metadata.lifecycle != Some(Lifecycle::Alumni) {
+            continue;
+        }
+        let Some(latest_season) = metadata
+            .license_history
+            .iter()
+            .flatten()
+            .map(|it| it.season)
+            .max()
+        else {
+            continue;
+        };
+        if season.saturating_sub(latest_season) < archive_after_seasons {
+            continue;
+        }
+        info!("archiving {} {}", user.first_name, user.last_name);
+        let user_id = user.id.to_string();
+        let myffme_user_id = metadata.myffme_user_id.clone();
+        // the licensee can still be listed by MyFFME for the past seasons
+        if let Some(myffme_user_id) = myffme_user_id.as_deref() {
+            forget_myffme_user(&Actor::Job, myffme_user_id, now).await?;
+        }
+        let before = user.clone();
+        anonymise(&mut user, metadata, now)?;
+        Snapshot::set_and_wait_for_update(key, &user).await?;
+        record_anonymisation(snapshot, &Actor::Job, key, &before, &user).await;
+        erase_related_entries(
+            snapshot,
+            &Actor::Job,
+            &user_id,
+            myffme_user_id.as_deref(),
+            now,
+        )
+        .await?;
+        archived += 1;
+    }
+    info!("archived accounts: {archived}");
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::myffme::{LicenseType, MedicalCertificateStatus};
+    use crate::user::test_user;
+
+    fn record(season: u16, structure_id: u32) -> LicenseRecord {
+        LicenseRecord {
+            id: format!("{season}"),
+            season,
+            structure_id,
+            structure_name: format!("structure {structure_id}"),
+            license_type: LicenseType::Adult,
+            insurance_level: None,
+            insurance_options: Vec::new(),
+            medical_certificate_status: MedicalCertificateStatus::Recreational,
+        }
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        assert_eq!(None, lifecycle(&[], 2026, 1, 20251201));
+        let history = [record(2024, 1), record(2025, 1), record(2026, 1)];
+        assert_eq!(
+            Some(Lifecycle::Active),
+            lifecycle(&history, 2026, 1, 20251201)
+        );
+        assert_eq!(
+            Some(Lifecycle::Active),
+            lifecycle(&history, 2027, 1, 20261031)
+        );
+        assert_eq!(
+            Some(Lifecycle::Lapsed),
+            lifecycle(&history, 2027, 1, 20261201)
+        );
+        assert_eq!(
+            Some(Lifecycle::Lapsed),
+            lifecycle(&history, 2028, 1, 20271201)
+        );
+        assert_eq!(
+            Some(Lifecycle::Alumni),
+            lifecycle(&history, 2029, 1, 20281201)
+        );
+        let history = [record(2025, 1), record(2026, 2)];
+        assert_eq!(
+            Some(Lifecycle::Transferred),
+            lifecycle(&history, 2026, 1, 20251201)
+        );
+        assert_eq!(
+            Some(Lifecycle::Transferred),
+            lifecycle(&history, 2027, 1, 20261201)
+        );
+        assert_eq!(
+            Some(Lifecycle::Alumni),
+            lifecycle(&history, 2029, 1, 20281201)
+        );
+        let mut user = User {
+            admin: true,
+            metadata: None,
+            ..test_user("Léa", "Martin", 19900101, Metadata::default())
+        };
+        let metadata = Metadata {
+            license_number: Some(123),
+            lifecycle: Some(Lifecycle::Alumni),
+            license_history: Some(history.to_vec()),
+            ..Default::default()
+        };
+        anonymise(&mut user, metadata, 1_800_000_000).unwrap();
+        assert!(user.first_name.is_empty() && user.last_name.is_empty());
+        assert_eq!(0, user.date_of_birth);
+        assert!(!user.admin);
+        let metadata = serde_json::from_value::<Metadata>(user.metadata.unwrap()).unwrap();
+        assert_eq!(None, metadata.license_number);
+        assert_eq!(Some(1_800_000_000), metadata.archived);
+        let history = metadata.license_history.unwrap();
+        assert_eq!(
+            vec![2025, 2026],
+            history.iter().map(|it| it.season).collect::<Vec<_>>()
+        );
+        assert!(history.iter().all(|it| it.id.is_empty()));
+    }
+}
@@ -1,6 +1,20 @@
 use pierre_blanche_server::api::ApiExtension;
-use pierre_blanche_server::update::update_loop;
+use pierre_blanche_server::scheduler::{start_scheduler, stop_scheduler};
 use tiered_server::server::serve;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{info, warn};
+
+async fn sigterm() {
+    match signal(SignalKind::terminate()) {
+        Ok(mut it) => {
+            it.recv().await;
+        }
+        Err(err) => {
+            warn!("failed to listen for SIGTERM:\n{err:?}");
+            std::future::pending::<()>().await;
+        }
+    }
+}
 
 #[tokio::main]
 async fn main() {
@@ -28,6 +42,18 @@ async fn main() {
             "pierre_blanche_server=debug,tiered_server=debug,zip_static_handler=info,hyper=info",
         ))
         .init();
-    update_loop().await;
-    serve(Box::leak(Box::new(ApiExtension))).await;
+    start_scheduler().await;
+    let server = serve(Box::leak(Box::new(ApiExtension)));
+    tokio::pin!(server);
+    tokio::select! {
+        _ = &mut server => {}
+        _ = sigterm() => {
+            info!("SIGTERM received");
+            // requests are still served while the running jobs complete
+            tokio::select! {
+                _ = &mut server => {}
+                _ = stop_scheduler() => {}
+            }
+        }
+    }
 }
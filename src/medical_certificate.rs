@@ -0,0 +1,642 @@
+use crate::audit::{record, Actor};
+use crate::mycompet::calendar::days_from_date;
+use crate::myffme::document::{upload_document, MEDICAL_CERTIFICATE_DOCUMENT_TYPE};
+use crate::myffme::MedicalCertificateStatus;
+use crate::notification::notify_member;
+use crate::season::current_season;
+use crate::user::Metadata;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock};
+use std::time::SystemTime;
+use tiered_server::env::{secret_value, ConfigurationKey};
+use tiered_server::store::Snapshot;
+use tiered_server::user::User;
+use tracing::{info, warn};
+
+const DOCUMENTS_DIRECTORY_KEY: ConfigurationKey = ConfigurationKey::Other {
+    variable_name: "MEDICAL_DOCUMENTS_DIRECTORY",
+};
+
+/// Where the documents uploaded by the members are kept, `.medical_documents` in the working
+/// directory by default.
+static DOCUMENTS_DIRECTORY: LazyLock<PathBuf> = LazyLock::new(|| {
+    secret_value(DOCUMENTS_DIRECTORY_KEY)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(".medical_documents"))
+});
+
+/// Maximum size of an uploaded document.
+pub(crate) const MAX_DOCUMENT_SIZE: usize = 5 * 1024 * 1024;
+
+/// Members are reminded this many days before their certificate or questionnaire expires.
+const EXPIRY_REMINDER_DAYS: i64 = 30;
+
+/// Minimum delay between two reminders about the same document.
+const REMINDER_INTERVAL_SECONDS: u32 = 7 * 86_400;
+
+const MAX_REMINDERS: usize = 3;
+
+/// The medical certificate or health questionnaire attached to the licence of a season.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct MedicalCertificate {
+    pub status: MedicalCertificateStatus,
+    pub season: u16,
+    /// yyyymmdd, documents are valid until the end of the season of the licence.
+    pub valid_until: u32,
+    /// When the status was first seen.
+    pub since: u32,
+}
+
+impl MedicalCertificate {
+    /// Keeps the date the status was first seen if it didn't change.
+    pub(crate) fn new(
+        status: MedicalCertificateStatus,
+        season: u16,
+        previous: Option<&MedicalCertificate>,
+        now: u32,
+    ) -> Self {
+        let since = previous
+            .filter(|it| it.status == status && it.season == season)
+            .map(|it| it.since)
+            .unwrap_or(now);
+        Self {
+            status,
+            season,
+            valid_until: season as u32 * 1_00_00 + 8_31,
+            since,
+        }
+    }
+
+    /// Seasons start in August, the document of the previous season is still valid until the end of
+    /// August.
+    fn applies_to(&self, season: u16) -> bool {
+        self.season == season || self.season + 1 == season
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ClimbingRestriction {
+    /// The federation is still waiting for the certificate or questionnaire.
+    WaitingForDocument,
+    Expired,
+}
+
+/// Why a member of the season cannot legally climb yet, if they can't.
+pub(crate) fn climbing_restriction(
+    certificate: &MedicalCertificate,
+    today: u32,
+) -> Option<ClimbingRestriction> {
+    if certificate.valid_until < today {
+        Some(ClimbingRestriction::Expired)
+    } else if certificate.status == MedicalCertificateStatus::WaitingForDocument {
+        Some(ClimbingRestriction::WaitingForDocument)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct RestrictedMember {
+    user_id: String,
+    first_name: String,
+    last_name: String,
+    #[serde(flatten)]
+    certificate: MedicalCertificate,
+    restriction: ClimbingRestriction,
+}
+
+/// Lists the members of the current season that cannot legally climb yet, and the members of the
+/// previous season whose document expired.
+pub(crate) fn restricted_members(snapshot: &Snapshot, today: u32) -> Vec<RestrictedMember> {
+    let season = current_season(None);
+    snapshot
+        .list::<User>("acc/")
+        .filter_map(|(_, user)| {
+            let certificate = user
+                .metadata
+                .and_then(|it| serde_json::from_value::<Metadata>(it).ok())
+                .and_then(|it| it.medical_certificate)
+                .filter(|it| it.applies_to(season))?;
+            let restriction = climbing_restriction(&certificate, today)?;
+            Some(RestrictedMember {
+                user_id: user.id.to_string(),
+                first_name: user.first_name,
+                last_name: user.last_name,
+                certificate,
+                restriction,
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum ReminderKind {
+    MissingDocument,
+    Expiry,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub(crate) struct Reminders {
+    /// Timestamps of the reminders.
+    #[serde(default)]
+    sent: Vec<u32>,
+}
+
+impl Reminders {
+    fn key(kind: ReminderKind, season: u16, user_id: &str) -> String {
+        match kind {
+            ReminderKind::MissingDocument => format!("mcr/{season}_missing_{user_id}"),
+            ReminderKind::Expiry => format!("mcr/{season}_expiry_{user_id}"),
+        }
+    }
+
+    fn is_due(&self, now: u32) -> bool {
+        self.sent.len() < MAX_REMINDERS
+            && self
+                .sent
+                .last()
+                .is_none_or(|&it| now >= it + REMINDER_INTERVAL_SECONDS)
+    }
+}
+
+/// The reminder due for the document, the document is missing only if it is for the current season.
+fn reminder_kind(
+    certificate: &MedicalCertificate,
+    season: u16,
+    today: u32,
+) -> Option<ReminderKind> {
+    if !certificate.applies_to(season) {
+        None
+    } else if certificate.status == MedicalCertificateStatus::WaitingForDocument {
+        Some(ReminderKind::MissingDocument).filter(|_| certificate.season == season)
+    } else if (0..=EXPIRY_REMINDER_DAYS)
+        .contains(&(days_from_date(certificate.valid_until) - days_from_date(today)))
+    {
+        Some(ReminderKind::Expiry)
+    } else {
+        None
+    }
+}
+
+fn format_date(date: u32) -> String {
+    format!(
+        "{:02}/{:02}/{}",
+        date % 1_00,
+        date / 1_00 % 1_00,
+        date / 1_00_00
+    )
+}
+
+fn reminder_texts(
+    kind: ReminderKind,
+    first_name: &str,
+    certificate: &MedicalCertificate,
+) -> (String, String, String) {
+    match kind {
+        ReminderKind::MissingDocument => (
+            "Certificat médical ou questionnaire de santé manquant".to_string(),
+            format!(
+                "Bonjour {first_name},\n\n\
+                La fédération n'a pas encore validé votre certificat médical \
+                ou votre questionnaire de santé pour la saison {}-{}.\n\
+                Vous ne pouvez pas grimper tant qu'il n'est pas déposé sur MyFFME.\n",
+                certificate.season - 1,
+                certificate.season
+            ),
+            "Votre certificat médical ou questionnaire de santé n'est pas encore validé, \
+            merci de le déposer sur MyFFME."
+                .to_string(),
+        ),
+        ReminderKind::Expiry => {
+            let date = format_date(certificate.valid_until);
+            (
+                "Expiration de votre certificat médical".to_string(),
+                format!(
+                    "Bonjour {first_name},\n\n\
+                    Votre certificat médical ou questionnaire de santé expire le {date}.\n\
+                    Pensez à le renouveler avec votre licence pour continuer à grimper.\n"
+                ),
+                format!("Votre certificat médical ou questionnaire de santé expire le {date}."),
+            )
+        }
+    }
+}
+
+/// Reminds the members whose document is missing or expires soon.
+///
+/// The documents uploaded by members that MyFFME did not accept yet are forwarded again first.
+pub async fn remind_medical_certificates(snapshot: &Arc<Snapshot>, today: u32) -> Option<()> {
+    let season = current_season(None);
+    let failures = forward_pending_documents(snapshot, season).await;
+    if failures > 0 {
+        warn!("failed to forward {failures} pending medical certificate(s)");
+    }
+    let now = now();
+    let mut sent = 0;
+    for (_, user) in snapshot.list::<User>("acc/") {
+        let Some(certificate) = user
+            .metadata
+            .as_ref()
+            .and_then(|it| serde_json::from_value::<Metadata>(it.clone()).ok())
+            .and_then(|it| it.medical_certificate)
+        else {
+            continue;
+        };
+        let Some(kind) = reminder_kind(&certificate, season, today) else {
+            continue;
+        };
+        let user_id = user.id.to_string();
+        let key = Reminders::key(kind, certificate.season, &user_id);
+        let before = snapshot.get::<Reminders>(&key);
+        let mut reminders = before.clone().unwrap_or_default();
+        if !reminders.is_due(now) {
+            continue;
+        }
+        let (subject, email, sms) = reminder_texts(kind, &user.first_name, &certificate);
+        if !notify_member(snapshot, &user, &subject, &email, &sms).await {
+            warn!(
+                "failed to send medical certificate reminder to {} {}",
+                user.first_name, user.last_name
+            );
+            continue;
+        }
+        reminders.sent.push(now);
+        Snapshot::set_and_wait_for_update(&key, &reminders).await?;
+        record(&Actor::Job, &key, before.as_ref(), &reminders).await;
+        sent += 1;
+    }
+    info!("medical certificates: {sent} reminder(s) sent");
+    Some(())
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum DocumentFormat {
+    Pdf,
+    Jpeg,
+}
+
+impl DocumentFormat {
+    /// Checks both the declared content type and the signature of the file.
+    pub(crate) fn detect(content_type: Option<&str>, bytes: &[u8]) -> Option<Self> {
+        let content_type = content_type?.split(';').next()?.trim();
+        match content_type {
+            "application/pdf" if bytes.starts_with(b"%PDF-") => Some(Self::Pdf),
+            "image/jpeg" if bytes.starts_with(&[0xff, 0xd8, 0xff]) => Some(Self::Jpeg),
+            _ => None,
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Pdf => "application/pdf",
+            Self::Jpeg => "image/jpeg",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Pdf => "pdf",
+            Self::Jpeg => "jpg",
+        }
+    }
+}
+
+/// Reads the kind of certificate (`?type=recreational|competition`, defaults to recreational).
+pub(crate) fn certificate_status_from_query(
+    query: Option<&str>,
+) -> Option<MedicalCertificateStatus> {
+    let kind = query
+        .into_iter()
+        .flat_map(|it| it.split('&'))
+        .find_map(|it| it.strip_prefix("type="));
+    match kind {
+        None | Some("recreational") => Some(MedicalCertificateStatus::Recreational),
+        Some("competition") => Some(MedicalCertificateStatus::Competition),
+        Some(_) => None,
+    }
+}
+
+/// A medical certificate uploaded by a member, relayed to MyFFME.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct UploadedDocument {
+    user_id: String,
+    season: u16,
+    /// The kind of certificate, recreational or competition.
+    status: MedicalCertificateStatus,
+    format: DocumentFormat,
+    size: usize,
+    uploaded: u32,
+    /// When MyFFME accepted the document.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    forwarded: Option<u32>,
+}
+
+impl UploadedDocument {
+    fn key(season: u16, user_id: &str) -> String {
+        format!("mcd/{season}_{user_id}")
+    }
+
+    fn file_name(&self) -> String {
+        format!(
+            "{}_{}.{}",
+            self.season,
+            self.user_id,
+            self.format.extension()
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UploadError {
+    /// The member has no MyFFME licence for the current season.
+    NotLicensed,
+    Storage,
+    /// The document is kept and will be forwarded again later.
+    Upstream,
+}
+
+/// Stores a medical certificate uploaded by a member and forwards it to MyFFME.
+///
+/// The member keeps waiting for their document until MyFFME validates it, the sync then updates
+/// their status.
+pub(crate) async fn upload_medical_certificate(
+    snapshot: &Snapshot,
+    actor: &Actor,
+    user: User,
+    status: MedicalCertificateStatus,
+    format: DocumentFormat,
+    bytes: &[u8],
+) -> Result<UploadedDocument, UploadError> {
+    let season = current_season(None);
+    let metadata = user
+        .metadata
+        .as_ref()
+        .and_then(|it| serde_json::from_value::<Metadata>(it.clone()).ok())
+        .filter(|it| it.myffme_user_id.is_some() && it.latest_license_season == Some(season))
+        .ok_or(UploadError::NotLicensed)?;
+    let document = UploadedDocument {
+        user_id: user.id.to_string(),
+        season,
+        status,
+        format,
+        size: bytes.len(),
+        uploaded: now(),
+        forwarded: None,
+    };
+    std::fs::create_dir_all(DOCUMENTS_DIRECTORY.as_path())
+        .and_then(|_| std::fs::write(DOCUMENTS_DIRECTORY.join(document.file_name()), bytes))
+        .map_err(|err| {
+            warn!("failed to store medical certificate:\n{err:?}");
+            UploadError::Storage
+        })?;
+    let key = UploadedDocument::key(season, &document.user_id);
+    let previous = snapshot.get::<UploadedDocument>(&key);
+    Snapshot::set_and_wait_for_update(&key, &document)
+        .await
+        .ok_or(UploadError::Storage)?;
+    record(actor, &key, previous.as_ref(), &document).await;
+    forward(actor, &metadata, document, bytes)
+        .await
+        .ok_or(UploadError::Upstream)
+}
+
+/// Forwards the document to MyFFME and records when it was accepted.
+async fn forward(
+    actor: &Actor,
+    metadata: &Metadata,
+    mut document: UploadedDocument,
+    bytes: &[u8],
+) -> Option<UploadedDocument> {
+    upload_document(
+        metadata.myffme_user_id.as_deref()?,
+        document.season,
+        MEDICAL_CERTIFICATE_DOCUMENT_TYPE,
+        &document.file_name(),
+        document.format.content_type(),
+        bytes,
+    )
+    .await?;
+    let key = UploadedDocument::key(document.season, &document.user_id);
+    let before = document.clone();
+    document.forwarded = Some(now());
+    Snapshot::set_and_wait_for_update(&key, &document).await?;
+    record(actor, &key, Some(&before), &document).await;
+    info!(
+        "medical certificate {} accepted by myffme",
+        document.file_name()
+    );
+    Some(document)
+}
+
+/// Forwards again the documents of the season that MyFFME did not accept yet.
+///
+/// Returns the number of documents that still could not be forwarded.
+async fn forward_pending_documents(snapshot: &Snapshot, season: u16) -> usize {
+    let mut failures = 0;
+    for (_, document) in snapshot.list::<UploadedDocument>(&format!("mcd/{season}_")) {
+        if document.forwarded.is_some() {
+            continue;
+        }
+        let Some(metadata) = snapshot
+            .get::<User>(&format!("acc/{}", document.user_id))
+            .and_then(|it| it.metadata)
+            .and_then(|it| serde_json::from_value::<Metadata>(it).ok())
+        else {
+            continue;
+        };
+        let Ok(bytes) = std::fs::read(DOCUMENTS_DIRECTORY.join(document.file_name())) else {
+            warn!("missing medical certificate {}", document.file_name());
+            continue;
+        };
+        let file_name = document.file_name();
+        if forward(&Actor::Job, &metadata, document, &bytes)
+            .await
+            .is_none()
+        {
+            warn!("failed to forward medical certificate {file_name}");
+            failures += 1;
+        }
+    }
+    failures
+}
+
+/// Renames the files of the documents uploaded by a merged account after the kept account.
+///
+/// The files of the seasons the kept account already uploaded a document for are deleted instead.
+pub(crate) fn move_documents(snapshot: &Snapshot, from: &str, to: &str) {
+    for (_, document) in snapshot.list::<UploadedDocument>("mcd/") {
+        if document.user_id != from {
+            continue;
+        }
+        let path = DOCUMENTS_DIRECTORY.join(document.file_name());
+        let moved = UploadedDocument {
+            user_id: to.to_string(),
+            ..document
+        };
+        let result = if snapshot
+            .get::<UploadedDocument>(&UploadedDocument::key(moved.season, to))
+            .is_some()
+        {
+            std::fs::remove_file(&path)
+        } else {
+            std::fs::rename(&path, DOCUMENTS_DIRECTORY.join(moved.file_name()))
+        };
+        if let Err(err) = result {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                warn!("failed to move {}: {err:?}", path.display());
+            }
+        }
+    }
+}
+
+/// Deletes the files of the documents uploaded by a member, of every season.
+pub(crate) fn remove_documents(snapshot: &Snapshot, user_id: &str) {
+    for (_, document) in snapshot.list::<UploadedDocument>("mcd/") {
+        if document.user_id != user_id {
+            continue;
+        }
+        let path = DOCUMENTS_DIRECTORY.join(document.file_name());
+        if let Err(err) = std::fs::remove_file(&path) {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                warn!("failed to remove {}: {err:?}", path.display());
+            }
+        }
+    }
+}
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::season::season_of_date;
+
+    #[test]
+    fn test_medical_certificate() {
+        let waiting = MedicalCertificate::new(
+            MedicalCertificateStatus::WaitingForDocument,
+            2026,
+            None,
+            1_760_000_000,
+        );
+        assert_eq!(20260831, waiting.valid_until);
+        assert_eq!(
+            Some(ClimbingRestriction::WaitingForDocument),
+            climbing_restriction(&waiting, 20251018)
+        );
+        let validated = MedicalCertificate::new(
+            MedicalCertificateStatus::HealthQuestionnaire,
+            2026,
+            Some(&waiting),
+            1_760_100_000,
+        );
+        assert_eq!(1_760_100_000, validated.since);
+        assert_eq!(None, climbing_restriction(&validated, 20251018));
+        assert_eq!(
+            Some(ClimbingRestriction::Expired),
+            climbing_restriction(&validated, 20260901)
+        );
+        let unchanged = MedicalCertificate::new(
+            MedicalCertificateStatus::HealthQuestionnaire,
+            2026,
+            Some(&validated),
+            1_760_200_000,
+        );
+        assert_eq!(validated, unchanged);
+        let mut reminders = Reminders::default();
+        assert!(reminders.is_due(1_760_000_000));
+        reminders.sent.push(1_760_000_000);
+        assert!(!reminders.is_due(1_760_000_000 + 86_400));
+        assert!(reminders.is_due(1_760_000_000 + REMINDER_INTERVAL_SECONDS));
+        let (_, email, _) = reminder_texts(ReminderKind::Expiry, "Alice", &validated);
+        assert!(email.contains("expire le 31/08/2026"));
+    }
+
+    #[test]
+    fn test_august() {
+        // the 2027 season started on the 1st of August
+        let season = season_of_date(20260815);
+        assert_eq!(2027, season);
+        let validated = MedicalCertificate::new(
+            MedicalCertificateStatus::HealthQuestionnaire,
+            2026,
+            None,
+            1_760_000_000,
+        );
+        assert_eq!(None, reminder_kind(&validated, 2026, 20260615));
+        assert_eq!(
+            Some(ReminderKind::Expiry),
+            reminder_kind(&validated, 2026, 20260801)
+        );
+        assert_eq!(
+            Some(ReminderKind::Expiry),
+            reminder_kind(&validated, season, 20260815)
+        );
+        assert_eq!(None, climbing_restriction(&validated, 20260815));
+        assert_eq!(None, reminder_kind(&validated, season, 20260901));
+        assert!(validated.applies_to(season));
+        assert_eq!(
+            Some(ClimbingRestriction::Expired),
+            climbing_restriction(&validated, 20260901)
+        );
+        let waiting = MedicalCertificate::new(
+            MedicalCertificateStatus::WaitingForDocument,
+            2026,
+            None,
+            1_760_000_000,
+        );
+        assert_eq!(None, reminder_kind(&waiting, season, 20260815));
+        assert_eq!(
+            Some(ClimbingRestriction::Expired),
+            climbing_restriction(&waiting, 20260901)
+        );
+        assert!(!validated.applies_to(2028));
+    }
+
+    #[test]
+    fn test_uploaded_document() {
+        assert_eq!(
+            Some(DocumentFormat::Pdf),
+            DocumentFormat::detect(Some("application/pdf"), b"%PDF-1.7\n")
+        );
+        assert_eq!(
+            Some(DocumentFormat::Jpeg),
+            DocumentFormat::detect(Some("image/jpeg"), &[0xff, 0xd8, 0xff, 0xe0])
+        );
+        assert_eq!(
+            None,
+            DocumentFormat::detect(Some("application/pdf"), &[0xff, 0xd8, 0xff, 0xe0])
+        );
+        assert_eq!(None, DocumentFormat::detect(Some("image/png"), b"\x89PNG"));
+        assert_eq!(None, DocumentFormat::detect(None, b"%PDF-1.7\n"));
+        assert_eq!(
+            Some(MedicalCertificateStatus::Recreational),
+            certificate_status_from_query(None)
+        );
+        assert_eq!(
+            Some(MedicalCertificateStatus::Competition),
+            certificate_status_from_query(Some("type=competition"))
+        );
+        assert_eq!(None, certificate_status_from_query(Some("type=qs")));
+        let document = UploadedDocument {
+            user_id: "1".to_string(),
+            season: 2026,
+            status: MedicalCertificateStatus::Competition,
+            format: DocumentFormat::Jpeg,
+            size: 4,
+            uploaded: 1_760_000_000,
+            forwarded: None,
+        };
+        assert_eq!("mcd/2026_1", UploadedDocument::key(2026, "1"));
+        assert_eq!("2026_1.jpg", document.file_name());
+    }
+}
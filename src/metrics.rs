@@ -0,0 +1,367 @@
+use crate::status::SyncKind;
+use hyper::header::HeaderValue;
+use reqwest::Response;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tiered_server::env::{secret_value, ConfigurationKey};
+
+const METRICS_TOKEN_KEY: ConfigurationKey = ConfigurationKey::Other {
+    variable_name: "METRICS_TOKEN",
+};
+
+/// Bearer token expected from the prometheus scraper, if configured.
+static METRICS_AUTHORIZATION: LazyLock<Option<HeaderValue>> = LazyLock::new(|| {
+    secret_value(METRICS_TOKEN_KEY)
+        .and_then(|it| HeaderValue::try_from(format!("Bearer {it}")).ok())
+});
+
+#[derive(Clone, Copy)]
+pub(crate) enum Upstream {
+    Myffme,
+    MyCompet,
+    Geo,
+    HelloAsso,
+}
+
+impl Upstream {
+    const ALL: [Upstream; 4] = [
+        Upstream::Myffme,
+        Upstream::MyCompet,
+        Upstream::Geo,
+        Upstream::HelloAsso,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Upstream::Myffme => "myffme",
+            Upstream::MyCompet => "mycompet",
+            Upstream::Geo => "geo",
+            Upstream::HelloAsso => "hello_asso",
+        }
+    }
+}
+
+const LATENCY_BUCKETS_IN_MILLIS: [u64; 10] =
+    [25, 50, 100, 250, 500, 1000, 2500, 5000, 10000, 15000];
+
+struct UpstreamMetrics {
+    success: AtomicU64,
+    http_error: AtomicU64,
+    network_error: AtomicU64,
+    // the last bucket is +Inf
+    buckets: [AtomicU64; LATENCY_BUCKETS_IN_MILLIS.len() + 1],
+    sum_in_micros: AtomicU64,
+}
+
+impl UpstreamMetrics {
+    const fn new() -> Self {
+        Self {
+            success: AtomicU64::new(0),
+            http_error: AtomicU64::new(0),
+            network_error: AtomicU64::new(0),
+            buckets: [const { AtomicU64::new(0) }; LATENCY_BUCKETS_IN_MILLIS.len() + 1],
+            sum_in_micros: AtomicU64::new(0),
+        }
+    }
+}
+
+enum UpstreamOutcome {
+    Success,
+    HttpError,
+    NetworkError,
+}
+
+struct Metrics {
+    upstreams: [UpstreamMetrics; Upstream::ALL.len()],
+    // [scheduled, rejected] x [success, failure]
+    token_renewals: [[AtomicU64; 2]; 2],
+    // [add_missing_users, update_users_metadata] x [created, updated]
+    sync_users_total: [[AtomicU64; 2]; 2],
+    sync_users_last: [[AtomicU64; 2]; 2],
+    /// By route template and status code.
+    api_requests: Mutex<BTreeMap<(String, u16), u64>>,
+}
+
+static METRICS: Metrics = Metrics::new();
+
+/// Awaits an upstream request and records its outcome and latency.
+pub(crate) async fn timed<F>(upstream: Upstream, request: F) -> reqwest::Result<Response>
+where
+    F: Future<Output = reqwest::Result<Response>>,
+{
+    let t0 = Instant::now();
+    let result = request.await;
+    let outcome = match &result {
+        Ok(response) if response.status().is_success() => UpstreamOutcome::Success,
+        Ok(_) => UpstreamOutcome::HttpError,
+        Err(_) => UpstreamOutcome::NetworkError,
+    };
+    METRICS.record_upstream_request(upstream, outcome, t0.elapsed());
+    result
+}
+
+pub(crate) fn record_token_renewal(rejected: bool, success: bool) {
+    METRICS.record_token_renewal(rejected, success)
+}
+
+pub(crate) fn record_sync_users(kind: SyncKind, created: u64, updated: u64) {
+    METRICS.record_sync_users(kind, created, updated)
+}
+
+pub(crate) fn record_api_request(path: &str, status: u16) {
+    METRICS.record_api_request(path, status)
+}
+
+/// Replaces the variable segments of a request path, to keep the number of series bounded.
+fn route_template(path: &str) -> String {
+    if path.starts_with("/api/user/admin/jobs/") {
+        return "/api/user/admin/jobs/{name}/{action}".to_string();
+    }
+    path.split('/')
+        .map(|it| {
+            if it.chars().any(|it| it.is_ascii_digit()) {
+                "{id}"
+            } else {
+                it
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            upstreams: [const { UpstreamMetrics::new() }; Upstream::ALL.len()],
+            token_renewals: [const { [const { AtomicU64::new(0) }; 2] }; 2],
+            sync_users_total: [const { [const { AtomicU64::new(0) }; 2] }; 2],
+            sync_users_last: [const { [const { AtomicU64::new(0) }; 2] }; 2],
+            api_requests: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn record_upstream_request(
+        &self,
+        upstream: Upstream,
+        outcome: UpstreamOutcome,
+        elapsed: Duration,
+    ) {
+        let metrics = &self.upstreams[upstream as usize];
+        match outcome {
+            UpstreamOutcome::Success => &metrics.success,
+            UpstreamOutcome::HttpError => &metrics.http_error,
+            UpstreamOutcome::NetworkError => &metrics.network_error,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+        let millis = elapsed.as_millis() as u64;
+        let bucket = LATENCY_BUCKETS_IN_MILLIS
+            .iter()
+            .position(|&it| millis <= it)
+            .unwrap_or(LATENCY_BUCKETS_IN_MILLIS.len());
+        metrics.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        metrics
+            .sum_in_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn record_token_renewal(&self, rejected: bool, success: bool) {
+        self.token_renewals[rejected as usize][!success as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_sync_users(&self, kind: SyncKind, created: u64, updated: u64) {
+        let i = match kind {
+            SyncKind::AddMissingUsers => 0,
+            SyncKind::UpdateUsersMetadata => 1,
+        };
+        self.sync_users_total[i][0].fetch_add(created, Ordering::Relaxed);
+        self.sync_users_total[i][1].fetch_add(updated, Ordering::Relaxed);
+        self.sync_users_last[i][0].store(created, Ordering::Relaxed);
+        self.sync_users_last[i][1].store(updated, Ordering::Relaxed);
+    }
+
+    fn record_api_request(&self, path: &str, status: u16) {
+        *self
+            .api_requests
+            .lock()
+            .unwrap()
+            .entry((route_template(path), status))
+            .or_insert(0) += 1;
+    }
+}
+
+pub(crate) fn is_scraper_authorized(authorization: Option<&HeaderValue>) -> bool {
+    match (METRICS_AUTHORIZATION.as_ref(), authorization) {
+        (Some(expected), Some(authorization)) => expected == authorization,
+        _ => false,
+    }
+}
+
+/// Renders the metrics in the prometheus text exposition format.
+pub(crate) fn render() -> String {
+    METRICS.render()
+}
+
+impl Metrics {
+    fn render(&self) -> String {
+        let mut output = String::new();
+        let _ = writeln!(
+            output,
+            "# HELP pierre_blanche_upstream_requests_total Requests sent to upstream services."
+        );
+        let _ = writeln!(
+            output,
+            "# TYPE pierre_blanche_upstream_requests_total counter"
+        );
+        for upstream in Upstream::ALL {
+            let metrics = &self.upstreams[upstream as usize];
+            let label = upstream.label();
+            for (outcome, count) in [
+                ("success", &metrics.success),
+                ("http_error", &metrics.http_error),
+                ("network_error", &metrics.network_error),
+            ] {
+                let _ = writeln!(
+                output,
+                "pierre_blanche_upstream_requests_total{{upstream=\"{label}\",outcome=\"{outcome}\"}} {}",
+                count.load(Ordering::Relaxed)
+            );
+            }
+        }
+        let _ = writeln!(
+            output,
+            "# HELP pierre_blanche_upstream_request_duration_seconds Latency of upstream requests."
+        );
+        let _ = writeln!(
+            output,
+            "# TYPE pierre_blanche_upstream_request_duration_seconds histogram"
+        );
+        for upstream in Upstream::ALL {
+            let metrics = &self.upstreams[upstream as usize];
+            let label = upstream.label();
+            let mut cumulative = 0;
+            for (i, bucket) in metrics.buckets.iter().enumerate() {
+                cumulative += bucket.load(Ordering::Relaxed);
+                let le = LATENCY_BUCKETS_IN_MILLIS
+                    .get(i)
+                    .map(|&it| format!("{}", it as f64 / 1000.0))
+                    .unwrap_or_else(|| "+Inf".to_string());
+                let _ = writeln!(
+                output,
+                "pierre_blanche_upstream_request_duration_seconds_bucket{{upstream=\"{label}\",le=\"{le}\"}} {cumulative}"
+            );
+            }
+            let _ = writeln!(
+                output,
+                "pierre_blanche_upstream_request_duration_seconds_sum{{upstream=\"{label}\"}} {}",
+                metrics.sum_in_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0
+            );
+            let _ = writeln!(
+            output,
+            "pierre_blanche_upstream_request_duration_seconds_count{{upstream=\"{label}\"}} {cumulative}"
+        );
+        }
+        let _ = writeln!(
+            output,
+            "# HELP pierre_blanche_myffme_token_renewals_total MyFFME bearer token renewals."
+        );
+        let _ = writeln!(
+            output,
+            "# TYPE pierre_blanche_myffme_token_renewals_total counter"
+        );
+        for (i, trigger) in ["schedule", "rejection"].into_iter().enumerate() {
+            for (j, result) in ["success", "failure"].into_iter().enumerate() {
+                let _ = writeln!(
+                output,
+                "pierre_blanche_myffme_token_renewals_total{{trigger=\"{trigger}\",result=\"{result}\"}} {}",
+                self.token_renewals[i][j].load(Ordering::Relaxed)
+            );
+            }
+        }
+        for (name, kind, help, values) in [
+            (
+                "pierre_blanche_sync_users_total",
+                "counter",
+                "Users created or updated by the MyFFME sync.",
+                &self.sync_users_total,
+            ),
+            (
+                "pierre_blanche_sync_users_last",
+                "gauge",
+                "Users created or updated by the last MyFFME sync.",
+                &self.sync_users_last,
+            ),
+        ] {
+            let _ = writeln!(output, "# HELP {name} {help}");
+            let _ = writeln!(output, "# TYPE {name} {kind}");
+            for (i, sync) in ["add_missing_users", "update_users_metadata"]
+                .into_iter()
+                .enumerate()
+            {
+                for (j, operation) in ["created", "updated"].into_iter().enumerate() {
+                    let _ = writeln!(
+                        output,
+                        "{name}{{sync=\"{sync}\",operation=\"{operation}\"}} {}",
+                        values[i][j].load(Ordering::Relaxed)
+                    );
+                }
+            }
+        }
+        let _ = writeln!(
+            output,
+            "# HELP pierre_blanche_api_requests_total Requests handled by the api extension."
+        );
+        let _ = writeln!(output, "# TYPE pierre_blanche_api_requests_total counter");
+        for ((route, status), count) in self.api_requests.lock().unwrap().iter() {
+            let _ = writeln!(
+            output,
+            "pierre_blanche_api_requests_total{{route=\"{route}\",status=\"{status}\"}} {count}"
+        );
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        // not the global metrics, other tests record upstream requests
+        let metrics = Metrics::new();
+        metrics.record_api_request("/api/user/prices", 200);
+        metrics.record_api_request("/api/user/prices", 200);
+        metrics.record_api_request("/api/user/admin/jobs/archive/run", 202);
+        metrics.record_sync_users(SyncKind::AddMissingUsers, 3, 1);
+        metrics.record_token_renewal(true, false);
+        let output = metrics.render();
+        assert!(output.contains(
+            "pierre_blanche_api_requests_total{route=\"/api/user/prices\",status=\"200\"} 2"
+        ));
+        assert!(output.contains(
+            "pierre_blanche_api_requests_total{route=\"/api/user/admin/jobs/{name}/{action}\",status=\"202\"} 1"
+        ));
+        assert!(output.contains(
+            "pierre_blanche_sync_users_last{sync=\"add_missing_users\",operation=\"created\"} 3"
+        ));
+        assert!(output.contains(
+            "pierre_blanche_myffme_token_renewals_total{trigger=\"rejection\",result=\"failure\"} 1"
+        ));
+        assert!(output.contains(
+            "pierre_blanche_upstream_request_duration_seconds_bucket{upstream=\"geo\",le=\"+Inf\"} 0"
+        ));
+    }
+
+    #[test]
+    fn test_route_template() {
+        assert_eq!("/api/user/prices", route_template("/api/user/prices"));
+        assert_eq!(
+            "/api/user/admin/jobs/{name}/{action}",
+            route_template("/api/user/admin/jobs/member_sync/pause")
+        );
+        assert_eq!("/api/user/{id}/data", route_template("/api/user/1234/data"));
+    }
+}
@@ -0,0 +1,497 @@
+use crate::audit::{record, Actor};
+use crate::http_client::html_client;
+use crate::metrics::{timed, Upstream};
+use crate::mycompet::results::{parse_date, parse_discipline, parse_level};
+use crate::myffme::structure::structure_hierarchy_by_id;
+use crate::myffme::{CompetitionLevel, Discipline, STRUCTURE_ID};
+use crate::status::{record_scraped_page, ScrapedPage, ScrapedTable};
+use reqwest::Url;
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::time::SystemTime;
+use tiered_server::store::{snapshot, Snapshot};
+use tracing::{info, warn};
+
+const CALENDAR_URL: &str = "https://mycompet.ffme.fr/calendrier";
+
+/// Headers that should always be on the calendar page.
+const REQUIRED_HEADERS: [&str; 2] = ["Date", "Compétition"];
+
+pub(crate) const CALENDAR_KEY: &str = "cal/competitions";
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct UpcomingCompetition {
+    pub name: String,
+    /// yyyymmdd
+    pub start_date: u32,
+    /// yyyymmdd, only for competitions that last more than a day.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_date: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    /// Department number.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub department: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discipline: Option<Discipline>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<CompetitionLevel>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Held in the department of our structure.
+    pub in_department: bool,
+    /// Held in the region of our structure.
+    pub in_region: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Calendar {
+    pub(crate) timestamp: u32,
+    pub(crate) competitions: Vec<UpcomingCompetition>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CalendarScope {
+    Department,
+    Region,
+    All,
+}
+
+impl CalendarScope {
+    pub(crate) fn from_query(query: Option<&str>) -> Option<Self> {
+        let scope = query
+            .into_iter()
+            .flat_map(|it| it.split('&'))
+            .find_map(|it| it.strip_prefix("scope="));
+        match scope {
+            None | Some("region") => Some(Self::Region),
+            Some("department") => Some(Self::Department),
+            Some("all") => Some(Self::All),
+            Some(_) => None,
+        }
+    }
+
+    fn includes(self, competition: &UpcomingCompetition) -> bool {
+        match self {
+            Self::Department => competition.in_department,
+            Self::Region => competition.in_department || competition.in_region,
+            Self::All => true,
+        }
+    }
+}
+
+/// Imports the competition calendar and stores it in the snapshot.
+pub async fn update_competition_calendar() -> Option<()> {
+    let structure = structure_hierarchy_by_id(*STRUCTURE_ID).await?;
+    let department = structure.department.map(|it| it.number);
+    let region = structure_hierarchy_by_id(structure.region_structure_id)
+        .await
+        .map(|it| it.name);
+    let client = html_client();
+    let request = client.get(Url::parse(CALENDAR_URL).unwrap()).build().ok()?;
+    let response = timed(Upstream::MyCompet, client.execute(request))
+        .await
+        .inspect_err(|err| warn!("{err:?}"))
+        .ok()?;
+    if !response.status().is_success() {
+        warn!("failed to get competition calendar");
+        return None;
+    }
+    let text = response.text().await.ok()?;
+    let mut table = ScrapedTable::default();
+    let competitions = parse_competition_calendar(
+        text.as_str(),
+        department.as_deref(),
+        region.as_deref(),
+        &mut table,
+    );
+    record_scraped_page(ScrapedPage::CompetitionCalendar, &REQUIRED_HEADERS, &table);
+    let competitions = competitions?;
+    info!("competition calendar: {}", competitions.len());
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32;
+    let before = snapshot().get::<Calendar>(CALENDAR_KEY);
+    let calendar = Calendar {
+        timestamp,
+        competitions,
+    };
+    Snapshot::set_and_wait_for_update(CALENDAR_KEY, &calendar).await?;
+    // only the import time changed otherwise
+    if before
+        .as_ref()
+        .is_none_or(|it| it.competitions != calendar.competitions)
+    {
+        record(&Actor::Job, CALENDAR_KEY, before.as_ref(), &calendar).await;
+    }
+    Some(())
+}
+
+pub(crate) fn parse_competition_calendar(
+    html: &str,
+    our_department: Option<&str>,
+    our_region: Option<&str>,
+    table_report: &mut ScrapedTable,
+) -> Option<Vec<UpcomingCompetition>> {
+    let document = Html::parse_document(html);
+    let table = document
+        .select(&Selector::parse(".index-table").unwrap())
+        .next()?;
+    let mut date_index = None;
+    let mut name_index = None;
+    let mut location_index = None;
+    let mut department_index = None;
+    let mut region_index = None;
+    let mut discipline_index = None;
+    let mut level_index = None;
+    for (i, header) in table
+        .select(&Selector::parse("thead tr:first-of-type :is(td,th)").unwrap())
+        .enumerate()
+    {
+        let text = header.text().collect::<String>();
+        let (index, name) = match text.trim() {
+            "Date" | "Dates" => (&mut date_index, "Date"),
+            "Compétition" | "Nom" => (&mut name_index, "Compétition"),
+            "Lieu" | "Ville" => (&mut location_index, "Lieu"),
+            "Département" | "Dépt" => (&mut department_index, "Département"),
+            "Région" | "Ligue" => (&mut region_index, "Région"),
+            "Discipline" | "Épreuve" => (&mut discipline_index, "Discipline"),
+            "Niveau" | "Type" => (&mut level_index, "Niveau"),
+            _ => continue,
+        };
+        *index = Some(i);
+        table_report.found_headers.push(name);
+    }
+    if date_index.is_none() {
+        warn!("failed to find column header for competition date");
+        return None;
+    }
+    let date_index = date_index.unwrap();
+    if name_index.is_none() {
+        warn!("failed to find column header for competition name");
+        return None;
+    }
+    let name_index = name_index.unwrap();
+    let our_region = our_region.map(normalize_region);
+    let mut competitions = Vec::new();
+    for row in table.select(&Selector::parse("tbody tr").unwrap()) {
+        let mut dates = None;
+        let mut name = None;
+        let mut url = None;
+        let mut location = None;
+        let mut department = None;
+        let mut region = None;
+        let mut discipline = None;
+        let mut level = None;
+        for (i, col) in row.select(&Selector::parse("td").unwrap()).enumerate() {
+            let text = col.text().collect::<String>();
+            let text = text.trim();
+            if i == date_index {
+                let start = parse_date(text);
+                let end = text
+                    .split_once('-')
+                    .and_then(|(_, it)| parse_date(it.trim()));
+                dates = start.map(|it| (it, end.filter(|&end| end != it)));
+            } else if i == name_index {
+                name = Some(text.to_string());
+                url = col
+                    .select(&Selector::parse("a[href]").unwrap())
+                    .next()
+                    .and_then(|it| it.value().attr("href"))
+                    .and_then(|it| Url::parse(CALENDAR_URL).ok()?.join(it).ok())
+                    .map(|it| it.to_string());
+            } else if Some(i) == location_index {
+                location = Some(text.to_string()).filter(|it| !it.is_empty());
+            } else if Some(i) == department_index {
+                department = parse_department_number(text);
+            } else if Some(i) == region_index {
+                region = Some(text.to_string()).filter(|it| !it.is_empty());
+            } else if Some(i) == discipline_index {
+                discipline = parse_discipline(text);
+            } else if Some(i) == level_index {
+                level = parse_level(text);
+            }
+        }
+        let (Some((start_date, end_date)), Some(name)) = (dates, name) else {
+            warn!("skipping calendar row without date or name");
+            table_report.skipped_rows += 1;
+            continue;
+        };
+        let in_department = our_department.is_some() && department.as_deref() == our_department;
+        let in_region = match (our_region.as_ref(), region.as_deref()) {
+            (Some(ours), Some(theirs)) => {
+                let theirs = normalize_region(theirs);
+                !theirs.is_empty() && (ours.contains(&theirs) || theirs.contains(ours))
+            }
+            _ => false,
+        };
+        competitions.push(UpcomingCompetition {
+            discipline: discipline.or_else(|| parse_discipline(&name)),
+            level: level.or_else(|| parse_level(&name)),
+            name,
+            start_date,
+            end_date,
+            location,
+            department,
+            region,
+            url,
+            in_department,
+            in_region,
+        });
+    }
+    competitions.sort_by_key(|it| it.start_date);
+    Some(competitions)
+}
+
+/// Extracts "86" from "Vienne (86)" or "86 - Vienne", "2A" from "Corse-du-Sud (2A)".
+fn parse_department_number(text: &str) -> Option<String> {
+    text.split(|c: char| !c.is_ascii_alphanumeric())
+        .find(|it| {
+            (it.len() == 2 || it.len() == 3)
+                && (it.chars().all(|c| c.is_ascii_digit()) || *it == "2A" || *it == "2B")
+        })
+        .map(|it| it.to_string())
+}
+
+fn normalize_region(name: &str) -> String {
+    let name = name.to_lowercase();
+    let name = name.trim();
+    name.strip_prefix("ligue ")
+        .or_else(|| name.strip_prefix("comité régional "))
+        .unwrap_or(name)
+        .trim()
+        .to_string()
+}
+
+pub(crate) fn upcoming_competitions(
+    snapshot: &Snapshot,
+    scope: CalendarScope,
+    today: u32,
+) -> Vec<UpcomingCompetition> {
+    snapshot
+        .get::<Calendar>(CALENDAR_KEY)
+        .map(|it| it.competitions)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|it| it.end_date.unwrap_or(it.start_date) >= today && scope.includes(it))
+        .collect()
+}
+
+/// Days since 1970-01-01 to yyyymmdd.
+fn date_from_days(days: i64) -> u32 {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year * 1_00_00 + month * 1_00 + day) as u32
+}
+
+/// yyyymmdd to days since 1970-01-01.
+pub(crate) fn days_from_date(date: u32) -> i64 {
+    // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let (year, month, day) = (
+        (date / 1_00_00) as i64,
+        (date / 1_00 % 1_00) as i64,
+        (date % 1_00) as i64,
+    );
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Whether the yyyymmdd date exists.
+pub(crate) fn is_valid_date(date: u32) -> bool {
+    date >= 1_01_01 && date_from_days(days_from_date(date)) == date
+}
+
+pub(crate) fn today(timestamp: u32) -> u32 {
+    date_from_days(timestamp as i64 / 86_400)
+}
+
+fn escape_ics_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Folds content lines longer than 75 octets, as required by RFC 5545.
+fn write_ics_line(ics: &mut String, line: &str) {
+    let mut len = 0;
+    for c in line.chars() {
+        if len + c.len_utf8() > 75 {
+            ics.push_str("\r\n ");
+            len = 1;
+        }
+        ics.push(c);
+        len += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
+
+pub(crate) fn to_ics(competitions: &[UpcomingCompetition], timestamp: u32) -> String {
+    let days = timestamp as i64 / 86_400;
+    let seconds = timestamp % 86_400;
+    let stamp = format!(
+        "{}T{:02}{:02}{:02}Z",
+        date_from_days(days),
+        seconds / 3_600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+    let mut ics = String::new();
+    write_ics_line(&mut ics, "BEGIN:VCALENDAR");
+    write_ics_line(&mut ics, "VERSION:2.0");
+    write_ics_line(&mut ics, "PRODID:-//Pierre Blanche//Competitions//FR");
+    write_ics_line(&mut ics, "CALSCALE:GREGORIAN");
+    write_ics_line(&mut ics, "X-WR-CALNAME:Compétitions");
+    for competition in competitions {
+        let end = date_from_days(
+            days_from_date(competition.end_date.unwrap_or(competition.start_date)) + 1,
+        );
+        let mut uid = String::new();
+        let _ = write!(uid, "UID:{}-", competition.start_date);
+        uid.extend(
+            competition
+                .name
+                .chars()
+                .filter(|it| it.is_ascii_alphanumeric())
+                .map(|it| it.to_ascii_lowercase()),
+        );
+        uid.push_str("@mycompet.ffme.fr");
+        write_ics_line(&mut ics, "BEGIN:VEVENT");
+        write_ics_line(&mut ics, &uid);
+        write_ics_line(&mut ics, &format!("DTSTAMP:{stamp}"));
+        write_ics_line(
+            &mut ics,
+            &format!("DTSTART;VALUE=DATE:{}", competition.start_date),
+        );
+        write_ics_line(&mut ics, &format!("DTEND;VALUE=DATE:{end}"));
+        write_ics_line(
+            &mut ics,
+            &format!("SUMMARY:{}", escape_ics_text(&competition.name)),
+        );
+        if let Some(location) = competition.location.as_ref() {
+            write_ics_line(&mut ics, &format!("LOCATION:{}", escape_ics_text(location)));
+        }
+        if let Some(url) = competition.url.as_ref() {
+            write_ics_line(&mut ics, &format!("URL:{url}"));
+        }
+        write_ics_line(&mut ics, "END:VEVENT");
+    }
+    write_ics_line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HTML: &str = r#"
+<html><body><table class="index-table">
+<thead><tr>
+<th>Dates</th><th>Compétition</th><th>Lieu</th><th>Département</th><th>Ligue</th>
+</tr></thead>
+<tbody>
+<tr>
+<td>29/02/2024 - 01/03/2024</td><td><a href="/competition/1234">Championnat régional de bloc</a></td>
+<td>Poitiers</td><td>Vienne (86)</td><td>Nouvelle-Aquitaine</td>
+</tr>
+<tr>
+<td>12/01/2024</td><td>Open de difficulté</td><td>Niort</td><td>79 - Deux-Sèvres</td><td>Nouvelle-Aquitaine</td>
+</tr>
+<tr>
+<td>20/01/2024</td><td>Coupe de France de vitesse</td><td>Voiron</td><td>Isère (38)</td><td>Auvergne-Rhône-Alpes</td>
+</tr>
+</tbody>
+</table></body></html>
+"#;
+
+    #[test]
+    fn test_parse_competition_calendar() {
+        let competitions = parse_competition_calendar(
+            HTML,
+            Some("86"),
+            Some("Ligue Nouvelle-Aquitaine"),
+            &mut ScrapedTable::default(),
+        )
+        .unwrap();
+        assert_eq!(3, competitions.len());
+        let first = &competitions[0];
+        assert_eq!("Open de difficulté", first.name);
+        assert_eq!(20240112, first.start_date);
+        assert_eq!(None, first.end_date);
+        assert_eq!(Some("79"), first.department.as_deref());
+        assert_eq!(Some(Discipline::Lead), first.discipline);
+        assert!(!first.in_department);
+        assert!(first.in_region);
+        let third = &competitions[2];
+        assert_eq!(Some(20240301), third.end_date);
+        assert_eq!(
+            Some("https://mycompet.ffme.fr/competition/1234"),
+            third.url.as_deref()
+        );
+        assert_eq!(Some(CompetitionLevel::Regional), third.level);
+        assert!(third.in_department);
+        assert!(!competitions[1].in_region);
+        assert_eq!(Some(CompetitionLevel::National), competitions[1].level);
+        assert!(CalendarScope::Region.includes(first));
+        assert!(!CalendarScope::Department.includes(first));
+        assert!(!CalendarScope::Region.includes(&competitions[1]));
+    }
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(20240229, date_from_days(days_from_date(20240228) + 1));
+        assert_eq!(20250101, date_from_days(days_from_date(20241231) + 1));
+        assert_eq!(19700101, date_from_days(0));
+        assert_eq!(20231114, today(1_700_000_000));
+        assert!(is_valid_date(20240229));
+        assert!(!is_valid_date(20250229));
+        assert!(!is_valid_date(20251301));
+        assert!(!is_valid_date(20250100));
+    }
+
+    #[test]
+    fn test_scope() {
+        assert_eq!(Some(CalendarScope::Region), CalendarScope::from_query(None));
+        assert_eq!(
+            Some(CalendarScope::Department),
+            CalendarScope::from_query(Some("x=1&scope=department"))
+        );
+        assert_eq!(None, CalendarScope::from_query(Some("scope=world")));
+    }
+
+    #[test]
+    fn test_ics() {
+        let competitions = parse_competition_calendar(
+            HTML,
+            Some("86"),
+            Some("Ligue Nouvelle-Aquitaine"),
+            &mut ScrapedTable::default(),
+        )
+        .unwrap();
+        let ics = to_ics(&competitions[2..], 1_700_000_000);
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(ics.contains("DTSTAMP:20231114T221320Z\r\n"));
+        assert!(ics.contains("DTSTART;VALUE=DATE:20240229\r\n"));
+        assert!(ics.contains("DTEND;VALUE=DATE:20240302\r\n"));
+        assert!(ics.contains("LOCATION:Poitiers\r\n"));
+        assert!(ics.ends_with("END:VEVENT\r\nEND:VCALENDAR\r\n"));
+        assert!(ics.split("\r\n").all(|it| it.len() <= 75));
+    }
+}
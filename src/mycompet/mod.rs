@@ -1,12 +1,18 @@
+pub mod calendar;
+pub mod registration;
 pub mod results;
 
+use crate::audit::{record, Actor};
 use crate::mycompet::results::competition_results;
-use crate::myffme::MedicalCertificateStatus;
+use crate::myffme::{MedicalCertificateStatus, ResultStatus};
 use crate::season::current_season;
 use crate::user::Metadata;
+use serde::Serialize;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use tiered_server::store::Snapshot;
 use tiered_server::user::User;
+use tracing::info;
 
 pub async fn update_competition_results(snapshot: &Arc<Snapshot>) -> Option<()> {
     let season = current_season(None);
@@ -30,19 +36,204 @@ pub async fn update_competition_results(snapshot: &Arc<Snapshot>) -> Option<()>
             }
         })
         .collect::<Vec<_>>();
+    let mut updated = 0;
     for (key, (mut user, mut metadata)) in current_data.into_iter() {
         let license_number = metadata.license_number.unwrap();
         if let Some(results) = competition_results(license_number).await {
-            if !results.is_empty() {
-                if let Some(competition_results) = metadata.competition_results {
-                    if results.len() != competition_results.len() {
-                        metadata.competition_results = Some(results);
-                        user.metadata = Some(serde_json::to_value(metadata).unwrap());
-                        Snapshot::set_and_wait_for_update(key, &user).await?;
-                    }
-                }
+            // an empty list is more likely a glitch than results being removed
+            if !results.is_empty() && metadata.competition_results.as_ref() != Some(&results) {
+                let before = user.clone();
+                metadata.competition_results = Some(results);
+                user.metadata = Some(serde_json::to_value(metadata).unwrap());
+                Snapshot::set_and_wait_for_update(key, &user).await?;
+                record(&Actor::Sync, key, Some(&before), &user).await;
+                updated += 1;
             }
         }
     }
+    info!("updated competition results for {updated} user(s)");
     Some(())
 }
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub(crate) struct SeasonResults {
+    season: u16,
+    competitions: Vec<CompetitionResults>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub(crate) struct CompetitionResults {
+    name: String,
+    results: Vec<ClimberResult>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub(crate) struct ClimberResult {
+    first_name: String,
+    /// Only the initial, the page is public.
+    last_name: String,
+    category_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rank: Option<u16>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    tied: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<ResultStatus>,
+}
+
+/// Aggregates the results of the club members per season (latest first) and competition.
+pub(crate) fn club_results(users: impl Iterator<Item = User>) -> Vec<SeasonResults> {
+    let mut seasons = BTreeMap::<u16, BTreeMap<String, Vec<ClimberResult>>>::new();
+    for user in users {
+        let Some(results) = user
+            .metadata
+            .and_then(|it| serde_json::from_value::<Metadata>(it).ok())
+            .and_then(|it| it.competition_results)
+        else {
+            continue;
+        };
+        let last_name = user
+            .last_name
+            .chars()
+            .next()
+            .map(|it| format!("{}.", it.to_uppercase()))
+            .unwrap_or_default();
+        for result in results {
+            seasons
+                .entry(result.competition.season)
+                .or_default()
+                .entry(result.competition.name)
+                .or_default()
+                .push(ClimberResult {
+                    first_name: user.first_name.clone(),
+                    last_name: last_name.clone(),
+                    category_name: result.category_name,
+                    rank: result.rank,
+                    tied: result.tied,
+                    status: result.status,
+                });
+        }
+    }
+    seasons
+        .into_iter()
+        .rev()
+        .map(|(season, competitions)| SeasonResults {
+            season,
+            competitions: competitions
+                .into_iter()
+                .map(|(name, mut results)| {
+                    // climbers without a rank come last
+                    results.sort_by(|a, b| {
+                        a.category_name
+                            .cmp(&b.category_name)
+                            .then(a.rank.is_none().cmp(&b.rank.is_none()))
+                            .then(a.rank.cmp(&b.rank))
+                    });
+                    CompetitionResults { name, results }
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::myffme::{Competition, CompetitionResult};
+    use crate::user::test_user;
+
+    fn user(first_name: &str, last_name: &str, results: Vec<CompetitionResult>) -> User {
+        test_user(
+            first_name,
+            last_name,
+            20100101,
+            Metadata {
+                competition_results: Some(results),
+                ..Default::default()
+            },
+        )
+    }
+
+    fn result(
+        season: u16,
+        name: &str,
+        category_name: &str,
+        rank: Option<u16>,
+    ) -> CompetitionResult {
+        CompetitionResult {
+            rank,
+            tied: false,
+            status: None,
+            category_name: category_name.to_string(),
+            competition: Competition {
+                season,
+                name: name.to_string(),
+                date: None,
+                discipline: None,
+                level: None,
+                location: None,
+            },
+            participants: None,
+        }
+    }
+
+    #[test]
+    fn test_club_results() {
+        let users = vec![
+            user(
+                "Alice",
+                "martin",
+                vec![
+                    result(2024, "Open de Niort", "U16", Some(3)),
+                    result(2025, "Coupe régionale", "U18", Some(1)),
+                ],
+            ),
+            user(
+                "Bob",
+                "Durand",
+                vec![result(2024, "Open de Niort", "U16", Some(1))],
+            ),
+            user("Carole", "Petit", vec![]),
+            user(
+                "David",
+                "Moreau",
+                vec![result(2024, "Open de Niort", "U16", None)],
+            ),
+        ];
+        let seasons = club_results(users.into_iter());
+        assert_eq!(2, seasons.len());
+        assert_eq!(2025, seasons[0].season);
+        assert_eq!(2024, seasons[1].season);
+        let open = &seasons[1].competitions[0];
+        assert_eq!("Open de Niort", open.name);
+        assert_eq!(
+            vec![
+                ClimberResult {
+                    first_name: "Bob".to_string(),
+                    last_name: "D.".to_string(),
+                    category_name: "U16".to_string(),
+                    rank: Some(1),
+                    tied: false,
+                    status: None,
+                },
+                ClimberResult {
+                    first_name: "Alice".to_string(),
+                    last_name: "M.".to_string(),
+                    category_name: "U16".to_string(),
+                    rank: Some(3),
+                    tied: false,
+                    status: None,
+                },
+                ClimberResult {
+                    first_name: "David".to_string(),
+                    last_name: "M.".to_string(),
+                    category_name: "U16".to_string(),
+                    rank: None,
+                    tied: false,
+                    status: None,
+                },
+            ],
+            open.results
+        );
+    }
+}
@@ -0,0 +1,472 @@
+use crate::audit::{record, Actor};
+use crate::category::Category;
+use crate::mycompet::calendar::{days_from_date, is_valid_date};
+use crate::myffme::MedicalCertificateStatus;
+use crate::notification::notify_admins;
+use crate::season::season_of_date;
+use crate::user::Metadata;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use tiered_server::email::send_email;
+use tiered_server::store::Snapshot;
+use tiered_server::user::User;
+use tracing::{info, warn};
+
+/// Coaches are alerted about ineligible athletes this many days before the registration deadline.
+const ALERT_DAYS_BEFORE_DEADLINE: i64 = 7;
+
+/// Registrations can only be recorded for competitions starting in the next two years.
+const MAX_DAYS_BEFORE_START: i64 = 2 * 366;
+
+const SUBJECT: &str = "Athlètes non éligibles en compétition";
+
+/// The athletes of the club that coaches intend to register for a competition.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub(crate) struct CompetitionRegistrations {
+    pub(crate) competition: String,
+    /// yyyymmdd
+    pub(crate) start_date: u32,
+    /// yyyymmdd, last day to register, defaults to the start date.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) deadline: Option<u32>,
+    /// Categories allowed to compete, any category if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) categories: Vec<Category>,
+    #[serde(default)]
+    pub(crate) athletes: Vec<RegisteredAthlete>,
+    /// Ids of the coaches who registered the athletes, they are the ones alerted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) coaches: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub(crate) struct RegisteredAthlete {
+    pub(crate) user_id: String,
+    /// Whether the coaches were already alerted that the athlete is not eligible.
+    #[serde(default)]
+    pub(crate) alerted: bool,
+}
+
+/// Request body to record the athletes registered for a competition.
+///
+/// The list of athletes replaces the previous one, an empty list cancels every registration.
+#[derive(Debug, Deserialize)]
+pub(crate) struct RegistrationsUpdate {
+    competition: String,
+    start_date: u32,
+    deadline: Option<u32>,
+    #[serde(default)]
+    categories: Vec<Category>,
+    user_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RegistrationError {
+    /// The competition has no name, or its dates are invalid.
+    InvalidRequest,
+    Storage,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Ineligibility {
+    UnknownAthlete,
+    License,
+    MedicalCertificate,
+    Category,
+}
+
+impl Ineligibility {
+    fn description(self) -> &'static str {
+        match self {
+            Self::UnknownAthlete => "athlète inconnu",
+            Self::License => "pas de licence pour la saison",
+            Self::MedicalCertificate => "pas de certificat médical en compétition",
+            Self::Category => "catégorie non admise",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct CompetitionStatus {
+    competition: String,
+    start_date: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deadline: Option<u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    categories: Vec<Category>,
+    athletes: Vec<AthleteStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct AthleteStatus {
+    user_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    first_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<Category>,
+    ineligibilities: Vec<Ineligibility>,
+}
+
+impl CompetitionRegistrations {
+    fn key(&self) -> String {
+        let name = self
+            .competition
+            .to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|it| !it.is_empty())
+            .collect::<Vec<_>>()
+            .join("-");
+        format!("crg/{}_{name}", self.start_date)
+    }
+
+    fn deadline(&self) -> u32 {
+        self.deadline.unwrap_or(self.start_date)
+    }
+
+    /// The competition must start after today but not too far ahead, and registrations must
+    /// close before it starts.
+    fn has_valid_dates(&self, today: u32) -> bool {
+        if !is_valid_date(self.start_date)
+            || !self.deadline.is_none_or(is_valid_date)
+            || self.deadline() > self.start_date
+        {
+            return false;
+        }
+        let days_before_start = days_from_date(self.start_date) - days_from_date(today);
+        (1..=MAX_DAYS_BEFORE_START).contains(&days_before_start)
+    }
+
+    /// Lists the reasons why the athlete cannot compete, none if they are eligible.
+    pub(crate) fn ineligibilities(&self, user: Option<&User>) -> Vec<Ineligibility> {
+        let Some(user) = user else {
+            return vec![Ineligibility::UnknownAthlete];
+        };
+        let season = season_of_date(self.start_date);
+        let metadata = user
+            .metadata
+            .as_ref()
+            .and_then(|it| serde_json::from_value::<Metadata>(it.clone()).ok())
+            .unwrap_or_default();
+        let mut ineligibilities = Vec::new();
+        if metadata.latest_license_season != Some(season) {
+            ineligibilities.push(Ineligibility::License);
+        }
+        if metadata.medical_certificate_status != Some(MedicalCertificateStatus::Competition) {
+            ineligibilities.push(Ineligibility::MedicalCertificate);
+        }
+        if !self.categories.is_empty()
+            && !self
+                .categories
+                .contains(&Category::from_dob(user.date_of_birth, season))
+        {
+            ineligibilities.push(Ineligibility::Category);
+        }
+        ineligibilities
+    }
+
+    fn status(self, snapshot: &Snapshot) -> CompetitionStatus {
+        let season = season_of_date(self.start_date);
+        let athletes = self
+            .athletes
+            .iter()
+            .map(|athlete| {
+                let user = snapshot.get::<User>(&format!("acc/{}", athlete.user_id));
+                AthleteStatus {
+                    user_id: athlete.user_id.clone(),
+                    first_name: user.as_ref().map(|it| it.first_name.clone()),
+                    last_name: user.as_ref().map(|it| it.last_name.clone()),
+                    category: user
+                        .as_ref()
+                        .map(|it| Category::from_dob(it.date_of_birth, season)),
+                    ineligibilities: self.ineligibilities(user.as_ref()),
+                }
+            })
+            .collect();
+        CompetitionStatus {
+            competition: self.competition,
+            start_date: self.start_date,
+            deadline: self.deadline,
+            categories: self.categories,
+            athletes,
+        }
+    }
+}
+
+/// Lists the registrations for the competitions that have not started yet.
+pub(crate) fn registrations(snapshot: &Snapshot, today: u32) -> Vec<CompetitionStatus> {
+    snapshot
+        .list::<CompetitionRegistrations>("crg/")
+        .map(|(_, it)| it)
+        .filter(|it| it.start_date >= today && !it.athletes.is_empty())
+        .map(|it| it.status(snapshot))
+        .collect()
+}
+
+/// Records the athletes registered for a competition.
+///
+/// The admin recording the registrations is added to the coaches of the competition.
+pub(crate) async fn update_registrations(
+    snapshot: &Snapshot,
+    update: RegistrationsUpdate,
+    admin: &User,
+    today: u32,
+) -> Result<CompetitionStatus, RegistrationError> {
+    let mut registrations = CompetitionRegistrations {
+        competition: update.competition.trim().to_string(),
+        start_date: update.start_date,
+        deadline: update.deadline,
+        categories: update.categories,
+        athletes: vec![],
+        coaches: vec![],
+    };
+    if registrations.competition.is_empty() || !registrations.has_valid_dates(today) {
+        return Err(RegistrationError::InvalidRequest);
+    }
+    let key = registrations.key();
+    let before = snapshot.get::<CompetitionRegistrations>(&key);
+    if let Some(before) = before.as_ref() {
+        registrations.coaches = before.coaches.clone();
+    }
+    let admin_id = admin.id.to_string();
+    if !registrations.coaches.contains(&admin_id) {
+        registrations.coaches.push(admin_id);
+    }
+    // keep the alert flags, unless the deadline changed
+    let previous = before
+        .as_ref()
+        .filter(|it| it.deadline == registrations.deadline)
+        .map(|it| it.athletes.as_slice())
+        .unwrap_or_default();
+    for user_id in update.user_ids {
+        if registrations
+            .athletes
+            .iter()
+            .any(|it| it.user_id == user_id)
+        {
+            continue;
+        }
+        let alerted = previous
+            .iter()
+            .any(|it| it.user_id == user_id && it.alerted);
+        registrations
+            .athletes
+            .push(RegisteredAthlete { user_id, alerted });
+    }
+    Snapshot::set_and_wait_for_update(&key, &registrations)
+        .await
+        .ok_or(RegistrationError::Storage)?;
+    record(&Actor::admin(admin), &key, before.as_ref(), &registrations).await;
+    Ok(registrations.status(snapshot))
+}
+
+/// Emails the coaches about the athletes that are not eligible when the registration deadline is near.
+///
+/// Each athlete is only reported once per competition.
+pub async fn alert_ineligible_registrations(snapshot: &Snapshot, today: u32) -> Option<()> {
+    let today_days = days_from_date(today);
+    let mut alerts = Vec::new();
+    for (key, mut registrations) in snapshot.list::<CompetitionRegistrations>("crg/") {
+        let days_left = days_from_date(registrations.deadline()) - today_days;
+        if !(0..=ALERT_DAYS_BEFORE_DEADLINE).contains(&days_left) {
+            continue;
+        }
+        let mut lines = Vec::new();
+        for i in 0..registrations.athletes.len() {
+            if registrations.athletes[i].alerted {
+                continue;
+            }
+            let user = snapshot.get::<User>(&format!("acc/{}", registrations.athletes[i].user_id));
+            let ineligibilities = registrations.ineligibilities(user.as_ref());
+            if ineligibilities.is_empty() {
+                continue;
+            }
+            lines.push((
+                user.map(|it| format!("{} {}", it.first_name, it.last_name))
+                    .unwrap_or_else(|| registrations.athletes[i].user_id.clone()),
+                ineligibilities,
+            ));
+            registrations.athletes[i].alerted = true;
+        }
+        if !lines.is_empty() {
+            alerts.push((key, registrations, lines));
+        }
+    }
+    if alerts.is_empty() {
+        return Some(());
+    }
+    // each coach gets a single email for the competitions they registered athletes for,
+    // the admins get the alerts for the competitions without any coach that can be reached
+    let mut addresses = Vec::<(String, Vec<usize>)>::new();
+    let mut unassigned = Vec::new();
+    for (i, (_, registrations, _)) in alerts.iter().enumerate() {
+        let mut reachable = false;
+        for coach in registrations.coaches.iter() {
+            let Some(address) = snapshot
+                .get::<User>(&format!("acc/{coach}"))
+                .and_then(|it| it.email().map(|it| it.to_string()))
+            else {
+                continue;
+            };
+            reachable = true;
+            match addresses.iter_mut().find(|(it, _)| *it == address) {
+                Some((_, competitions)) => competitions.push(i),
+                None => addresses.push((address, vec![i])),
+            }
+        }
+        if !reachable {
+            unassigned.push(i);
+        }
+    }
+    let describe = |competitions: &[usize]| {
+        describe_alerts(competitions.iter().map(|&i| {
+            let (_, registrations, lines) = &alerts[i];
+            (registrations, lines.as_slice())
+        }))
+    };
+    let mut alerted = vec![false; alerts.len()];
+    for (address, competitions) in addresses.iter() {
+        if send_email(address, SUBJECT, &describe(competitions))
+            .await
+            .is_some()
+        {
+            competitions.iter().for_each(|&i| alerted[i] = true);
+        } else {
+            warn!("failed to send \"{SUBJECT}\" to {address}");
+        }
+    }
+    if !unassigned.is_empty() && notify_admins(snapshot, SUBJECT, &describe(&unassigned)).await > 0
+    {
+        unassigned.iter().for_each(|&i| alerted[i] = true);
+    }
+    let count = alerted.iter().filter(|it| **it).count();
+    if count == 0 {
+        return None;
+    }
+    info!("alerted coaches about {count} competition(s)");
+    for ((key, registrations, _), alerted) in alerts.into_iter().zip(alerted) {
+        if alerted {
+            Snapshot::set_and_wait_for_update(&key, &registrations).await?;
+        }
+    }
+    Some(())
+}
+
+fn format_date(date: u32) -> String {
+    format!(
+        "{:02}/{:02}/{}",
+        date % 1_00,
+        date / 1_00 % 1_00,
+        date / 1_00_00
+    )
+}
+
+fn describe_alerts<'a>(
+    alerts: impl Iterator<
+        Item = (
+            &'a CompetitionRegistrations,
+            &'a [(String, Vec<Ineligibility>)],
+        ),
+    >,
+) -> String {
+    let mut text =
+        String::from("Les athlètes suivants ne remplissent pas les conditions pour participer :\n");
+    for (registrations, lines) in alerts {
+        let _ = writeln!(
+            text,
+            "\n{} ({}, inscriptions jusqu'au {}) :",
+            registrations.competition,
+            format_date(registrations.start_date),
+            format_date(registrations.deadline())
+        );
+        for (name, ineligibilities) in lines {
+            let _ = writeln!(
+                text,
+                "- {name} : {}",
+                ineligibilities
+                    .iter()
+                    .map(|it| it.description())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user::test_user;
+
+    #[test]
+    fn test_ineligibilities() {
+        let registrations = CompetitionRegistrations {
+            competition: "Coupe régionale de bloc".to_string(),
+            start_date: 20250118,
+            deadline: Some(20250110),
+            categories: vec![Category::U14, Category::U16],
+            athletes: vec![],
+            coaches: vec![],
+        };
+        assert_eq!("crg/20250118_coupe-régionale-de-bloc", registrations.key());
+        assert!(registrations.has_valid_dates(20241201));
+        assert!(!registrations.has_valid_dates(20250118));
+        assert!(!registrations.has_valid_dates(20220101));
+        assert!(!CompetitionRegistrations {
+            deadline: Some(20250120),
+            ..registrations.clone()
+        }
+        .has_valid_dates(20241201));
+        assert!(!CompetitionRegistrations {
+            deadline: Some(20250132),
+            ..registrations.clone()
+        }
+        .has_valid_dates(20241201));
+        assert_eq!(
+            vec![Ineligibility::UnknownAthlete],
+            registrations.ineligibilities(None)
+        );
+        let eligible = test_user(
+            "Alice",
+            "Martin",
+            20110305,
+            Metadata {
+                latest_license_season: Some(2025),
+                medical_certificate_status: Some(MedicalCertificateStatus::Competition),
+                ..Default::default()
+            },
+        );
+        assert!(registrations.ineligibilities(Some(&eligible)).is_empty());
+        let ineligible = test_user(
+            "Alice",
+            "Martin",
+            20070305,
+            Metadata {
+                latest_license_season: Some(2024),
+                medical_certificate_status: Some(MedicalCertificateStatus::Recreational),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            vec![
+                Ineligibility::License,
+                Ineligibility::MedicalCertificate,
+                Ineligibility::Category
+            ],
+            registrations.ineligibilities(Some(&ineligible))
+        );
+        let lines = vec![(
+            "Bob Durand".to_string(),
+            vec![Ineligibility::MedicalCertificate],
+        )];
+        assert_eq!(
+            "Les athlètes suivants ne remplissent pas les conditions pour participer :\n\n\
+            Coupe régionale de bloc (18/01/2025, inscriptions jusqu'au 10/01/2025) :\n\
+            - Bob Durand : pas de certificat médical en compétition\n",
+            describe_alerts([(&registrations, lines.as_slice())].into_iter())
+        );
+    }
+}
@@ -1,9 +1,17 @@
 use crate::http_client::html_client;
-use crate::myffme::{Competition, CompetitionResult};
+use crate::metrics::{timed, Upstream};
+use crate::myffme::{Competition, CompetitionLevel, CompetitionResult, Discipline, ResultStatus};
+use crate::status::{record_scraped_page, ScrapedPage, ScrapedTable};
 use reqwest::Url;
 use scraper::{Html, Selector};
 use tracing::warn;
 
+/// Headers that should always be on the palmarès page.
+const REQUIRED_HEADERS: [&str; 4] = ["Saison", "Compétition", "Catégorie", "Rang"];
+
+/// Markers following a rank shared with other climbers, "=" can also precede the rank.
+const TIED_MARKERS: [&str; 5] = ["ex aequo", "ex-aequo", "exaequo", "ex", "="];
+
 pub async fn competition_results(license_number: u32) -> Option<Vec<CompetitionResult>> {
     let client = html_client();
     let request = client
@@ -15,8 +23,7 @@ pub async fn competition_results(license_number: u32) -> Option<Vec<CompetitionR
         )
         .build()
         .ok()?;
-    let response = client
-        .execute(request)
+    let response = timed(Upstream::MyCompet, client.execute(request))
         .await
         .inspect_err(|err| warn!("{err:?}"))
         .ok()?;
@@ -25,7 +32,86 @@ pub async fn competition_results(license_number: u32) -> Option<Vec<CompetitionR
         return None;
     }
     let text = response.text().await.ok()?;
-    let document = Html::parse_document(text.as_str());
+    let mut table = ScrapedTable::default();
+    let results = parse_competition_results(text.as_str(), &mut table);
+    // licensees without results have no table
+    if !table.found_headers.is_empty() {
+        record_scraped_page(ScrapedPage::CompetitionResults, &REQUIRED_HEADERS, &table);
+        // the results would be saved without the missing values
+        if let Some(header) = REQUIRED_HEADERS
+            .iter()
+            .find(|it| !table.found_headers.contains(*it))
+        {
+            warn!("ignoring competition results for license number {license_number}, missing {header} column");
+            return None;
+        }
+    }
+    results
+}
+
+struct Rank {
+    rank: Option<u16>,
+    tied: bool,
+    status: Option<ResultStatus>,
+    participants: Option<u16>,
+}
+
+/// Parses "3", "3/45" (with the number of participants), "3 ex aequo", "=3", "DNS", ...
+///
+/// Returns None if the text is not recognized.
+fn parse_rank(text: &str) -> Option<Rank> {
+    // the rank is sometimes followed by the number of participants: "3/45"
+    let (text, participants) = match text.split_once('/') {
+        Some((rank, total)) => (rank.trim(), total.trim().parse::<u16>().ok()),
+        None => (text.trim(), None),
+    };
+    let lowercase = text.to_lowercase();
+    let status = match lowercase.trim_end_matches('.') {
+        "dns" | "abs" | "absent" | "np" | "non partant" | "forfait" => {
+            Some(ResultStatus::DidNotStart)
+        }
+        "dnf" | "ab" | "abd" | "abandon" => Some(ResultStatus::DidNotFinish),
+        it if it == "dsq" || it.starts_with("disq") => Some(ResultStatus::Disqualified),
+        _ => None,
+    };
+    if status.is_some() {
+        return Some(Rank {
+            rank: None,
+            tied: false,
+            status,
+            participants,
+        });
+    }
+    let (text_after_marker, tied_before) = match lowercase.strip_prefix('=') {
+        Some(it) => (it.trim_start(), true),
+        None => (lowercase.as_str(), false),
+    };
+    let digits = text_after_marker
+        .chars()
+        .take_while(|it| it.is_ascii_digit())
+        .collect::<String>();
+    let tied = tied_before || TIED_MARKERS.contains(&text_after_marker[digits.len()..].trim());
+    let rank = digits.parse::<u16>().ok();
+    if rank.is_none() && !tied && !text.is_empty() {
+        return None;
+    }
+    Some(Rank {
+        rank,
+        tied,
+        status: None,
+        participants,
+    })
+}
+
+/// Parses the palmarès table.
+///
+/// Returns None when the table or the season and competition columns are missing.
+/// Rows that can't be parsed are skipped, and unknown ranks are flagged but kept.
+pub(crate) fn parse_competition_results(
+    html: &str,
+    table_report: &mut ScrapedTable,
+) -> Option<Vec<CompetitionResult>> {
+    let document = Html::parse_document(html);
     let table = document
         .select(&Selector::parse("#resultats-content .index-table").unwrap())
         .next()?;
@@ -33,18 +119,32 @@ pub async fn competition_results(license_number: u32) -> Option<Vec<CompetitionR
     let mut competition_name_index = None;
     let mut category_name_index = None;
     let mut rank_index = None;
+    let mut date_index = None;
+    let mut discipline_index = None;
+    let mut level_index = None;
+    let mut location_index = None;
+    let mut participants_index = None;
     for (i, header) in table
         .select(&Selector::parse("thead tr:first-of-type :is(td,th)").unwrap())
         .enumerate()
     {
         let text = header.text().collect::<String>();
-        match text.as_str() {
-            "Saison" => season_index = Some(i),
-            "Compétition" => competition_name_index = Some(i),
-            "Catégorie" => category_name_index = Some(i),
-            "Rang" => rank_index = Some(i),
-            _ => {}
+        let (index, name) = match text.trim() {
+            "Saison" => (&mut season_index, "Saison"),
+            "Compétition" => (&mut competition_name_index, "Compétition"),
+            "Catégorie" => (&mut category_name_index, "Catégorie"),
+            "Rang" => (&mut rank_index, "Rang"),
+            "Date" => (&mut date_index, "Date"),
+            "Discipline" | "Épreuve" => (&mut discipline_index, "Discipline"),
+            "Niveau" | "Type" => (&mut level_index, "Niveau"),
+            "Lieu" | "Ville" => (&mut location_index, "Lieu"),
+            "Participants" | "Classés" | "Nb participants" => {
+                (&mut participants_index, "Participants")
+            }
+            _ => continue,
         };
+        *index = Some(i);
+        table_report.found_headers.push(name);
     }
     if season_index.is_none() {
         warn!("failed to find column header for competition season");
@@ -58,81 +158,149 @@ pub async fn competition_results(license_number: u32) -> Option<Vec<CompetitionR
     let competition_name_index = competition_name_index.unwrap();
     if category_name_index.is_none() {
         warn!("failed to find column header for competition category");
-        return None;
     }
-    let category_name_index = category_name_index.unwrap();
     if rank_index.is_none() {
         warn!("failed to find column header for competition rank");
-        return None;
     }
-    let rank_index = rank_index.unwrap();
     let mut results = Vec::new();
     for row in table.select(&Selector::parse("tbody tr").unwrap()) {
         let mut season = None;
         let mut competition_name = None;
         let mut category_name = None;
         let mut rank = None;
+        let mut date = None;
+        let mut discipline = None;
+        let mut level = None;
+        let mut location = None;
+        let mut participants = None;
+        let mut flagged = false;
         for (i, col) in row.select(&Selector::parse("td").unwrap()).enumerate() {
             if i == season_index {
                 let text = col.text().map(|it| it.trim()).collect::<String>();
                 let mut split = text.split('-');
                 let year = split.next();
-                if year.is_none() || split.next().is_none() || split.next().is_some() {
-                    warn!("failed to parse season");
-                    return None;
-                }
-                let year = year.unwrap().parse::<u16>();
-                if year.is_err() {
-                    warn!("failed to parse season");
-                    return None;
+                if year.is_some() && split.next().is_some() && split.next().is_none() {
+                    season = year.unwrap().parse::<u16>().ok();
                 }
-                season = year.ok();
             } else if i == competition_name_index {
                 competition_name = Some(col.text().collect::<String>().trim().to_string())
-            } else if i == category_name_index {
+                    .filter(|it| !it.is_empty());
+            } else if Some(i) == category_name_index {
                 category_name = Some(col.text().collect::<String>().trim().to_string())
-            } else if i == rank_index {
+            } else if Some(i) == rank_index {
                 let text = col.text().map(|it| it.trim()).collect::<String>();
-                let n = text.parse::<u16>();
-                if n.is_err() {
-                    warn!("failed to parse rank");
-                    return None;
+                rank = parse_rank(&text);
+                if rank.is_none() {
+                    warn!("failed to parse rank: {text}");
+                    flagged = true;
+                }
+            } else if Some(i) == date_index {
+                date = parse_date(col.text().collect::<String>().trim());
+            } else if Some(i) == discipline_index {
+                discipline = parse_discipline(&col.text().collect::<String>());
+            } else if Some(i) == level_index {
+                level = parse_level(&col.text().collect::<String>());
+            } else if Some(i) == location_index {
+                let text = col.text().collect::<String>();
+                let text = text.trim();
+                if !text.is_empty() {
+                    location = Some(text.to_string());
+                }
+            } else if Some(i) == participants_index {
+                let text = col.text().map(|it| it.trim()).collect::<String>();
+                if let Ok(n) = text.parse::<u16>() {
+                    participants = Some(n);
                 }
-                rank = n.ok();
             }
         }
-        if season.is_none() {
-            warn!("failed to find column for competition season");
-            return None;
-        }
-        let season = season.unwrap();
-        if competition_name.is_none() {
-            warn!("failed to find column for competition name");
-            return None;
-        }
-        let competition_name = competition_name.unwrap();
-        if category_name.is_none() {
-            warn!("failed to find column for competition category");
-            return None;
-        }
-        let category_name = category_name.unwrap();
-        if rank.is_none() {
-            warn!("failed to find column for competition rank");
-            return None;
+        let Some(season) = season else {
+            warn!("skipping result row without season");
+            table_report.skipped_rows += 1;
+            continue;
+        };
+        let Some(competition_name) = competition_name else {
+            warn!("skipping result row without competition name");
+            table_report.skipped_rows += 1;
+            continue;
+        };
+        if flagged {
+            table_report.flagged_rows += 1;
         }
-        let rank = rank.unwrap();
+        let Rank {
+            rank,
+            tied,
+            status,
+            participants: total,
+        } = rank.unwrap_or(Rank {
+            rank: None,
+            tied: false,
+            status: None,
+            participants: None,
+        });
+        // competition names usually include the discipline and the level
+        let discipline = discipline.or_else(|| parse_discipline(&competition_name));
+        let level = level.or_else(|| parse_level(&competition_name));
         results.push(CompetitionResult {
             competition: Competition {
                 season,
                 name: competition_name,
+                date,
+                discipline,
+                level,
+                location,
             },
-            category_name,
+            category_name: category_name.unwrap_or_default(),
             rank,
+            tied,
+            status,
+            participants: total.or(participants),
         });
     }
     Some(results)
 }
 
+/// Parses the first date of "dd/mm/yyyy" or "dd/mm/yyyy - dd/mm/yyyy" as yyyymmdd.
+pub(crate) fn parse_date(text: &str) -> Option<u32> {
+    let mut split = text.get(..10)?.split('/');
+    let day = split.next()?.parse::<u32>().ok()?;
+    let month = split.next()?.parse::<u32>().ok()?;
+    let year = split.next()?.parse::<u32>().ok()?;
+    if day == 0 || day > 31 || month == 0 || month > 12 {
+        return None;
+    }
+    Some(year * 1_00_00 + month * 1_00 + day)
+}
+
+pub(crate) fn parse_discipline(text: &str) -> Option<Discipline> {
+    let text = text.to_lowercase();
+    if text.contains("combin") {
+        Some(Discipline::Combined)
+    } else if text.contains("bloc") {
+        Some(Discipline::Bouldering)
+    } else if text.contains("difficult") {
+        Some(Discipline::Lead)
+    } else if text.contains("vitesse") {
+        Some(Discipline::Speed)
+    } else {
+        None
+    }
+}
+
+pub(crate) fn parse_level(text: &str) -> Option<CompetitionLevel> {
+    let text = text.to_lowercase();
+    if text.contains("international") || text.contains("coupe du monde") {
+        Some(CompetitionLevel::International)
+    } else if text.contains("national") || text.contains("de france") {
+        Some(CompetitionLevel::National)
+    } else if text.contains("régional") || text.contains("regional") {
+        Some(CompetitionLevel::Regional)
+    } else if text.contains("départemental") || text.contains("departemental") {
+        Some(CompetitionLevel::Departmental)
+    } else {
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -145,7 +313,124 @@ mod tests {
             .into_iter()
             .find(|it| it.competition.season == 2021)
             .unwrap();
-        assert_eq!(result.rank, 1);
+        assert_eq!(result.rank, Some(1));
         assert_eq!(result.category_name, "VETERAN");
     }
+
+    #[test]
+    fn test_parse_competition_results() {
+        let html = r#"
+<html><body><div id="resultats-content"><table class="index-table">
+<thead><tr>
+<th>Saison</th><th>Date</th><th>Compétition</th><th>Lieu</th><th>Discipline</th><th>Catégorie</th><th>Rang</th>
+</tr></thead>
+<tbody>
+<tr>
+<td>2023-2024</td><td>18/11/2023 - 19/11/2023</td><td>Championnat régional</td><td>Poitiers</td>
+<td>Bloc</td><td>U16</td><td>3/24</td>
+</tr>
+<tr>
+<td>2022-2023</td><td>04/02/2023</td><td>Open départemental de difficulté</td><td></td>
+<td></td><td>U14</td><td>1</td>
+</tr>
+<tr>
+<td>2022-2023</td><td>21/01/2023</td><td>Coupe régionale de vitesse</td><td>Limoges</td>
+<td>Vitesse</td><td>U14</td><td>5 ex aequo</td>
+</tr>
+<tr>
+<td>2022-2023</td><td>10/12/2022</td><td>Open de Niort</td><td>Niort</td>
+<td>Bloc</td><td>U14</td><td>DNS</td>
+</tr>
+<tr>
+<td>2022-2023</td><td>03/12/2022</td><td>Open de Poitiers</td><td>Poitiers</td>
+<td>Bloc</td><td>U14</td><td>?</td>
+</tr>
+<tr>
+<td></td><td></td><td></td><td></td><td></td><td></td><td></td>
+</tr>
+</tbody>
+</table></div></body></html>
+"#;
+        let mut table = ScrapedTable::default();
+        let results = parse_competition_results(html, &mut table).unwrap();
+        assert_eq!(5, results.len());
+        assert_eq!(1, table.skipped_rows);
+        assert_eq!(1, table.flagged_rows);
+        assert_eq!(
+            vec![
+                "Saison",
+                "Date",
+                "Compétition",
+                "Lieu",
+                "Discipline",
+                "Catégorie",
+                "Rang"
+            ],
+            table.found_headers
+        );
+        let first = &results[0];
+        assert_eq!(2023, first.competition.season);
+        assert_eq!("Championnat régional", first.competition.name);
+        assert_eq!(Some(20231118), first.competition.date);
+        assert_eq!(Some("Poitiers"), first.competition.location.as_deref());
+        assert_eq!(Some(Discipline::Bouldering), first.competition.discipline);
+        assert_eq!(Some(CompetitionLevel::Regional), first.competition.level);
+        assert_eq!("U16", first.category_name);
+        assert_eq!(Some(3), first.rank);
+        assert!(!first.tied);
+        assert_eq!(Some(24), first.participants);
+        let second = &results[1];
+        assert_eq!(Some(20230204), second.competition.date);
+        assert_eq!(None, second.competition.location);
+        assert_eq!(Some(Discipline::Lead), second.competition.discipline);
+        assert_eq!(
+            Some(CompetitionLevel::Departmental),
+            second.competition.level
+        );
+        assert_eq!(Some(1), second.rank);
+        assert_eq!(None, second.participants);
+        assert_eq!(None, second.relative_rank());
+        assert_eq!(Some(2.0 / 23.0), first.relative_rank());
+        assert_eq!(Some(5), results[2].rank);
+        assert!(results[2].tied);
+        assert_eq!(None, results[3].rank);
+        assert_eq!(Some(ResultStatus::DidNotStart), results[3].status);
+        assert_eq!(None, results[4].rank);
+        assert_eq!(None, results[4].status);
+    }
+
+    #[test]
+    fn test_parse_rank() {
+        for (text, rank, tied) in [
+            ("3", Some(3), false),
+            ("3 ex aequo", Some(3), true),
+            ("3 ex-aequo", Some(3), true),
+            ("3ex", Some(3), true),
+            ("=3", Some(3), true),
+            ("3=", Some(3), true),
+            ("3e", Some(3), false),
+            ("3e (exclu)", Some(3), false),
+        ] {
+            let parsed = parse_rank(text).unwrap();
+            assert_eq!(rank, parsed.rank, "{text}");
+            assert_eq!(tied, parsed.tied, "{text}");
+        }
+        assert!(parse_rank("?").is_none());
+    }
+
+    #[test]
+    fn test_parse_competition_results_without_rank() {
+        let html = r#"
+<html><body><div id="resultats-content"><table class="index-table">
+<thead><tr><th>Saison</th><th>Compétition</th><th>Classement</th></tr></thead>
+<tbody><tr><td>2023-2024</td><td>Championnat régional</td><td>3</td></tr></tbody>
+</table></div></body></html>
+"#;
+        let mut table = ScrapedTable::default();
+        let results = parse_competition_results(html, &mut table).unwrap();
+        assert_eq!(1, results.len());
+        assert_eq!(None, results[0].rank);
+        assert_eq!("", results[0].category_name);
+        assert_eq!(vec!["Saison", "Compétition"], table.found_headers);
+    }
 }
@@ -1,6 +1,6 @@
 use crate::http_client::json_client;
 use crate::myffme::licensee::{address, user_data};
-use crate::myffme::MYFFME_AUTHORIZATION;
+use crate::myffme::send_with_authorization;
 use hyper::header::{HeaderValue, AUTHORIZATION, ORIGIN, REFERER};
 use reqwest::Url;
 use serde::{Deserialize, Deserializer, Serialize};
@@ -118,23 +118,18 @@ pub(crate) async fn update_address_city(
     ))
     .unwrap();
     let client = json_client();
-    let response = client
-        .patch(url.as_str())
-        .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
-        .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
-        .header(
-            AUTHORIZATION,
-            MYFFME_AUTHORIZATION
-                .get_ref()
-                .map(|it| it.bearer_token.clone())?,
-        )
-        .json(&json!({
-            "city": city,
-            "zipcode": zip_code,
-        }))
-        .send()
-        .await
-        .ok()?;
+    let response = send_with_authorization(|bearer_token| {
+        client
+            .patch(url.as_str())
+            .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
+            .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
+            .header(AUTHORIZATION, bearer_token)
+            .json(&json!({
+                "city": city,
+                "zipcode": zip_code,
+            }))
+    })
+    .await?;
     #[cfg(test)]
     let success = {
         println!("address city");
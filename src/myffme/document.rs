@@ -0,0 +1,70 @@
+use crate::http_client::json_client;
+use crate::myffme::send_with_authorization;
+use hyper::header::{HeaderValue, AUTHORIZATION, ORIGIN, REFERER};
+use reqwest::multipart::{Form, Part};
+use reqwest::Url;
+#[cfg(test)]
+use tokio::io::AsyncWriteExt;
+
+/// `ID_Type_Document` of a medical certificate in MyFFME.
+pub(crate) const MEDICAL_CERTIFICATE_DOCUMENT_TYPE: u8 = 5;
+
+/// Uploads a document to the MyFFME documents of the member for the season.
+pub(crate) async fn upload_document(
+    myffme_user_id: &str,
+    season: u16,
+    category: u8,
+    file_name: &str,
+    content_type: &str,
+    bytes: &[u8],
+) -> Option<()> {
+    let url = Url::parse("https://api.core.myffme.fr/api/documents").unwrap();
+    let client = json_client();
+    let response = send_with_authorization(|bearer_token| {
+        let part = Part::bytes(bytes.to_vec())
+            .file_name(file_name.to_string())
+            .mime_str(content_type)
+            .unwrap();
+        client
+            .post(url.as_str())
+            .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
+            .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
+            .header(AUTHORIZATION, bearer_token)
+            .multipart(
+                Form::new()
+                    .text("user", format!("/api/user_datas/{myffme_user_id}"))
+                    .text("season", season.to_string())
+                    .text("documentType", category.to_string())
+                    .part("file", part),
+            )
+    })
+    .await?;
+    #[cfg(test)]
+    let success = {
+        println!("document");
+        println!("POST {}", url.as_str());
+        let success = response.status().is_success();
+        println!("{}", response.status());
+        let text = response.text().await.ok()?;
+        let file_name = format!(".api/.upload_document_{myffme_user_id}.json");
+        tokio::fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(&file_name)
+            .await
+            .ok()?
+            .write_all(text.as_bytes())
+            .await
+            .unwrap();
+        success
+    };
+    #[cfg(not(test))]
+    let success = response.status().is_success();
+    if success {
+        Some(())
+    } else {
+        tracing::warn!("failed to upload document");
+        None
+    }
+}
@@ -1,5 +1,5 @@
 use crate::http_client::json_client;
-use crate::myffme::MYFFME_AUTHORIZATION;
+use crate::myffme::send_with_authorization;
 use hyper::header::{HeaderValue, AUTHORIZATION, ORIGIN, REFERER};
 use reqwest::Url;
 use serde_json::json;
@@ -16,23 +16,18 @@ pub(crate) async fn update_email(
     ))
     .unwrap();
     let client = json_client();
-    let response = client
-        .patch(url.as_str())
-        .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
-        .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
-        .header(
-            AUTHORIZATION,
-            MYFFME_AUTHORIZATION
-                .get_ref()
-                .map(|it| it.bearer_token.clone())?,
-        )
-        .json(&json!({
-            "email": email,
-            "secondaryEmail": alt_email,
-        }))
-        .send()
-        .await
-        .ok()?;
+    let response = send_with_authorization(|bearer_token| {
+        client
+            .patch(url.as_str())
+            .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
+            .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
+            .header(AUTHORIZATION, bearer_token)
+            .json(&json!({
+                "email": email,
+                "secondaryEmail": alt_email,
+            }))
+    })
+    .await?;
     #[cfg(test)]
     let success = {
         println!("email");
@@ -3,14 +3,18 @@ use crate::http_client::json_client;
 use crate::myffme::address::Address;
 use crate::myffme::license::{deserialize_license_type, deserialize_product_option, ProductOption};
 use crate::myffme::{
-    Gender, LicenseType, MedicalCertificateStatus, MYFFME_AUTHORIZATION, STRUCTURE_ID,
+    send_with_authorization, Gender, LicenseRecord, LicenseType, MedicalCertificateStatus,
+    STRUCTURE_ID,
 };
+use crate::order::{InsuranceLevel, InsuranceOption};
 use crate::season::current_season;
-use hyper::header::{HeaderValue, AUTHORIZATION, ORIGIN, REFERER};
+use hyper::header::{HeaderValue, ACCEPT, AUTHORIZATION, ORIGIN, REFERER};
 use reqwest::Url;
 use serde::de::Error;
 use serde::Deserialize;
 use std::collections::BTreeSet;
+use std::sync::LazyLock;
+use tiered_server::env::{secret_value, ConfigurationKey};
 #[cfg(test)]
 use tokio::io::AsyncWriteExt;
 
@@ -141,68 +145,144 @@ pub(crate) struct Licensee {
     pub(crate) medical_certificate_status: MedicalCertificateStatus,
 }
 
-const PAST_SEASONS: usize = 4;
+const PAST_SEASONS_KEY: ConfigurationKey = ConfigurationKey::Other {
+    variable_name: "MYFFME_PAST_SEASONS",
+};
+
+/// Number of previous seasons to import licensees from.
+static PAST_SEASONS: LazyLock<u16> = LazyLock::new(|| {
+    secret_value(PAST_SEASONS_KEY)
+        .and_then(|it| {
+            it.parse::<u16>()
+                .inspect_err(|_| tracing::warn!("invalid number of past seasons: {it}"))
+                .ok()
+        })
+        .unwrap_or(4)
+});
+
+const ITEMS_PER_PAGE: usize = 500;
+
+/// Safety net against pagination loops, that's 10000 licences per season.
+const MAX_PAGES: u32 = 20;
+
+#[derive(Deserialize)]
+struct HydraView {
+    #[serde(rename = "hydra:next", alias = "next")]
+    next: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct HydraCollection<T> {
+    #[serde(rename = "hydra:member", alias = "member")]
+    member: Vec<T>,
+    #[serde(rename = "hydra:totalItems", alias = "totalItems")]
+    total_items: Option<usize>,
+    #[serde(rename = "hydra:view", alias = "view")]
+    view: Option<HydraView>,
+}
+
+/// The collection is a plain list when the hydra metadata is missing.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Page<T> {
+    Hydra(HydraCollection<T>),
+    List(Vec<T>),
+}
+
+impl<T> Page<T> {
+    /// Returns the items, the total number of items if known, and whether there is a next page.
+    fn into_parts(self, items_so_far: usize) -> (Vec<T>, Option<usize>, bool) {
+        match self {
+            Page::Hydra(collection) => {
+                let count = items_so_far + collection.member.len();
+                let has_next = match (collection.view, collection.total_items) {
+                    (Some(HydraView { next: Some(_) }), _) => true,
+                    (_, Some(total)) => count < total && !collection.member.is_empty(),
+                    _ => collection.member.len() == ITEMS_PER_PAGE,
+                };
+                (collection.member, collection.total_items, has_next)
+            }
+            Page::List(list) => {
+                let has_next = list.len() == ITEMS_PER_PAGE;
+                (list, None, has_next)
+            }
+        }
+    }
+}
 
 pub(crate) async fn licensees() -> Option<Vec<Licensee>> {
     let current_season = current_season(None);
     let mut licensees = Vec::new();
     let mut ids = BTreeSet::new();
-    let mut season = current_season;
-    for _ in 0..=PAST_SEASONS {
-        let mut url = Url::parse("https://api.core.myffme.fr/api/licences/unique").unwrap();
-        url.query_pairs_mut()
-            .append_pair("pagination", "true")
-            .append_pair("itemsPerPage", "500")
-            .append_pair("page", "1")
-            .append_pair("season", &season.to_string())
-            .append_pair("structure", &STRUCTURE_ID.to_string());
-        let client = json_client();
-        let request = client
-            .get(url.as_str())
-            .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
-            .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
-            .header(
-                AUTHORIZATION,
-                MYFFME_AUTHORIZATION
-                    .get_ref()
-                    .map(|it| it.bearer_token.clone())?,
-            )
-            .build()
-            .ok()?;
-        let response = client.execute(request).await.ok()?;
-        #[cfg(test)]
-        let list = {
-            println!("licenses");
-            println!("GET {}", url.as_str());
-            println!("{}", response.status());
-            let text = response.text().await.ok()?;
-            let file_name = format!(".api/.licenses_{season}.json");
-            tokio::fs::OpenOptions::new()
-                .write(true)
-                .truncate(true)
-                .create(true)
-                .open(&file_name)
-                .await
-                .ok()?
-                .write_all(text.as_bytes())
+    for season in (current_season.saturating_sub(*PAST_SEASONS)..=current_season).rev() {
+        let mut count = 0;
+        let mut page = 1;
+        loop {
+            let mut url = Url::parse("https://api.core.myffme.fr/api/licences/unique").unwrap();
+            url.query_pairs_mut()
+                .append_pair("pagination", "true")
+                .append_pair("itemsPerPage", &ITEMS_PER_PAGE.to_string())
+                .append_pair("page", &page.to_string())
+                .append_pair("season", &season.to_string())
+                .append_pair("structure", &STRUCTURE_ID.to_string());
+            let client = json_client();
+            let response = send_with_authorization(|bearer_token| {
+                client
+                    .get(url.as_str())
+                    .header(ACCEPT, HeaderValue::from_static("application/ld+json"))
+                    .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
+                    .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
+                    .header(AUTHORIZATION, bearer_token)
+            })
+            .await?;
+            #[cfg(test)]
+            let list = {
+                println!("licenses");
+                println!("GET {}", url.as_str());
+                println!("{}", response.status());
+                let text = response.text().await.ok()?;
+                let file_name = format!(".api/.licenses_{season}_{page}.json");
+                tokio::fs::OpenOptions::new()
+                    .write(true)
+                    .truncate(true)
+                    .create(true)
+                    .open(&file_name)
+                    .await
+                    .ok()?
+                    .write_all(text.as_bytes())
+                    .await
+                    .unwrap();
+                serde_json::from_str::<Page<Licensee>>(&text)
+                    .inspect_err(|err| eprintln!("{err:?}"))
+                    .ok()?
+            };
+            #[cfg(not(test))]
+            let list = response
+                .json::<Page<Licensee>>()
                 .await
-                .unwrap();
-            serde_json::from_str::<Vec<Licensee>>(&text)
-                .inspect_err(|err| eprintln!("{err:?}"))
-                .ok()?
-        };
-        #[cfg(not(test))]
-        let list = response
-            .json::<Vec<Licensee>>()
-            .await
-            .inspect_err(|err| tracing::warn!("{err:?}"))
-            .ok()?;
-        for it in list {
-            if ids.insert(it.myffme_user_id.clone()) {
-                licensees.push(it);
+                .inspect_err(|err| tracing::warn!("{err:?}"))
+                .ok()?;
+            let (list, total, has_next) = list.into_parts(count);
+            count += list.len();
+            for it in list {
+                if ids.insert(it.myffme_user_id.clone()) {
+                    licensees.push(it);
+                }
+            }
+            if !has_next {
+                if let Some(total) = total.filter(|&it| it > count) {
+                    tracing::warn!("only got {count} of {total} licences for season {season}");
+                }
+                break;
             }
+            if page == MAX_PAGES {
+                tracing::warn!(
+                    "stopped after {MAX_PAGES} pages of licences for season {season}, some licensees are missing"
+                );
+                break;
+            }
+            page += 1;
         }
-        season -= 1;
     }
     Some(licensees)
 }
@@ -213,19 +293,14 @@ pub(crate) async fn user_data(user_id: &str) -> Option<UserData> {
     ))
     .unwrap();
     let client = json_client();
-    let request = client
-        .get(url.as_str())
-        .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
-        .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
-        .header(
-            AUTHORIZATION,
-            MYFFME_AUTHORIZATION
-                .get_ref()
-                .map(|it| it.bearer_token.clone())?,
-        )
-        .build()
-        .ok()?;
-    let response = client.execute(request).await.ok()?;
+    let response = send_with_authorization(|bearer_token| {
+        client
+            .get(url.as_str())
+            .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
+            .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
+            .header(AUTHORIZATION, bearer_token)
+    })
+    .await?;
     #[cfg(test)]
     let data = {
         println!("user_data");
@@ -256,22 +331,93 @@ pub(crate) async fn user_data(user_id: &str) -> Option<UserData> {
     Some(data)
 }
 
+/// Searches the MyFFME users by licence number or by date of birth.
+///
+/// The filters are not documented by MyFFME and may be ignored, the results must be filtered by
+/// the caller. Only the first page is read, more results than a page means that the filters were
+/// ignored and nothing is returned.
+pub(crate) async fn search_user_data(
+    license_number: Option<u32>,
+    dob: Option<u32>,
+) -> Option<Vec<UserData>> {
+    let mut url = Url::parse("https://api.core.myffme.fr/api/user_datas").unwrap();
+    {
+        let mut query = url.query_pairs_mut();
+        query
+            .append_pair("pagination", "true")
+            .append_pair("itemsPerPage", &ITEMS_PER_PAGE.to_string())
+            .append_pair("page", "1");
+        if let Some(license_number) = license_number {
+            query.append_pair("licenceNumber", &license_number.to_string());
+        }
+        if let Some(dob) = dob {
+            query.append_pair(
+                "birthdate",
+                &format!(
+                    "{}-{:02}-{:02}",
+                    dob / 1_00_00,
+                    dob / 1_00 % 1_00,
+                    dob % 1_00
+                ),
+            );
+        }
+    }
+    let client = json_client();
+    let response = send_with_authorization(|bearer_token| {
+        client
+            .get(url.as_str())
+            .header(ACCEPT, HeaderValue::from_static("application/ld+json"))
+            .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
+            .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
+            .header(AUTHORIZATION, bearer_token)
+    })
+    .await?;
+    #[cfg(test)]
+    let list = {
+        println!("user_data search");
+        println!("GET {}", url.as_str());
+        println!("{}", response.status());
+        let text = response.text().await.ok()?;
+        let file_name = ".api/.user_data_search.json";
+        tokio::fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(file_name)
+            .await
+            .ok()?
+            .write_all(text.as_bytes())
+            .await
+            .unwrap();
+        serde_json::from_str::<Page<UserData>>(&text)
+            .inspect_err(|err| eprintln!("{err:?}"))
+            .ok()?
+    };
+    #[cfg(not(test))]
+    let list = response
+        .json::<Page<UserData>>()
+        .await
+        .inspect_err(|err| tracing::warn!("{err:?}"))
+        .ok()?;
+    let (users, _, has_next) = list.into_parts(0);
+    if has_next {
+        tracing::warn!("too many MyFFME users found, the search filters were ignored");
+        return Some(Vec::new());
+    }
+    Some(users)
+}
+
 pub(crate) async fn emergency_contact(path: &str) -> Option<EmergencyContact> {
     let url = Url::parse(&format!("https://api.core.myffme.fr{path}")).unwrap();
     let client = json_client();
-    let request = client
-        .get(url.as_str())
-        .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
-        .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
-        .header(
-            AUTHORIZATION,
-            MYFFME_AUTHORIZATION
-                .get_ref()
-                .map(|it| it.bearer_token.clone())?,
-        )
-        .build()
-        .ok()?;
-    let response = client.execute(request).await.ok()?;
+    let response = send_with_authorization(|bearer_token| {
+        client
+            .get(url.as_str())
+            .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
+            .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
+            .header(AUTHORIZATION, bearer_token)
+    })
+    .await?;
     #[cfg(test)]
     let data = {
         println!("emergency_contact");
@@ -306,19 +452,14 @@ pub(crate) async fn emergency_contact(path: &str) -> Option<EmergencyContact> {
 pub(crate) async fn license(path: &str) -> Option<License> {
     let url = Url::parse(&format!("https://api.core.myffme.fr{path}")).unwrap();
     let client = json_client();
-    let request = client
-        .get(url.as_str())
-        .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
-        .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
-        .header(
-            AUTHORIZATION,
-            MYFFME_AUTHORIZATION
-                .get_ref()
-                .map(|it| it.bearer_token.clone())?,
-        )
-        .build()
-        .ok()?;
-    let response = client.execute(request).await.ok()?;
+    let response = send_with_authorization(|bearer_token| {
+        client
+            .get(url.as_str())
+            .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
+            .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
+            .header(AUTHORIZATION, bearer_token)
+    })
+    .await?;
     #[cfg(test)]
     let data = {
         println!("license");
@@ -353,19 +494,14 @@ pub(crate) async fn license(path: &str) -> Option<License> {
 pub(crate) async fn address(path: &str) -> Option<Address> {
     let url = Url::parse(&format!("https://api.core.myffme.fr{path}")).unwrap();
     let client = json_client();
-    let request = client
-        .get(url.as_str())
-        .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
-        .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
-        .header(
-            AUTHORIZATION,
-            MYFFME_AUTHORIZATION
-                .get_ref()
-                .map(|it| it.bearer_token.clone())?,
-        )
-        .build()
-        .ok()?;
-    let response = client.execute(request).await.ok()?;
+    let response = send_with_authorization(|bearer_token| {
+        client
+            .get(url.as_str())
+            .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
+            .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
+            .header(AUTHORIZATION, bearer_token)
+    })
+    .await?;
     #[cfg(test)]
     let data = {
         println!("address");
@@ -491,11 +627,39 @@ pub(crate) struct License {
     pub(crate) medical_certificate_status: MedicalCertificateStatus,
 }
 
+impl License {
+    /// The insurance level and options chosen with the licence.
+    pub(crate) fn insurance(&self) -> (Option<InsuranceLevel>, Vec<InsuranceOption>) {
+        let mut insurance_level = None;
+        let mut insurance_options = Vec::new();
+        for it in self.options.iter() {
+            match &it.product_option {
+                ProductOption::InsuranceLevel(it) => insurance_level = Some(it.level),
+                ProductOption::InsuranceOption(it) => insurance_options.push(it.option),
+            }
+        }
+        (insurance_level, insurance_options)
+    }
+
+    pub(crate) fn record(&self, id: &str) -> LicenseRecord {
+        let (insurance_level, insurance_options) = self.insurance();
+        LicenseRecord {
+            id: id.to_string(),
+            season: self.season.season,
+            structure_id: self.structure.structure,
+            structure_name: self.structure.name.clone(),
+            license_type: self.product.product,
+            insurance_level,
+            insurance_options,
+            medical_certificate_status: self.medical_certificate_status,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::myffme::update_myffme_bearer_token;
-    use crate::order::InsuranceLevel;
+    use crate::myffme::{update_myffme_bearer_token, InsuranceLevelOption, InsuranceOptionOption};
     use std::time::SystemTime;
 
     #[tokio::test]
@@ -521,6 +685,29 @@ mod tests {
         assert_eq!("GRAS", result.last_name);
     }
 
+    #[test]
+    fn test_pages() {
+        let page = serde_json::from_str::<Page<u32>>(
+            r#"{
+                "hydra:member": [1, 2],
+                "hydra:totalItems": 5,
+                "hydra:view": { "hydra:next": "/api/licences/unique?page=2" }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!((vec![1, 2], Some(5), true), page.into_parts(0));
+        let page = serde_json::from_str::<Page<u32>>(
+            r#"{ "hydra:member": [5], "hydra:totalItems": 5, "hydra:view": {} }"#,
+        )
+        .unwrap();
+        assert_eq!((vec![5], Some(5), false), page.into_parts(4));
+        let page =
+            serde_json::from_str::<Page<u32>>(r#"{ "member": [3, 4], "totalItems": 5 }"#).unwrap();
+        assert_eq!((vec![3, 4], Some(5), true), page.into_parts(2));
+        let page = serde_json::from_str::<Page<u32>>("[1, 2, 3]").unwrap();
+        assert_eq!((vec![1, 2, 3], None, false), page.into_parts(0));
+    }
+
     #[tokio::test]
     async fn test_user_data() {
         assert!(update_myffme_bearer_token(0, None).await.is_some());
@@ -576,6 +763,47 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_license_insurance() {
+        let license = License {
+            season: SeasonWithId { season: 2026 },
+            structure: StructureWithId {
+                structure: 12345,
+                name: "Pierre Blanche".to_string(),
+            },
+            product: ProductWithId {
+                product: LicenseType::Adult,
+            },
+            options: vec![
+                OptionWrapper {
+                    product_option: ProductOption::InsuranceOption(InsuranceOptionOption {
+                        id: "92e7eebe-71cd-4258-b178-141587374b81".to_string(),
+                        option: InsuranceOption::Ski,
+                    }),
+                },
+                OptionWrapper {
+                    product_option: ProductOption::InsuranceLevel(InsuranceLevelOption {
+                        id: "a3a2d318-c8a5-410b-ac9d-1f07c1d69bdc".to_string(),
+                        level: InsuranceLevel::BasePlus,
+                    }),
+                },
+            ],
+            medical_certificate_status: MedicalCertificateStatus::Recreational,
+        };
+        assert_eq!(
+            (Some(InsuranceLevel::BasePlus), vec![InsuranceOption::Ski]),
+            license.insurance()
+        );
+        let record = license.record("0191f60f-f135-7ec4-a800-d3afbebc7ea7");
+        assert_eq!(Some(InsuranceLevel::BasePlus), record.insurance_level);
+        assert_eq!(vec![InsuranceOption::Ski], record.insurance_options);
+        let license = License {
+            options: vec![],
+            ..license
+        };
+        assert_eq!((None, vec![]), license.insurance());
+    }
+
     #[tokio::test]
     async fn test_address() {
         assert!(update_myffme_bearer_token(0, None).await.is_some());
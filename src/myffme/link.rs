@@ -0,0 +1,268 @@
+use crate::audit::{record, Actor};
+use crate::myffme::licensee::{search_user_data, user_data, UserData};
+use crate::user::Metadata;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use tiered_server::norm::{normalize_first_name, normalize_last_name};
+use tiered_server::store::Snapshot;
+use tiered_server::user::User;
+use tracing::info;
+
+/// Search for MyFFME licensees, either by licence number or by name and date of birth.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum LicenseeSearch {
+    LicenseNumber(u32),
+    NameAndDob {
+        first_name: Option<String>,
+        last_name: String,
+        dob: u32,
+    },
+}
+
+impl LicenseeSearch {
+    /// Reads `?license_number=...` or `?last_name=...&dob=yyyymmdd[&first_name=...]`.
+    pub(crate) fn from_query(query: Option<&str>) -> Option<Self> {
+        let url = Url::parse(&format!("https://localhost/?{}", query?)).ok()?;
+        let mut license_number = None;
+        let mut first_name = None;
+        let mut last_name = None;
+        let mut dob = None;
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "license_number" => license_number = Some(value.parse::<u32>().ok()?),
+                "first_name" => first_name = Some(value.trim().to_string()),
+                "last_name" => last_name = Some(value.trim().to_string()),
+                "dob" => dob = Some(value.parse::<u32>().ok()?),
+                _ => {}
+            }
+        }
+        if let Some(license_number) = license_number {
+            return Some(Self::LicenseNumber(license_number));
+        }
+        Some(Self::NameAndDob {
+            first_name: first_name.filter(|it| !it.is_empty()),
+            last_name: last_name.filter(|it| !it.is_empty())?,
+            dob: dob?,
+        })
+    }
+
+    /// Whether the MyFFME user matches the search, MyFFME may ignore the search filters.
+    fn matches(&self, user_data: &UserData) -> bool {
+        match self {
+            LicenseeSearch::LicenseNumber(license_number) => {
+                user_data.license_number == *license_number
+            }
+            LicenseeSearch::NameAndDob {
+                first_name,
+                last_name,
+                dob,
+            } => {
+                user_data.dob == *dob && matches_names(user_data, first_name.as_deref(), last_name)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct LicenseeMatch {
+    myffme_user_id: String,
+    first_name: String,
+    last_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    birth_name: Option<String>,
+    dob: u32,
+    license_number: u32,
+    /// The local account already linked to the licensee.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    linked_user_id: Option<String>,
+}
+
+fn metadata(user: &User) -> Metadata {
+    user.metadata
+        .as_ref()
+        .and_then(|it| serde_json::from_value::<Metadata>(it.clone()).ok())
+        .unwrap_or_default()
+}
+
+fn linked_user(snapshot: &Snapshot, myffme_user_id: &str) -> Option<User> {
+    snapshot
+        .list::<User>("acc/")
+        .map(|(_, it)| it)
+        .find(|it| metadata(it).myffme_user_id.as_deref() == Some(myffme_user_id))
+}
+
+/// Whether the licensee matches the first name and the last name or the birth name of the search.
+fn matches_names(user_data: &UserData, first_name: Option<&str>, last_name: &str) -> bool {
+    let last_name = normalize_last_name(last_name);
+    (normalize_last_name(&user_data.last_name) == last_name
+        || user_data
+            .birth_name
+            .as_deref()
+            .is_some_and(|it| normalize_last_name(it) == last_name))
+        && first_name.is_none_or(|it| {
+            normalize_first_name(it) == normalize_first_name(&user_data.first_name)
+        })
+}
+
+/// Searches MyFFME for licensees, with the local account they are linked to if any.
+///
+/// The last name is also compared to the birth name, for members that changed their name.
+pub(crate) async fn search_licensees(
+    snapshot: &Snapshot,
+    search: &LicenseeSearch,
+) -> Option<Vec<LicenseeMatch>> {
+    let results = match search {
+        LicenseeSearch::LicenseNumber(license_number) => {
+            search_user_data(Some(*license_number), None).await?
+        }
+        // MyFFME only filters by the current last name, search by date of birth
+        // and compare the names here.
+        LicenseeSearch::NameAndDob { dob, .. } => search_user_data(None, Some(*dob)).await?,
+    };
+    Some(
+        results
+            .into_iter()
+            .filter(|it| search.matches(it))
+            .map(|it| LicenseeMatch {
+                linked_user_id: linked_user(snapshot, &it.id).map(|it| it.id.to_string()),
+                myffme_user_id: it.id,
+                first_name: it.first_name,
+                last_name: it.last_name,
+                birth_name: it.birth_name,
+                dob: it.dob,
+                license_number: it.license_number,
+            })
+            .collect(),
+    )
+}
+
+/// Request body to link a local account to a MyFFME licensee, or to unlink it if the MyFFME id
+/// is missing.
+#[derive(Debug, Deserialize)]
+pub(crate) struct LinkRequest {
+    user_id: String,
+    myffme_user_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LinkError {
+    UnknownUser,
+    UnknownLicensee,
+    /// The licensee is already linked to another account.
+    AlreadyLinked,
+    /// The account was anonymised.
+    Archived,
+    Storage,
+}
+
+/// Links a local account to a MyFFME licensee, or unlinks it.
+///
+/// The data from MyFFME is filled by the next member sync. An unlinked licensee is not matched
+/// again automatically with the account. Archived accounts can't be linked or unlinked, and
+/// merged accounts no longer exist.
+pub(crate) async fn link_user(
+    snapshot: &Snapshot,
+    request: LinkRequest,
+    admin: &User,
+) -> Result<User, LinkError> {
+    let key = format!("acc/{}", request.user_id);
+    let mut user = snapshot.get::<User>(&key).ok_or(LinkError::UnknownUser)?;
+    let before = user.clone();
+    let metadata = metadata(&user);
+    if metadata.archived.is_some() {
+        return Err(LinkError::Archived);
+    }
+    let metadata = match request.myffme_user_id {
+        Some(myffme_user_id) => {
+            if linked_user(snapshot, &myffme_user_id).is_some_and(|it| it.id != user.id) {
+                return Err(LinkError::AlreadyLinked);
+            }
+            let user_data = user_data(&myffme_user_id)
+                .await
+                .ok_or(LinkError::UnknownLicensee)?;
+            info!(
+                "linking {} {} to licence {}",
+                user.first_name, user.last_name, user_data.license_number
+            );
+            let mut unlinked = metadata.unlinked_myffme_user_ids.unwrap_or_default();
+            unlinked.retain(|it| it != &user_data.id);
+            Metadata {
+                myffme_user_id: Some(user_data.id),
+                license_number: Some(user_data.license_number),
+                unlinked_myffme_user_ids: Some(unlinked).filter(|it| !it.is_empty()),
+                ..metadata
+            }
+        }
+        None => {
+            let Some(myffme_user_id) = metadata.myffme_user_id else {
+                return Ok(user);
+            };
+            info!("unlinking {} {}", user.first_name, user.last_name);
+            let mut unlinked = metadata.unlinked_myffme_user_ids.unwrap_or_default();
+            unlinked.push(myffme_user_id);
+            // everything else comes from the licence
+            Metadata {
+                unlinked_myffme_user_ids: Some(unlinked),
+                guardian_of: metadata.guardian_of,
+                ..Default::default()
+            }
+        }
+    };
+    user.metadata = Some(serde_json::to_value(metadata).map_err(|_| LinkError::Storage)?);
+    Snapshot::set_and_wait_for_update(&key, &user)
+        .await
+        .ok_or(LinkError::Storage)?;
+    record(&Actor::admin(admin), &key, Some(&before), &user).await;
+    Ok(user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_licensee_search() {
+        assert_eq!(
+            Some(LicenseeSearch::LicenseNumber(154316)),
+            LicenseeSearch::from_query(Some("license_number=154316"))
+        );
+        assert_eq!(
+            Some(LicenseeSearch::NameAndDob {
+                first_name: None,
+                last_name: "Le Gall".to_string(),
+                dob: 19750826,
+            }),
+            LicenseeSearch::from_query(Some("last_name=Le%20Gall&dob=19750826&first_name="))
+        );
+        assert_eq!(None, LicenseeSearch::from_query(Some("last_name=Le+Gall")));
+        assert_eq!(None, LicenseeSearch::from_query(Some("license_number=abc")));
+        assert_eq!(None, LicenseeSearch::from_query(None));
+    }
+
+    #[test]
+    fn test_search_matches() {
+        let user_data = serde_json::from_value::<UserData>(serde_json::json!({
+            "id": "1",
+            "firstname": "Anne",
+            "lastname": "Martin",
+            "birthname": "Le Gall",
+            "birthdate": "1975-08-26T00:00:00+00:00",
+            "username": null,
+            "licenceNumber": 154316,
+            "isLicensee": true,
+            "civility": "female",
+        }))
+        .unwrap();
+        assert!(LicenseeSearch::LicenseNumber(154316).matches(&user_data));
+        assert!(!LicenseeSearch::LicenseNumber(154317).matches(&user_data));
+        let search = |last_name: &str, dob: u32| LicenseeSearch::NameAndDob {
+            first_name: Some("Anne".to_string()),
+            last_name: last_name.to_string(),
+            dob,
+        };
+        assert!(search("Le Gall", 19750826).matches(&user_data));
+        assert!(search("Martin", 19750826).matches(&user_data));
+        assert!(!search("Martin", 19750827).matches(&user_data));
+        assert!(!search("Durand", 19750826).matches(&user_data));
+    }
+}
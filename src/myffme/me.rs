@@ -2,7 +2,7 @@
 
 use crate::http_client::json_client;
 use crate::myffme::licensee::UserData;
-use crate::myffme::MYFFME_AUTHORIZATION;
+use crate::myffme::send_with_authorization;
 use hyper::header::{HeaderValue, AUTHORIZATION, ORIGIN, REFERER};
 use reqwest::Url;
 use serde::Deserialize;
@@ -12,19 +12,14 @@ use tokio::io::AsyncWriteExt;
 pub(crate) async fn me() -> Option<UserData> {
     let url = Url::parse("https://api.core.myffme.fr/api/users/me").unwrap();
     let client = json_client();
-    let request = client
-        .get(url.as_str())
-        .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
-        .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
-        .header(
-            AUTHORIZATION,
-            MYFFME_AUTHORIZATION
-                .get_ref()
-                .map(|it| it.bearer_token.clone())?,
-        )
-        .build()
-        .ok()?;
-    let response = client.execute(request).await.ok()?;
+    let response = send_with_authorization(|bearer_token| {
+        client
+            .get(url.as_str())
+            .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
+            .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
+            .header(AUTHORIZATION, bearer_token)
+    })
+    .await?;
     #[cfg(test)]
     let data = {
         println!("me");
@@ -1,40 +1,58 @@
 pub mod address;
+pub(crate) mod document;
 pub mod email;
 // mod graphql;
 pub mod license;
 mod licensee;
+pub(crate) mod link;
 mod me;
+pub(crate) mod phone;
 pub mod price;
 mod product;
-mod structure;
+pub(crate) mod structure;
 
+use crate::audit::{record, Actor};
+use crate::conflict::{reconcile, Contacts};
 use crate::emergency_contact::EmergencyContact;
+use crate::guardian::{
+    age_outs, has_aged_out, is_minor, remove_guardian_identification, update_guardians,
+};
 use crate::http_client::json_client;
+use crate::lifecycle::lifecycle;
+use crate::medical_certificate::MedicalCertificate;
+use crate::metrics::{record_sync_users, record_token_renewal, timed, Upstream};
+use crate::mycompet::calendar::today;
 use crate::mycompet::results::competition_results;
 use crate::myffme::licensee::{
     address, emergency_contact, license, licensees, user_data, Licensee,
 };
 use crate::myffme::structure::structure_hierarchy_by_id;
 use crate::order::{InsuranceLevel, InsuranceOption};
+use crate::privacy::is_forgotten;
 use crate::season::current_season;
+use crate::status::{SyncKind, FAILURES};
 use crate::user::Metadata;
 use license::{
     deserialize_insurance_level, deserialize_insurance_option, deserialize_license_type,
 };
 use pinboard::Pinboard;
 use reqwest::header::HeaderValue;
+use reqwest::{RequestBuilder, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::collections::BTreeMap;
 use std::fmt::Write;
 use std::ops::Deref;
+use std::sync::atomic::Ordering;
 use std::sync::LazyLock;
+use std::time::SystemTime;
 use tiered_server::env::{secret_value, ConfigurationKey};
 use tiered_server::norm::{
     normalize_email, normalize_first_name, normalize_last_name, normalize_phone_number,
 };
 use tiered_server::store::Snapshot;
 use tiered_server::user::{Email, IdentificationMethod, Sms, User};
+use tokio::sync::Mutex;
 use tracing::{info, warn};
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -83,17 +101,92 @@ pub struct Structure {
     pub department: Option<String>,
 }
 
+/// A licence of a member, for one season.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct LicenseRecord {
+    /// MyFFME licence id.
+    pub id: String,
+    pub season: u16,
+    pub structure_id: u32,
+    pub structure_name: String,
+    pub license_type: LicenseType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub insurance_level: Option<InsuranceLevel>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub insurance_options: Vec<InsuranceOption>,
+    pub medical_certificate_status: MedicalCertificateStatus,
+}
+
+#[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Discipline {
+    Bouldering,
+    Lead,
+    Speed,
+    Combined,
+}
+
+#[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum CompetitionLevel {
+    Departmental,
+    Regional,
+    National,
+    International,
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Competition {
     pub season: u16,
     pub name: String,
+    /// First day of the competition (yyyymmdd).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discipline: Option<Discipline>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<CompetitionLevel>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+}
+
+/// Why a climber has no rank.
+#[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultStatus {
+    DidNotStart,
+    DidNotFinish,
+    Disqualified,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct CompetitionResult {
-    pub rank: u16,
+    /// None when the climber was not ranked, the status usually tells why.
+    pub rank: Option<u16>,
+    /// Whether the rank is shared with other climbers (ex aequo).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub tied: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<ResultStatus>,
     pub category_name: String,
     pub competition: Competition,
+    /// Number of participants in the category.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub participants: Option<u16>,
+}
+
+impl CompetitionResult {
+    /// Position in the category between 0 (first) and 1 (last).
+    pub fn relative_rank(&self) -> Option<f32> {
+        let rank = self.rank?;
+        match self.participants {
+            Some(0) | None => None,
+            Some(1) => Some(0.0),
+            Some(participants) => {
+                Some((rank.clamp(1, participants) - 1) as f32 / (participants - 1) as f32)
+            }
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -129,6 +222,7 @@ pub(crate) struct InsuranceOptionOption {
 
 pub(crate) struct Authorization {
     pub(crate) bearer_token: HeaderValue,
+    pub(crate) refresh_token: String,
     pub(crate) timestamp: u32,
 }
 
@@ -176,19 +270,24 @@ pub static STRUCTURE_ID: LazyLock<u32> = LazyLock::new(|| {
 pub(crate) static MYFFME_AUTHORIZATION: LazyLock<Pinboard<Authorization>> =
     LazyLock::new(Pinboard::new_empty);
 
+static MYFFME_AUTHORIZATION_RENEWAL: LazyLock<Mutex<()>> = LazyLock::new(|| Mutex::new(()));
+
 pub async fn update_myffme_bearer_token(
     timestamp: u32,
     refresh_token: Option<String>,
 ) -> Option<Token> {
     let client = json_client();
     if let Some(refresh_token) = refresh_token {
-        match client
-            .post("https://api.core.myffme.fr/auth/refresh")
-            .json(&json!({
-                "refreshToken": refresh_token,
-            }))
-            .send()
-            .await
+        match timed(
+            Upstream::Myffme,
+            client
+                .post("https://api.core.myffme.fr/auth/refresh")
+                .json(&json!({
+                    "refreshToken": refresh_token,
+                }))
+                .send(),
+        )
+        .await
         {
             Ok(response) => match response.json::<Token>().await {
                 Ok(token) => {
@@ -196,6 +295,7 @@ pub async fn update_myffme_bearer_token(
                         HeaderValue::try_from(format!("Bearer {}", token.token)).unwrap();
                     MYFFME_AUTHORIZATION.set(Authorization {
                         bearer_token,
+                        refresh_token: token.refresh_token.clone(),
                         timestamp,
                     });
                     return Some(token);
@@ -209,14 +309,17 @@ pub async fn update_myffme_bearer_token(
             }
         }
     }
-    match client
-        .post("https://api.core.myffme.fr/auth/login")
-        .json(&json!({
-            "username": *USERNAME,
-            "password": *PASSWORD,
-        }))
-        .send()
-        .await
+    match timed(
+        Upstream::Myffme,
+        client
+            .post("https://api.core.myffme.fr/auth/login")
+            .json(&json!({
+                "username": *USERNAME,
+                "password": *PASSWORD,
+            }))
+            .send(),
+    )
+    .await
     {
         Ok(response) => match response.json::<Token>().await {
             Ok(token) => {
@@ -224,22 +327,94 @@ pub async fn update_myffme_bearer_token(
                     HeaderValue::try_from(format!("Bearer {}", token.token)).unwrap();
                 MYFFME_AUTHORIZATION.set(Authorization {
                     bearer_token,
+                    refresh_token: token.refresh_token.clone(),
                     timestamp,
                 });
                 Some(token)
             }
             Err(err) => {
                 warn!("failed to parse login response:\n{err:?}");
+                FAILURES
+                    .myffme_authorization
+                    .fetch_add(1, Ordering::Relaxed);
                 None
             }
         },
         Err(err) => {
             warn!("failed to get login response:\n{err:?}");
+            FAILURES
+                .myffme_authorization
+                .fetch_add(1, Ordering::Relaxed);
             None
         }
     }
 }
 
+/// Renews the bearer token with the stored refresh token, falling back to the username and password.
+///
+/// `rejected` is the token that MyFFME refused, if any. When another caller already replaced it
+/// while we were waiting for the lock, the new token is returned as is so that concurrent callers
+/// share a single renewal.
+pub(crate) async fn renew_myffme_bearer_token(
+    rejected: Option<&HeaderValue>,
+) -> Option<HeaderValue> {
+    let _guard = MYFFME_AUTHORIZATION_RENEWAL.lock().await;
+    let refresh_token = match (MYFFME_AUTHORIZATION.get_ref(), rejected) {
+        (Some(current), Some(rejected)) if &current.bearer_token != rejected => {
+            return Some(current.bearer_token.clone());
+        }
+        (Some(current), _) => Some(current.refresh_token.clone()),
+        (None, _) => None,
+    };
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32;
+    let success = update_myffme_bearer_token(timestamp, refresh_token)
+        .await
+        .is_some();
+    record_token_renewal(rejected.is_some(), success);
+    if !success {
+        return None;
+    }
+    MYFFME_AUTHORIZATION
+        .get_ref()
+        .map(|it| it.bearer_token.clone())
+}
+
+async fn send(request: RequestBuilder) -> Option<Response> {
+    let (client, request) = request.build_split();
+    let request = request.inspect_err(|err| warn!("{err:?}")).ok()?;
+    timed(Upstream::Myffme, client.execute(request))
+        .await
+        .inspect_err(|err| warn!("{err:?}"))
+        .ok()
+}
+
+/// Sends a request built with the current bearer token.
+///
+/// If MyFFME answers with 401 or 403, the token is renewed and the request is sent once more.
+pub(crate) async fn send_with_authorization<F>(request: F) -> Option<Response>
+where
+    F: Fn(HeaderValue) -> RequestBuilder,
+{
+    let bearer_token = match MYFFME_AUTHORIZATION
+        .get_ref()
+        .map(|it| it.bearer_token.clone())
+    {
+        Some(it) => it,
+        None => renew_myffme_bearer_token(None).await?,
+    };
+    let response = send(request(bearer_token.clone())).await?;
+    let status = response.status();
+    if status != StatusCode::UNAUTHORIZED && status != StatusCode::FORBIDDEN {
+        return Some(response);
+    }
+    warn!("myffme rejected the bearer token ({status}), renewing it");
+    let bearer_token = renew_myffme_bearer_token(Some(&bearer_token)).await?;
+    send(request(bearer_token)).await
+}
+
 fn trim(str: String) -> String {
     let trimmed = str.trim();
     if trimmed.len() == str.len() {
@@ -276,6 +451,8 @@ pub(crate) async fn add_missing_users(
         .collect::<BTreeMap<_, _>>();
     let licensees = licensees().await.ok_or("failed to get licensees")?;
     info!("licensees: {}", licensees.len());
+    let mut created = 0;
+    let mut updated = 0;
     for licensee in licensees {
         let Licensee {
             myffme_user_id,
@@ -290,6 +467,10 @@ pub(crate) async fn add_missing_users(
         if lookup.contains_key(&myffme_user_id) {
             continue;
         }
+        // the account of the licensee was anonymised
+        if is_forgotten(snapshot, &myffme_user_id) {
+            continue;
+        }
         let metadata = Metadata {
             myffme_user_id: Some(myffme_user_id),
             license_number: Some(license_number),
@@ -305,6 +486,24 @@ pub(crate) async fn add_missing_users(
                     && (it.normalized_last_name == normalized_last_name
                         || (it.email().unwrap_or_default() == email) && !email.is_empty())
             })
+            // archived accounts are anonymised, they are never matched again
+            .filter(|&it| {
+                it.metadata
+                    .as_ref()
+                    .and_then(|value| Metadata::deserialize(value).ok())
+                    .is_none_or(|it| it.archived.is_none())
+            })
+            // an admin unlinked the account from this licensee
+            .filter(|&it| {
+                it.metadata
+                    .as_ref()
+                    .and_then(|value| Metadata::deserialize(value).ok())
+                    .and_then(|it| it.unlinked_myffme_user_ids)
+                    .is_none_or(|ids| {
+                        !ids.iter()
+                            .any(|id| Some(id) == metadata.myffme_user_id.as_ref())
+                    })
+            })
             .enumerate()
             .last();
         if let Some((i, it)) = last {
@@ -315,19 +514,34 @@ pub(crate) async fn add_missing_users(
                     let _ = writeln!(output, "assigning license to {first_name} {last_name}");
                 }
                 let mut user = it.clone();
+                let unlinked_myffme_user_ids = user
+                    .metadata
+                    .as_ref()
+                    .and_then(|value| Metadata::deserialize(value).ok())
+                    .and_then(|it| it.unlinked_myffme_user_ids);
                 user.metadata = Some(
-                    serde_json::to_value(metadata)
-                        .map_err(|_| "failed to serialize metadata".to_string())?,
+                    serde_json::to_value(Metadata {
+                        unlinked_myffme_user_ids,
+                        ..metadata
+                    })
+                    .map_err(|_| "failed to serialize metadata".to_string())?,
                 );
-                match Snapshot::set_and_return_before_update(&format!("acc/{}", user.id), &user)
-                    .await
-                {
-                    Some(_) => continue,
+                let key = format!("acc/{}", user.id);
+                match Snapshot::set_and_return_before_update(&key, &user).await {
+                    Some(_) => {
+                        record(&Actor::Sync, &key, Some(it), &user).await;
+                        updated += 1;
+                        continue;
+                    }
                     None => return Err(format!("failed to assign license to user {}", user.id)),
                 }
             } else {
-                // multiple matches, abort
-                return Err(format!("multiple users found for {first_name} {last_name}"));
+                // multiple matches, skip the licensee until the duplicates are merged
+                warn!("multiple users found for {first_name} {last_name}");
+                if let Some(output) = output.as_mut() {
+                    let _ = writeln!(output, "multiple users found for {first_name} {last_name}");
+                }
+                continue;
             }
         }
         // no match, create user
@@ -355,10 +569,33 @@ pub(crate) async fn add_missing_users(
         Snapshot::set_and_return_before_update(key.as_str(), &user)
             .await
             .ok_or("failed to add user".to_string())?;
+        record(&Actor::Sync, &key, None, &user).await;
+        created += 1;
     }
+    record_sync_users(SyncKind::AddMissingUsers, created, updated);
     Ok(output)
 }
 
+/// The user to save after the sync of their MyFFME data, or None if nothing changed.
+///
+/// The metadata is put back whatever changed, as it was taken off the user for the sync.
+fn updated_user(
+    mut user: User,
+    metadata: &Metadata,
+    modified: bool,
+) -> Result<Option<User>, String> {
+    if !modified {
+        return Ok(None);
+    }
+    user.metadata = Some(serde_json::to_value(metadata).map_err(|err| {
+        warn!("failed to serialize metadata:\n{err:?}");
+        "failed to serialize metadata".to_string()
+    })?);
+    Ok(Some(user))
+}
+
+/// Returns the log of the changes when `log` is set, as `add_missing_users` does: the admin
+/// endpoint and the status report both expect it.
 pub(crate) async fn update_users_metadata(
     snapshot: &Snapshot,
     log: bool,
@@ -377,7 +614,15 @@ pub(crate) async fn update_users_metadata(
         .ok_or("failed to get structure".to_string())?
         .into();
     let current_season = current_season(None);
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32;
+    let age_outs = age_outs(snapshot, current_season);
+    let mut updated_users = BTreeMap::new();
+    let mut own_identifications = BTreeMap::new();
     for (key, mut user) in entries {
+        let before = user.clone();
         let first_name = user.first_name.as_str();
         let last_name = user.last_name.as_str();
         if let Some(metadata) = user
@@ -390,17 +635,35 @@ pub(crate) async fn update_users_metadata(
                 let user_data = user_data(myffme_user_id).await.ok_or(format!(
                     "failed to get data for user {first_name} {last_name}"
                 ))?;
-                let latest_license = if let Some(paths) = user_data.license_paths.as_ref() {
-                    if let Some(license_path) = paths.last() {
-                        Some(license(license_path).await.ok_or(format!(
+                let mut latest_license = None;
+                let mut license_history = Vec::new();
+                if let Some(paths) = user_data.license_paths.as_ref() {
+                    for (i, license_path) in paths.iter().enumerate() {
+                        let id = license_path.rsplit('/').next().unwrap_or(license_path);
+                        let is_latest = i + 1 == paths.len();
+                        // past licences don't change, only the latest one is fetched again
+                        if !is_latest {
+                            if let Some(record) = metadata
+                                .license_history
+                                .iter()
+                                .flatten()
+                                .find(|it| it.id == id)
+                            {
+                                license_history.push(record.clone());
+                                continue;
+                            }
+                        }
+                        let license = license(license_path).await.ok_or(format!(
                             "failed to get license for user {first_name} {last_name}"
-                        ))?)
-                    } else {
-                        None
+                        ))?;
+                        license_history.push(license.record(id));
+                        if is_latest {
+                            latest_license = Some(license);
+                        }
                     }
-                } else {
-                    None
-                };
+                }
+                license_history.sort_by_key(|it| it.season);
+                let license_history = Some(license_history).filter(|it| !it.is_empty());
                 let latest_structure = if let Some(structure_id) =
                     latest_license.as_ref().map(|it| it.structure.structure)
                 {
@@ -476,150 +739,113 @@ pub(crate) async fn update_users_metadata(
                     } else {
                         None
                     };
-                if let Some(email) = user_data.email {
-                    let email = trim(email);
-                    if !email.is_empty() {
-                        let normalized_email = normalize_email(&email);
-                        if !user.identification.iter().any(|it| match it {
-                            IdentificationMethod::Email(it) => {
-                                it.normalized_address == normalized_email
-                            }
-                            _ => false,
-                        }) {
-                            info!("adding email to user {first_name} {last_name}");
-                            if let Some(output) = output.as_mut() {
-                                let _ = writeln!(
-                                    output,
-                                    "adding email to user {first_name} {last_name}"
-                                );
-                            }
-                            user.identification.push(IdentificationMethod::Email(Email {
-                                normalized_address: normalized_email,
-                                address: email,
-                            }));
-                            modified = true;
-                        }
-                    }
-                }
-                if let Some(email) = user_data.alternate_email {
-                    let email = trim(email);
-                    if !email.is_empty() {
-                        let normalized_email = normalize_email(&email);
-                        if !user.identification.iter().any(|it| match it {
-                            IdentificationMethod::Email(it) => {
-                                it.normalized_address == normalized_email
-                            }
-                            _ => false,
-                        }) {
-                            info!("adding email to user {first_name} {last_name}");
-                            if let Some(output) = output.as_mut() {
-                                let _ = writeln!(
-                                    output,
-                                    "adding email to user {first_name} {last_name}"
-                                );
-                            }
-                            user.identification.push(IdentificationMethod::Email(Email {
-                                normalized_address: normalized_email,
-                                address: email,
-                            }));
-                            modified = true;
-                        }
-                    }
-                }
-                if let Some(number) = user_data.phone_number {
-                    let number = trim(number);
-                    if !number.is_empty() {
-                        let normalized_number = normalize_phone_number(&number, 33);
-                        if is_mobile_number(&normalized_number) {
-                            if !user.identification.iter().any(|it| match it {
-                                IdentificationMethod::Sms(it) => {
-                                    it.normalized_number == normalized_number
-                                }
-                                _ => false,
-                            }) {
-                                info!("adding sms to user {first_name} {last_name}");
-                                if let Some(output) = output.as_mut() {
-                                    let _ = writeln!(
-                                        output,
-                                        "adding sms to user {first_name} {last_name}"
-                                    );
-                                }
-                                user.identification.push(IdentificationMethod::Sms(Sms {
-                                    number,
-                                    normalized_number,
-                                }));
-                            }
-                            modified = true;
-                        }
+                let own_identification = [
+                    user_data
+                        .email
+                        .as_deref()
+                        .map(|it| normalize_email(it.trim())),
+                    user_data
+                        .alternate_email
+                        .as_deref()
+                        .map(|it| normalize_email(it.trim())),
+                    user_data
+                        .phone_number
+                        .as_deref()
+                        .map(|it| normalize_phone_number(it.trim(), 33)),
+                    user_data
+                        .alternate_phone_number
+                        .as_deref()
+                        .map(|it| normalize_phone_number(it.trim(), 33)),
+                ]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>();
+                let remote_contacts = Contacts::remote(&user_data);
+                let contact_identification = emergency_contacts
+                    .iter()
+                    .flatten()
+                    .flat_map(|it| it.identification.iter().cloned())
+                    .collect::<Vec<_>>();
+                let reconciliation = reconcile(
+                    snapshot,
+                    &mut user,
+                    myffme_user_id,
+                    metadata.synced_contacts.as_ref(),
+                    &remote_contacts,
+                    &contact_identification,
+                    now,
+                )
+                .await;
+                let first_name = user.first_name.as_str();
+                let last_name = user.last_name.as_str();
+                if reconciliation.modified {
+                    info!("updating contacts of user {first_name} {last_name}");
+                    if let Some(output) = output.as_mut() {
+                        let _ =
+                            writeln!(output, "updating contacts of user {first_name} {last_name}");
                     }
+                    modified = true;
                 }
-                if let Some(number) = user_data.alternate_phone_number {
-                    let number = trim(number);
-                    if !number.is_empty() {
-                        let normalized_number = normalize_phone_number(&number, 33);
-                        if is_mobile_number(&normalized_number) {
-                            if !user.identification.iter().any(|it| match it {
-                                IdentificationMethod::Sms(it) => {
-                                    it.normalized_number == normalized_number
-                                }
-                                _ => false,
-                            }) {
-                                info!("adding sms to user {first_name} {last_name}");
-                                if let Some(output) = output.as_mut() {
-                                    let _ = writeln!(
-                                        output,
-                                        "adding sms to user {first_name} {last_name}"
-                                    );
-                                }
-                                user.identification.push(IdentificationMethod::Sms(Sms {
-                                    number,
-                                    normalized_number,
-                                }));
-                            }
-                            modified = true;
-                        }
+                if reconciliation.conflicts > 0 {
+                    if let Some(output) = output.as_mut() {
+                        let _ = writeln!(
+                            output,
+                            "conflicting contacts for user {first_name} {last_name}: {}",
+                            reconciliation.conflicts
+                        );
                     }
                 }
-                if (current_season - (user.date_of_birth / 1_00_00) as u16) < 18 {
-                    if let Some(emergency_contacts) = emergency_contacts.as_ref() {
-                        for emergency_contact in emergency_contacts {
-                            for identification in emergency_contact.identification.iter() {
-                                if !user.identification.contains(identification) {
-                                    let kind = match identification {
-                                        IdentificationMethod::Email(_) => "email",
-                                        IdentificationMethod::Sms(_) => "sms",
-                                        _ => continue,
-                                    };
-                                    info!("adding {kind} to user {first_name} {last_name}");
-                                    if let Some(output) = output.as_mut() {
-                                        let _ = writeln!(
-                                            output,
-                                            "adding {kind} to user {first_name} {last_name}"
-                                        );
-                                    }
-                                    user.identification.push(identification.clone());
-                                }
-                            }
-                        }
-                    }
+                let synced_contacts = Some(reconciliation.synced);
+                // guardians log in with their own account (see guardian.rs),
+                // the identification of the contacts copied by earlier syncs is removed
+                // once the guardian accounts are saved,
+                // after the notice for members that just turned 18.
+                if emergency_contacts.is_some()
+                    && (is_minor(user.date_of_birth, current_season)
+                        || has_aged_out(&user, current_season, &age_outs, now))
+                {
+                    own_identifications.insert(key.clone(), own_identification);
                 }
                 let competition_results = competition_results(user_data.license_number).await;
                 let license_number = Some(user_data.license_number);
                 let gender = Some(user_data.gender);
                 let license_type = latest_license.as_ref().map(|it| it.product.product);
                 let latest_license_season = latest_license.as_ref().map(|it| it.season.season);
+                // from the latest licence, the history is sorted by season and may end with another one
+                let (insurance_level, insurance_options) = latest_license
+                    .as_ref()
+                    .map(|it| it.insurance())
+                    .unwrap_or_default();
+                let insurance_options = Some(insurance_options).filter(|it| !it.is_empty());
                 let medical_certificate_status = latest_license
                     .as_ref()
                     .map(|it| it.medical_certificate_status);
-                if metadata.license_number != license_number
+                let lifecycle = license_history
+                    .as_deref()
+                    .and_then(|it| lifecycle(it, current_season, this_structure.id, today(now)));
+                let medical_certificate = latest_license.as_ref().map(|it| {
+                    MedicalCertificate::new(
+                        it.medical_certificate_status,
+                        it.season.season,
+                        metadata.medical_certificate.as_ref(),
+                        now,
+                    )
+                });
+                let metadata = if metadata.license_number != license_number
                     || metadata.gender != gender
                     || metadata.license_type != license_type
                     || metadata.latest_license_season != latest_license_season
                     || metadata.latest_structure != latest_structure
+                    || metadata.insurance_level != insurance_level
+                    || metadata.insurance_options != insurance_options
                     || metadata.medical_certificate_status != medical_certificate_status
+                    || metadata.medical_certificate != medical_certificate
                     || metadata.address != address
                     || metadata.emergency_contacts != emergency_contacts
                     || metadata.competition_results != competition_results
+                    || metadata.license_history != license_history
+                    || metadata.lifecycle != lifecycle
+                    || metadata.synced_contacts != synced_contacts
                 {
                     modified = true;
                     info!("modifying metadata for user {first_name} {last_name}");
@@ -629,37 +855,76 @@ pub(crate) async fn update_users_metadata(
                             "modifying metadata for user {first_name} {last_name}"
                         );
                     }
-                    user.metadata = Some(
-                        serde_json::to_value(Metadata {
-                            license_number,
-                            gender,
-                            license_type,
-                            latest_license_season,
-                            latest_structure,
-                            medical_certificate_status,
-                            address,
-                            emergency_contacts,
-                            competition_results,
-                            ..metadata
-                        })
-                        .map_err(|err| {
-                            warn!("failed to serialize metadata:\n{err:?}");
-                            "failed to serialize metadata".to_string()
-                        })?,
-                    );
-                }
-                if modified {
+                    Metadata {
+                        license_number,
+                        gender,
+                        license_type,
+                        latest_license_season,
+                        latest_structure,
+                        insurance_level,
+                        insurance_options,
+                        medical_certificate_status,
+                        medical_certificate,
+                        address,
+                        emergency_contacts,
+                        competition_results,
+                        license_history,
+                        lifecycle,
+                        synced_contacts,
+                        ..metadata
+                    }
+                } else {
+                    metadata
+                };
+                // the reconciliation can change the identification alone
+                if let Some(user) = updated_user(user, &metadata, modified)? {
                     Snapshot::set_and_return_before_update(key.as_str(), &user)
                         .await
                         .ok_or("failed to update user".to_string())?;
+                    record(&Actor::Sync, &key, Some(&before), &user).await;
+                    updated_users.insert(key, user);
                 }
             }
         }
     }
-    Ok(None)
+    let updated = updated_users.len();
+    // the snapshot doesn't include the updates yet
+    let mut users = snapshot
+        .list::<User>("acc/")
+        .map(|(k, v)| updated_users.remove(&k.to_string()).unwrap_or(v))
+        .collect();
+    let guardians = update_guardians(&mut users, current_season, &age_outs, now).await?;
+    info!("updated guardians: {guardians}");
+    if let Some(output) = output.as_mut() {
+        let _ = writeln!(output, "updated guardians: {guardians}");
+    }
+    for user in users.iter_mut() {
+        let key = format!("acc/{}", user.id);
+        let Some(own_identification) = own_identifications.get(&key) else {
+            continue;
+        };
+        let before = user.clone();
+        if !remove_guardian_identification(user, own_identification) {
+            continue;
+        }
+        let (first_name, last_name) = (&user.first_name, &user.last_name);
+        info!("removing guardian identification from user {first_name} {last_name}");
+        if let Some(output) = output.as_mut() {
+            let _ = writeln!(
+                output,
+                "removing guardian identification from user {first_name} {last_name}"
+            );
+        }
+        Snapshot::set_and_return_before_update(&key, &*user)
+            .await
+            .ok_or("failed to update user".to_string())?;
+        record(&Actor::Sync, &key, Some(&before), &*user).await;
+    }
+    record_sync_users(SyncKind::UpdateUsersMetadata, 0, updated + guardians);
+    Ok(output)
 }
 
-fn is_mobile_number(normalized_number: &str) -> bool {
+pub(crate) fn is_mobile_number(normalized_number: &str) -> bool {
     normalized_number.starts_with("+336") || normalized_number.starts_with("+337")
 }
 
@@ -683,6 +948,27 @@ mod tests {
         println!("token:{}", token.deref());
     }
 
+    #[tokio::test]
+    #[ignore]
+    async fn test_renew_bearer_token() {
+        update_myffme_bearer_token(0, None)
+            .await
+            .expect("failed to get bearer token");
+        let rejected = MYFFME_AUTHORIZATION
+            .get_ref()
+            .map(|it| it.bearer_token.clone())
+            .unwrap();
+        let renewed = renew_myffme_bearer_token(Some(&rejected))
+            .await
+            .expect("failed to renew bearer token");
+        assert_ne!(rejected, renewed);
+        // the rejected token was already replaced, so no new renewal should happen.
+        let shared = renew_myffme_bearer_token(Some(&rejected))
+            .await
+            .expect("failed to get renewed bearer token");
+        assert_eq!(renewed, shared);
+    }
+
     #[tokio::test]
     #[ignore]
     async fn test_add_missing_users() {
@@ -723,4 +1009,32 @@ mod tests {
             .expect("failed to get bearer token");
         update_users_metadata(&snapshot(), false).await.unwrap();
     }
+
+    #[test]
+    fn test_updated_user() {
+        use crate::user::{test_email, test_user};
+        let metadata = || Metadata {
+            myffme_user_id: Some("1".to_string()),
+            license_number: Some(154316),
+            ..Default::default()
+        };
+        let mut user = test_user("Léa", "Martin", 20100101, metadata());
+        let metadata = metadata();
+        // taken off for the sync, only the identification changes
+        let _ = user.metadata.take();
+        user.identification = vec![test_email("lea.martin@example.com")];
+        assert!(
+            updated_user(user.clone(), &metadata, false)
+                .unwrap()
+                .is_none()
+        );
+        let updated = updated_user(user, &metadata, true).unwrap().unwrap();
+        assert_eq!(
+            vec![test_email("lea.martin@example.com")],
+            updated.identification
+        );
+        let saved = serde_json::from_value::<Metadata>(updated.metadata.unwrap()).unwrap();
+        assert_eq!(Some("1".to_string()), saved.myffme_user_id);
+        assert_eq!(Some(154316), saved.license_number);
+    }
 }
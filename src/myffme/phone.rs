@@ -0,0 +1,159 @@
+use crate::http_client::json_client;
+use crate::myffme::licensee::user_data;
+use crate::myffme::send_with_authorization;
+use hyper::header::{HeaderValue, AUTHORIZATION, ORIGIN, REFERER};
+use reqwest::Url;
+use serde_json::json;
+use tiered_server::norm::normalize_phone_number;
+#[cfg(test)]
+use tokio::io::AsyncWriteExt;
+
+/// The phone number fields of a member in MyFFME.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PhoneField {
+    Mobile,
+    Phone,
+}
+
+impl PhoneField {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            PhoneField::Mobile => "mobile",
+            PhoneField::Phone => "phone",
+        }
+    }
+}
+
+/// MyFFME stores french numbers in the national format.
+fn national_number(normalized_number: &str) -> String {
+    match normalized_number.strip_prefix("+33") {
+        Some(number) => format!("0{number}"),
+        None => normalized_number.to_string(),
+    }
+}
+
+fn normalized(number: Option<&str>) -> Option<String> {
+    number
+        .map(str::trim)
+        .filter(|it| !it.is_empty())
+        .map(|it| normalize_phone_number(it, 33))
+}
+
+pub(crate) async fn update_phone_field(
+    myffme_user_id: &str,
+    field: PhoneField,
+    normalized_number: &str,
+) -> Option<()> {
+    let url = Url::parse(&format!(
+        "https://api.core.myffme.fr/api/user_datas/{myffme_user_id}"
+    ))
+    .unwrap();
+    let client = json_client();
+    let response = send_with_authorization(|bearer_token| {
+        client
+            .patch(url.as_str())
+            .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
+            .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
+            .header(AUTHORIZATION, bearer_token)
+            .json(&json!({
+                field.as_str(): national_number(normalized_number),
+            }))
+    })
+    .await?;
+    #[cfg(test)]
+    let success = {
+        println!("phone");
+        println!("PATCH {}", url.as_str());
+        let success = response.status().is_success();
+        println!("{}", response.status());
+        let text = response.text().await.ok()?;
+        let file_name = format!(".api/.update_phone_{myffme_user_id}.json");
+        tokio::fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(&file_name)
+            .await
+            .ok()?
+            .write_all(text.as_bytes())
+            .await
+            .unwrap();
+        success
+    };
+    #[cfg(not(test))]
+    let success = response.status().is_success();
+    if success {
+        Some(())
+    } else {
+        tracing::warn!("failed to update phone number");
+        None
+    }
+}
+
+/// Replaces a phone number of the member in MyFFME, in the field that holds it.
+///
+/// Returns the field that was updated, if the old number was in one of the `fields` that can be
+/// updated.
+pub(crate) async fn update_phone_number(
+    myffme_user_id: &str,
+    normalized_old_number: &str,
+    normalized_new_number: &str,
+    fields: &[PhoneField],
+) -> Option<Option<PhoneField>> {
+    let user_data = user_data(myffme_user_id).await?;
+    let field = if normalized(user_data.phone_number.as_deref()).as_deref()
+        == Some(normalized_old_number)
+    {
+        PhoneField::Mobile
+    } else if normalized(user_data.alternate_phone_number.as_deref()).as_deref()
+        == Some(normalized_old_number)
+    {
+        PhoneField::Phone
+    } else {
+        return Some(None);
+    };
+    if !fields.contains(&field) {
+        return Some(None);
+    }
+    update_phone_field(myffme_user_id, field, normalized_new_number).await?;
+    Some(Some(field))
+}
+
+/// Adds a phone number to the member in MyFFME, in the first empty field of `fields`.
+///
+/// Returns the field that was updated, existing numbers are never replaced.
+pub(crate) async fn add_phone_number(
+    myffme_user_id: &str,
+    normalized_new_number: &str,
+    fields: &[PhoneField],
+) -> Option<Option<PhoneField>> {
+    let user_data = user_data(myffme_user_id).await?;
+    let mobile = normalized(user_data.phone_number.as_deref());
+    let phone = normalized(user_data.alternate_phone_number.as_deref());
+    if [&mobile, &phone]
+        .into_iter()
+        .any(|it| it.as_deref() == Some(normalized_new_number))
+    {
+        return Some(None);
+    }
+    let field = if mobile.is_none() && fields.contains(&PhoneField::Mobile) {
+        PhoneField::Mobile
+    } else if phone.is_none() && fields.contains(&PhoneField::Phone) {
+        PhoneField::Phone
+    } else {
+        return Some(None);
+    };
+    update_phone_field(myffme_user_id, field, normalized_new_number).await?;
+    Some(Some(field))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_national_number() {
+        assert_eq!("0612345678", national_number("+33612345678"));
+        assert_eq!("+32470123456", national_number("+32470123456"));
+    }
+}
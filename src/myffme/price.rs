@@ -1,7 +1,7 @@
 use crate::http_client::json_client;
 use crate::myffme::license::{deserialize_product_option, ProductOption};
 use crate::myffme::product::{products, Product};
-use crate::myffme::{LicenseFees, LicenseType, MYFFME_AUTHORIZATION, STRUCTURE_ID};
+use crate::myffme::{send_with_authorization, LicenseFees, LicenseType, STRUCTURE_ID};
 use crate::order::{InsuranceLevel, InsuranceOption};
 use crate::season::current_season;
 use hyper::header::{HeaderValue, AUTHORIZATION, ORIGIN, REFERER};
@@ -97,19 +97,14 @@ async fn product_prices(
         .append_pair("productId", &joined_results)
         .append_pair("structureId", &STRUCTURE_ID.to_string());
     let client = json_client();
-    let request = client
-        .get(url.as_str())
-        .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
-        .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
-        .header(
-            AUTHORIZATION,
-            MYFFME_AUTHORIZATION
-                .get_ref()
-                .map(|it| it.bearer_token.clone())?,
-        )
-        .build()
-        .ok()?;
-    let response = client.execute(request).await.ok()?;
+    let response = send_with_authorization(|bearer_token| {
+        client
+            .get(url.as_str())
+            .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
+            .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
+            .header(AUTHORIZATION, bearer_token)
+    })
+    .await?;
     #[cfg(test)]
     let list = {
         println!("license_prices");
@@ -177,19 +172,14 @@ async fn insurance_prices(
         )
         .append_pair("structureId", &STRUCTURE_ID.to_string());
     let client = json_client();
-    let request = client
-        .get(url.as_str())
-        .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
-        .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
-        .header(
-            AUTHORIZATION,
-            MYFFME_AUTHORIZATION
-                .get_ref()
-                .map(|it| it.bearer_token.clone())?,
-        )
-        .build()
-        .ok()?;
-    let response = client.execute(request).await.ok()?;
+    let response = send_with_authorization(|bearer_token| {
+        client
+            .get(url.as_str())
+            .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
+            .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
+            .header(AUTHORIZATION, bearer_token)
+    })
+    .await?;
     #[cfg(test)]
     let list = {
         println!("insurance_prices");
@@ -1,6 +1,6 @@
 use crate::http_client::json_client;
 use crate::myffme::license::deserialize_license_type;
-use crate::myffme::{LicenseType, MYFFME_AUTHORIZATION};
+use crate::myffme::{send_with_authorization, LicenseType};
 use hyper::header::{HeaderValue, AUTHORIZATION, ORIGIN, REFERER};
 use reqwest::Url;
 use serde::Deserialize;
@@ -22,20 +22,14 @@ pub(crate) async fn products() -> Option<Vec<Product>> {
         .append_pair("itemsPerPage", "500")
         .append_pair("page", "1");
     let client = json_client();
-    let request = client
-        .get(url.as_str())
-        .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
-        .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
-        .header(
-            AUTHORIZATION,
-            MYFFME_AUTHORIZATION
-                .get_ref()
-                .map(|it| it.bearer_token.clone())?,
-        )
-        .build()
-        .ok()?;
-
-    let response = client.execute(request).await.ok()?;
+    let response = send_with_authorization(|bearer_token| {
+        client
+            .get(url.as_str())
+            .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
+            .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
+            .header(AUTHORIZATION, bearer_token)
+    })
+    .await?;
     #[cfg(test)]
     let list = {
         println!("products");
@@ -1,5 +1,5 @@
 use crate::http_client::json_client;
-use crate::myffme::{Structure, MYFFME_AUTHORIZATION};
+use crate::myffme::{send_with_authorization, Structure};
 use hyper::header::{HeaderValue, AUTHORIZATION, ORIGIN, REFERER};
 use reqwest::Url;
 use serde::Deserialize;
@@ -53,19 +53,14 @@ impl From<StructureHierarchy> for Structure {
 pub async fn structure_hierarchy_by_id(id: u32) -> Option<StructureHierarchy> {
     let url = Url::parse(&format!("https://api.core.myffme.fr/api/structures/{id}")).unwrap();
     let client = json_client();
-    let request = client
-        .get(url.as_str())
-        .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
-        .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
-        .header(
-            AUTHORIZATION,
-            MYFFME_AUTHORIZATION
-                .get_ref()
-                .map(|it| it.bearer_token.clone())?,
-        )
-        .build()
-        .ok()?;
-    let response = client.execute(request).await.ok()?;
+    let response = send_with_authorization(|bearer_token| {
+        client
+            .get(url.as_str())
+            .header(ORIGIN, HeaderValue::from_static("https://app.myffme.fr"))
+            .header(REFERER, HeaderValue::from_static("https://app.myffme.fr/"))
+            .header(AUTHORIZATION, bearer_token)
+    })
+    .await?;
 
     #[cfg(test)]
     let structure_hierarchy = {
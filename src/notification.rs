@@ -0,0 +1,82 @@
+use crate::guardian::{guardians, is_minor};
+use crate::season::current_season;
+use tiered_server::email::send_email;
+use tiered_server::sms::send_sms;
+use tiered_server::store::Snapshot;
+use tiered_server::user::{IdentificationMethod, Sms, User};
+use tracing::{info, warn};
+
+/// Sends an email to every admin that has an email address.
+///
+/// Returns the number of emails that were sent.
+pub(crate) async fn notify_admins(snapshot: &Snapshot, subject: &str, text: &str) -> usize {
+    let addresses = snapshot
+        .list::<User>("acc/")
+        .filter(|(_, it)| it.admin)
+        .filter_map(|(_, it)| it.email().map(|it| it.to_string()))
+        .collect::<Vec<_>>();
+    if addresses.is_empty() {
+        warn!("no admin email address to send \"{subject}\" to");
+        return 0;
+    }
+    let mut count = 0;
+    for address in addresses {
+        if send_email(&address, subject, text).await.is_some() {
+            count += 1;
+        } else {
+            warn!("failed to send \"{subject}\" to {address}");
+        }
+    }
+    info!("sent \"{subject}\" to {count} admin(s)");
+    count
+}
+
+/// Sends an email to the user, and a text message if they have a mobile number.
+///
+/// Returns whether the user was reached at least once.
+pub(crate) async fn notify_user(user: &User, subject: &str, text: &str, sms: &str) -> bool {
+    let mut reached = false;
+    if let Some(address) = user.email() {
+        if send_email(address, subject, text).await.is_some() {
+            reached = true;
+        } else {
+            warn!("failed to send \"{subject}\" to {address}");
+        }
+    }
+    for identification in user.identification.iter() {
+        if let IdentificationMethod::Sms(Sms {
+            normalized_number, ..
+        }) = identification
+        {
+            if send_sms(normalized_number, sms).await.is_some() {
+                reached = true;
+            } else {
+                warn!("failed to send text message to {normalized_number}");
+            }
+        }
+    }
+    reached
+}
+
+/// Notifies a member, or their guardians if they are a minor.
+///
+/// Returns whether anyone was reached.
+pub(crate) async fn notify_member(
+    snapshot: &Snapshot,
+    user: &User,
+    subject: &str,
+    text: &str,
+    sms: &str,
+) -> bool {
+    if is_minor(user.date_of_birth, current_season(None)) {
+        let guardians = guardians(snapshot, &user.id.to_string());
+        if !guardians.is_empty() {
+            let mut reached = false;
+            for guardian in guardians.iter() {
+                reached |= notify_user(guardian, subject, text, sms).await;
+            }
+            return reached;
+        }
+    }
+    notify_user(user, subject, text, sms).await
+}
@@ -1,23 +1,33 @@
 use crate::myffme::price::prices;
 use crate::myffme::{LicenseFees, LicenseType};
-use serde::Serialize;
+use crate::price_history::{notify_price_changes, set_price, PriceChange};
+use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
 use tiered_server::store::{snapshot, Snapshot};
 
-#[allow(clippy::inconsistent_digit_grouping)]
+/// Updates the prices and sends a single notification to the admins for all the changes, including
+/// those saved before a failure.
 pub async fn update_prices() -> Option<()> {
     let snapshot = snapshot();
+    let mut changes = Vec::new();
+    let result = save_prices(&snapshot, &mut changes).await;
+    notify_price_changes(&snapshot, &changes).await;
+    result
+}
+
+#[allow(clippy::inconsistent_digit_grouping)]
+async fn save_prices(snapshot: &Snapshot, changes: &mut Vec<PriceChange>) -> Option<()> {
     let base_license_price = match snapshot.get::<u16>(BaseLicensePrice.key()) {
         Some(price) => price,
         None => {
             let price = 135_00;
-            Snapshot::set_and_wait_for_update(BaseLicensePrice.key(), &price).await?;
+            set_price(BaseLicensePrice.key(), None, &price, false, changes).await?;
             price
         }
     };
     if snapshot.get::<u16>(EquipmentRental.key()).is_none() {
         let price: u16 = 50_00;
-        Snapshot::set_and_wait_for_update(EquipmentRental.key(), &price).await?;
+        set_price(EquipmentRental.key(), None, &price, false, changes).await?;
     }
     let (mut license_types, mut levels, mut options) = prices(None).await?;
     let mut default_level_price = None;
@@ -30,7 +40,7 @@ pub async fn update_prices() -> Option<()> {
         let price = snapshot.get::<u16>(level.key());
         if let Some(found) = levels.remove(&level) {
             if Some(found) != price {
-                Snapshot::set_and_wait_for_update(level.key(), &found).await?;
+                set_price(level.key(), price.as_ref(), &found, true, changes).await?;
             }
             if level == InsuranceLevel::default() {
                 default_level_price = price;
@@ -46,7 +56,7 @@ pub async fn update_prices() -> Option<()> {
         let price = snapshot.get::<u16>(option.key());
         if let Some(found) = options.remove(&option) {
             if Some(found) != price {
-                Snapshot::set_and_wait_for_update(option.key(), &found).await?;
+                set_price(option.key(), price.as_ref(), &found, true, changes).await?;
             }
         }
     }
@@ -68,13 +78,16 @@ pub async fn update_prices() -> Option<()> {
                     || Some(found.department_fee_in_cents)
                         != fees.as_ref().map(|it| it.department_fee_in_cents)
                 {
-                    Snapshot::set_and_wait_for_update(
+                    set_price(
                         license_type.key(),
+                        fees.as_ref(),
                         &LicenseFees {
                             federal_fee_in_cents: found.federal_fee_in_cents,
                             regional_fee_in_cents: found.regional_fee_in_cents,
                             department_fee_in_cents: found.department_fee_in_cents,
                         },
+                        true,
+                        changes,
                     )
                     .await?;
                 }
@@ -84,9 +97,12 @@ pub async fn update_prices() -> Option<()> {
                     - found.department_fee_in_cents
                     - default_level_price;
                 if fee != Some(expected_fee) {
-                    Snapshot::set_and_wait_for_update(
+                    set_price(
                         MembershipFee(license_type).key(),
+                        fee.as_ref(),
                         &expected_fee,
+                        false,
+                        changes,
                     )
                     .await?;
                 }
@@ -236,7 +252,7 @@ pub struct BaseLicensePrice;
 
 pub struct MembershipFee(LicenseType);
 
-#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Serialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Serialize, Deserialize)]
 pub enum InsuranceLevel {
     RC,
     Base,
@@ -250,7 +266,7 @@ impl Default for InsuranceLevel {
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Serialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Serialize, Deserialize)]
 pub enum InsuranceOption {
     MountainBike,
     Ski,
@@ -0,0 +1,201 @@
+use crate::audit::{record, Actor};
+use crate::notification::notify_admins;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt::Write;
+use std::time::SystemTime;
+use tiered_server::store::Snapshot;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct PriceChange {
+    pub(crate) key: String,
+    pub(crate) timestamp: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) old: Option<Value>,
+    pub(crate) new: Value,
+    /// Whether the price comes from the federation (license fees and insurance) rather than from us.
+    pub(crate) federation: bool,
+}
+
+impl PriceChange {
+    fn history_key(&self) -> String {
+        format!(
+            "pch/{:010}_{}",
+            self.timestamp,
+            self.key.strip_prefix("cts/").unwrap_or(&self.key)
+        )
+    }
+}
+
+/// Stores the new price and records the change in the price history and the audit log.
+///
+/// The change is added to `changes`, for the admin notification sent once the update is done, see
+/// `notify_price_changes`.
+pub(crate) async fn set_price<T: Serialize>(
+    key: &str,
+    old: Option<&T>,
+    new: &T,
+    federation: bool,
+    changes: &mut Vec<PriceChange>,
+) -> Option<()> {
+    Snapshot::set_and_wait_for_update(key, new).await?;
+    record(&Actor::Job, key, old, new).await;
+    let change = PriceChange {
+        key: key.to_string(),
+        timestamp: SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as u32,
+        old: old.and_then(|it| serde_json::to_value(it).ok()),
+        new: serde_json::to_value(new).ok()?,
+        federation,
+    };
+    Snapshot::set_and_wait_for_update(&change.history_key(), &change).await?;
+    changes.push(change);
+    Some(())
+}
+
+pub(crate) fn price_history(snapshot: &Snapshot) -> Vec<PriceChange> {
+    snapshot
+        .list::<PriceChange>("pch/")
+        .map(|(_, it)| it)
+        .collect()
+}
+
+/// The admins are notified when the federation changed its prices.
+///
+/// The first import of a price is not a change, and neither are the prices we set ourselves,
+/// but those are still notified when they follow a change of the federation fees in the same
+/// update, since structure fees follow federation fees.
+fn is_notified(change: &PriceChange, previous_changes: &[PriceChange]) -> bool {
+    change.old.is_some()
+        && (change.federation
+            || previous_changes
+                .iter()
+                .any(|it| it.federation && it.old.is_some()))
+}
+
+/// The subject and text of the notification of the changes of a price update, if any is notified.
+fn notification(changes: &[PriceChange]) -> Option<(&'static str, String)> {
+    let notified = changes
+        .iter()
+        .enumerate()
+        .filter(|(i, it)| is_notified(it, &changes[..*i]))
+        .map(|(_, it)| it.clone())
+        .collect::<Vec<_>>();
+    if notified.is_empty() {
+        return None;
+    }
+    let subject = if notified.iter().all(|it| it.federation) {
+        "Changement des tarifs de la fédération"
+    } else {
+        "Changement des tarifs"
+    };
+    Some((subject, describe_changes(&notified)))
+}
+
+/// Sends a single notification to the admins for all the changes of a price update.
+pub(crate) async fn notify_price_changes(snapshot: &Snapshot, changes: &[PriceChange]) {
+    if let Some((subject, text)) = notification(changes) {
+        let _ = notify_admins(snapshot, subject, &text).await;
+    }
+}
+
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::Number(cents) => cents
+            .as_u64()
+            .map(|it| format!("{},{:02} €", it / 100, it % 100))
+            .unwrap_or_else(|| cents.to_string()),
+        Value::Object(fees) => fees
+            .iter()
+            .map(|(name, value)| {
+                format!(
+                    "{}: {}",
+                    name.strip_suffix("_in_cents").unwrap_or(name),
+                    format_value(value)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", "),
+        _ => value.to_string(),
+    }
+}
+
+fn describe_changes(changes: &[PriceChange]) -> String {
+    let mut text = String::from("Les tarifs suivants ont été modifiés :\n\n");
+    for change in changes {
+        let name = change.key.strip_prefix("cts/").unwrap_or(&change.key);
+        let _ = match change.old.as_ref() {
+            Some(old) => writeln!(
+                text,
+                "- {name} : {} → {}",
+                format_value(old),
+                format_value(&change.new)
+            ),
+            None => writeln!(text, "- {name} : {}", format_value(&change.new)),
+        };
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_describe_changes() {
+        let changes = vec![
+            PriceChange {
+                key: "cts/adult".to_string(),
+                timestamp: 1_700_000_000,
+                old: Some(json!({
+                    "federal_fee_in_cents": 6200,
+                    "regional_fee_in_cents": 500,
+                    "department_fee_in_cents": 300,
+                })),
+                new: json!({
+                    "federal_fee_in_cents": 6450,
+                    "regional_fee_in_cents": 500,
+                    "department_fee_in_cents": 300,
+                }),
+                federation: true,
+            },
+            PriceChange {
+                key: "cts/adult_structure_fee".to_string(),
+                timestamp: 1_700_000_000,
+                old: Some(json!(4100)),
+                new: json!(3850),
+                federation: false,
+            },
+        ];
+        assert_eq!("pch/1700000000_adult", changes[0].history_key());
+        assert!(is_notified(&changes[0], &[]));
+        assert!(!is_notified(&changes[1], &[]));
+        assert!(is_notified(&changes[1], &changes[..1]));
+        assert!(!is_notified(
+            &PriceChange {
+                old: None,
+                ..changes[0].clone()
+            },
+            &[]
+        ));
+        assert_eq!(
+            Some((
+                "Changement des tarifs",
+                "Les tarifs suivants ont été modifiés :\n\n\
+                - adult : federal_fee: 62,00 €, regional_fee: 5,00 €, department_fee: 3,00 € → \
+                federal_fee: 64,50 €, regional_fee: 5,00 €, department_fee: 3,00 €\n\
+                - adult_structure_fee : 41,00 € → 38,50 €\n"
+                    .to_string()
+            )),
+            notification(&changes)
+        );
+        assert_eq!(
+            Some("Changement des tarifs de la fédération"),
+            notification(&changes[..1]).map(|(subject, _)| subject)
+        );
+        assert_eq!(None, notification(&changes[1..]));
+    }
+}
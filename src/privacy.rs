@@ -0,0 +1,359 @@
+use crate::audit::{history_of, record, record_anonymisation, Actor, AuditEntry};
+use crate::conflict::Conflict;
+use crate::guardian::AgeOut;
+use crate::lifecycle::{anonymise, erase_related_entries, Lifecycle};
+use crate::medical_certificate::{Reminders, UploadedDocument};
+use crate::mycompet::registration::CompetitionRegistrations;
+use crate::order::Order;
+use crate::renewal::RenewalReminder;
+use crate::season::current_season;
+use crate::user::Metadata;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tiered_server::store::Snapshot;
+use tiered_server::user::User;
+use tracing::info;
+
+/// A competition the member was registered for.
+#[derive(Debug, Serialize)]
+pub(crate) struct Registration {
+    competition: String,
+    /// yyyymmdd
+    start_date: u32,
+}
+
+/// A licence the member ordered through the club.
+#[derive(Debug, Serialize)]
+pub(crate) struct LicenseOrder {
+    season: u16,
+    structure_name: String,
+    /// The licence type, insurance level and insurance options.
+    description: String,
+}
+
+/// Everything the server holds about a member, by snapshot key.
+#[derive(Debug, Serialize)]
+pub(crate) struct DataExport {
+    user: User,
+    orders: Vec<LicenseOrder>,
+    renewal_reminders: BTreeMap<String, RenewalReminder>,
+    medical_certificate_reminders: BTreeMap<String, Reminders>,
+    medical_certificates: BTreeMap<String, UploadedDocument>,
+    age_outs: BTreeMap<String, AgeOut>,
+    competition_registrations: Vec<Registration>,
+    /// Contacts that differed between the club and MyFFME.
+    contact_conflicts: BTreeMap<String, Conflict>,
+    /// The changes made to the account and to its MyFFME contacts.
+    history: Vec<AuditEntry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    erasure_request: Option<ErasureRequest>,
+}
+
+/// The entries under the prefix that belong to the member, their keys end with the user id.
+fn entries_of<T: DeserializeOwned>(
+    snapshot: &Snapshot,
+    prefix: &str,
+    user_id: &str,
+) -> BTreeMap<String, T> {
+    let suffix = format!("_{user_id}");
+    snapshot
+        .list::<T>(prefix)
+        .filter(|(key, _)| key.ends_with(&suffix))
+        .map(|(key, value)| (key.to_string(), value))
+        .collect()
+}
+
+/// The licences of the member, from the licence history, oldest first.
+fn orders(user: &User) -> Vec<LicenseOrder> {
+    user.metadata
+        .as_ref()
+        .and_then(|it| serde_json::from_value::<Metadata>(it.clone()).ok())
+        .and_then(|it| it.license_history)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|it| LicenseOrder {
+            season: it.season,
+            structure_name: it.structure_name,
+            description: Order::License(
+                it.license_type,
+                it.insurance_level.unwrap_or_default(),
+                it.insurance_options,
+                None,
+            )
+            .to_string(),
+        })
+        .collect()
+}
+
+/// Collects the data held about a member, for them to download.
+pub(crate) fn export_data(snapshot: &Snapshot, user: User) -> DataExport {
+    let user_id = user.id.to_string();
+    let competition_registrations = snapshot
+        .list::<CompetitionRegistrations>("crg/")
+        .filter(|(_, it)| it.athletes.iter().any(|it| it.user_id == user_id))
+        .map(|(_, it)| Registration {
+            competition: it.competition,
+            start_date: it.start_date,
+        })
+        .collect();
+    // the changes pushed to MyFFME
+    let myffme_key = user
+        .metadata
+        .as_ref()
+        .and_then(|it| serde_json::from_value::<Metadata>(it.clone()).ok())
+        .and_then(|it| it.myffme_user_id)
+        .map(|it| format!("myffme/{it}"))
+        .unwrap_or_default();
+    DataExport {
+        orders: orders(&user),
+        renewal_reminders: entries_of(snapshot, "rnw/", &user_id),
+        medical_certificate_reminders: entries_of(snapshot, "mcr/", &user_id),
+        medical_certificates: entries_of(snapshot, "mcd/", &user_id),
+        age_outs: entries_of(snapshot, "ago/", &user_id),
+        competition_registrations,
+        contact_conflicts: entries_of(snapshot, "cfl/", &user_id),
+        history: history_of(snapshot, &[&format!("acc/{user_id}"), &myffme_key]),
+        erasure_request: snapshot.get::<ErasureRequest>(&ErasureRequest::key(&user_id)),
+        user,
+    }
+}
+
+/// A request from a member to erase their data, applied once an admin approves it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct ErasureRequest {
+    user_id: String,
+    first_name: String,
+    last_name: String,
+    requested: u32,
+    /// When an admin approved the request and the account was anonymised.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    approved: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    approved_by: Option<String>,
+}
+
+impl ErasureRequest {
+    fn key(user_id: &str) -> String {
+        format!("era/{user_id}")
+    }
+}
+
+/// Request body for an admin to approve the erasure request of a member.
+#[derive(Debug, Deserialize)]
+pub(crate) struct ErasureApproval {
+    user_id: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ErasureError {
+    UnknownRequest,
+    /// The member is licensed with the club this season, the federation requires the club to
+    /// keep their data until the end of the season.
+    ActiveMember,
+    Storage,
+}
+
+/// Records the erasure request of a member, or returns the pending one.
+pub(crate) async fn request_erasure(
+    snapshot: &Snapshot,
+    actor: &Actor,
+    user: &User,
+    now: u32,
+) -> Option<ErasureRequest> {
+    let user_id = user.id.to_string();
+    let key = ErasureRequest::key(&user_id);
+    if let Some(request) = snapshot.get::<ErasureRequest>(&key) {
+        return Some(request);
+    }
+    info!(
+        "erasure requested by {} {}",
+        user.first_name, user.last_name
+    );
+    let request = ErasureRequest {
+        user_id,
+        first_name: user.first_name.clone(),
+        last_name: user.last_name.clone(),
+        requested: now,
+        approved: None,
+        approved_by: None,
+    };
+    Snapshot::set_and_wait_for_update(&key, &request).await?;
+    record(actor, &key, None, &request).await;
+    Some(request)
+}
+
+/// Lists the erasure requests waiting for an admin.
+pub(crate) fn pending_erasures(snapshot: &Snapshot) -> Vec<ErasureRequest> {
+    let mut requests = snapshot
+        .list::<ErasureRequest>("era/")
+        .map(|(_, it)| it)
+        .filter(|it| it.approved.is_none())
+        .collect::<Vec<_>>();
+    requests.sort_by_key(|it| it.requested);
+    requests
+}
+
+/// Key of the tombstone of a MyFFME user whose account was anonymised, only a hash of the MyFFME
+/// id is kept.
+pub(crate) fn tombstone_key(myffme_user_id: &str) -> String {
+    // FNV-1a, stable across builds unlike the std hasher
+    let hash = myffme_user_id
+        .bytes()
+        .fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+    format!("tmb/{hash:016x}")
+}
+
+/// Whether the account of the MyFFME user was anonymised, the sync must not create it again.
+pub(crate) fn is_forgotten(snapshot: &Snapshot, myffme_user_id: &str) -> bool {
+    snapshot
+        .get::<u32>(&tombstone_key(myffme_user_id))
+        .is_some()
+}
+
+/// Leaves a tombstone for the MyFFME user of an anonymised account, as the licensee can still be
+/// listed by MyFFME for the past seasons.
+pub(crate) async fn forget_myffme_user(
+    actor: &Actor,
+    myffme_user_id: &str,
+    now: u32,
+) -> Option<()> {
+    let key = tombstone_key(myffme_user_id);
+    Snapshot::set_and_wait_for_update(&key, &now).await?;
+    record(actor, &key, None, &now).await;
+    Some(())
+}
+
+/// Anonymises the account of a member whose erasure request was approved.
+///
+/// The licence history is kept for the accounting, the uploaded documents, reminders and age outs
+/// are deleted, the contact conflicts are anonymised, and the member is removed from the
+/// guardianships and the competition registrations. The sync no longer creates an account for
+/// their MyFFME user.
+pub(crate) async fn approve_erasure(
+    snapshot: &Snapshot,
+    approval: ErasureApproval,
+    admin: &User,
+    now: u32,
+) -> Result<ErasureRequest, ErasureError> {
+    let user_id = approval.user_id.as_str();
+    let request_key = ErasureRequest::key(user_id);
+    let request = snapshot
+        .get::<ErasureRequest>(&request_key)
+        .filter(|it| it.approved.is_none())
+        .ok_or(ErasureError::UnknownRequest)?;
+    let user_key = format!("acc/{user_id}");
+    let mut user = snapshot
+        .get::<User>(&user_key)
+        .ok_or(ErasureError::UnknownRequest)?;
+    let metadata = user
+        .metadata
+        .as_ref()
+        .and_then(|it| serde_json::from_value::<Metadata>(it.clone()).ok())
+        .unwrap_or_default();
+    if metadata.lifecycle == Some(Lifecycle::Active)
+        && metadata.latest_license_season == Some(current_season(None))
+    {
+        return Err(ErasureError::ActiveMember);
+    }
+    let actor = Actor::admin(admin);
+    let myffme_user_id = metadata.myffme_user_id.clone();
+    if let Some(myffme_user_id) = myffme_user_id.as_deref() {
+        forget_myffme_user(&actor, myffme_user_id, now)
+            .await
+            .ok_or(ErasureError::Storage)?;
+    }
+    let before = user.clone();
+    anonymise(&mut user, metadata, now).ok_or(ErasureError::Storage)?;
+    Snapshot::set_and_wait_for_update(&user_key, &user)
+        .await
+        .ok_or(ErasureError::Storage)?;
+    record_anonymisation(snapshot, &actor, &user_key, &before, &user).await;
+    erase_related_entries(snapshot, &actor, user_id, myffme_user_id.as_deref(), now)
+        .await
+        .ok_or(ErasureError::Storage)?;
+    // the names are no longer needed once the request is applied
+    let before = request.clone();
+    let request = ErasureRequest {
+        first_name: String::new(),
+        last_name: String::new(),
+        approved: Some(now),
+        approved_by: Some(admin.id.to_string()),
+        ..request
+    };
+    Snapshot::set_and_wait_for_update(&request_key, &request)
+        .await
+        .ok_or(ErasureError::Storage)?;
+    record_anonymisation(snapshot, &actor, &request_key, &before, &request).await;
+    info!("erased account {user_id}");
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::myffme::{LicenseRecord, LicenseType, MedicalCertificateStatus};
+    use crate::order::{InsuranceLevel, InsuranceOption};
+    use crate::user::test_user;
+    use serde_json::json;
+
+    #[test]
+    fn test_orders() {
+        let user = test_user(
+            "Léa",
+            "Martin",
+            19900101,
+            Metadata {
+                license_history: Some(vec![LicenseRecord {
+                    id: "1".to_string(),
+                    season: 2026,
+                    structure_id: 1,
+                    structure_name: "Club 1".to_string(),
+                    license_type: LicenseType::Adult,
+                    insurance_level: Some(InsuranceLevel::BasePlusPlus),
+                    insurance_options: vec![InsuranceOption::TrailRunning],
+                    medical_certificate_status: MedicalCertificateStatus::Recreational,
+                }]),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            json!([{
+                "season": 2026,
+                "structure_name": "Club 1",
+                "description": "Licence Base++ adulte (option trail)",
+            }]),
+            serde_json::to_value(orders(&user)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_erasure_request() {
+        assert_eq!("era/1", ErasureRequest::key("1"));
+        let request = ErasureRequest {
+            user_id: "1".to_string(),
+            first_name: "Léa".to_string(),
+            last_name: "Martin".to_string(),
+            requested: 1_800_000_000,
+            approved: None,
+            approved_by: None,
+        };
+        assert_eq!(
+            json!({
+                "user_id": "1",
+                "first_name": "Léa",
+                "last_name": "Martin",
+                "requested": 1_800_000_000,
+            }),
+            serde_json::to_value(&request).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_tombstone_key() {
+        assert_eq!("tmb/af63dc4c8601ec8c", tombstone_key("a"));
+        assert_ne!(tombstone_key("1"), tombstone_key("2"));
+    }
+}
@@ -0,0 +1,318 @@
+use crate::audit::{record, Actor};
+use crate::guardian::expected_license_type;
+use crate::myffme::LicenseType;
+use crate::notification::notify_member;
+use crate::order::{InsuranceLevel, Priced};
+use crate::season::{current_season, is_during_discount_period};
+use crate::user::Metadata;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, LazyLock};
+use std::time::SystemTime;
+use tiered_server::env::{secret_value, ConfigurationKey};
+use tiered_server::server::DOMAIN_APEX;
+use tiered_server::store::Snapshot;
+use tiered_server::user::User;
+use tracing::{info, warn};
+
+const CAMPAIGN_START_KEY: ConfigurationKey = ConfigurationKey::Other {
+    variable_name: "RENEWAL_CAMPAIGN_START",
+};
+
+/// First day (mmdd) of the renewal campaign, in the first year of the season.
+static CAMPAIGN_START: LazyLock<u32> = LazyLock::new(|| {
+    secret_value(CAMPAIGN_START_KEY)
+        .and_then(|it| {
+            it.parse::<u32>()
+                .ok()
+                .filter(|it| (1_01..=12_31).contains(it))
+                .or_else(|| {
+                    warn!("invalid renewal campaign start: {it}");
+                    None
+                })
+        })
+        .unwrap_or(9_01)
+});
+
+/// Minimum delay between two reminders.
+const REMINDER_INTERVAL_SECONDS: u32 = 14 * 86_400;
+
+const MAX_REMINDERS: usize = 3;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub(crate) struct RenewalReminder {
+    pub(crate) user_id: String,
+    pub(crate) season: u16,
+    /// Used in the link sent to the member.
+    pub(crate) token: String,
+    /// Timestamps of the reminders.
+    #[serde(default)]
+    pub(crate) sent: Vec<u32>,
+    /// When the link was first opened.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) opened: Option<u32>,
+    /// When the licence for the season was found in MyFFME.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) renewed: Option<u32>,
+}
+
+impl RenewalReminder {
+    fn key(season: u16, user_id: &str) -> String {
+        format!("rnw/{season}_{user_id}")
+    }
+
+    fn is_due(&self, now: u32) -> bool {
+        self.renewed.is_none()
+            && self.sent.len() < MAX_REMINDERS
+            && self
+                .sent
+                .last()
+                .is_none_or(|&it| now >= it + REMINDER_INTERVAL_SECONDS)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct RenewalOffer {
+    first_name: String,
+    season: u16,
+    license_type: LicenseType,
+    insurance_level: InsuranceLevel,
+    price_in_cents: u16,
+}
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32
+}
+
+fn is_campaign_open(season: u16, today: u32) -> bool {
+    today >= (season as u32 - 1) * 1_00_00 + *CAMPAIGN_START
+}
+
+/// Renewal price with the insurance level of the previous licence.
+fn offer(snapshot: &Snapshot, user: &User, metadata: &Metadata, season: u16) -> RenewalOffer {
+    let is_during_discount_period = is_during_discount_period(None);
+    let license_type = expected_license_type(user.date_of_birth, season);
+    let insurance_level = metadata.insurance_level.unwrap_or_default();
+    let price_in_cents = license_type.price_in_cents(snapshot, is_during_discount_period)
+        + insurance_level.price_in_cents(snapshot, is_during_discount_period);
+    RenewalOffer {
+        first_name: user.first_name.clone(),
+        season,
+        license_type,
+        insurance_level,
+        price_in_cents,
+    }
+}
+
+fn format_price(cents: u16) -> String {
+    format!("{},{:02} €", cents / 100, cents % 100)
+}
+
+fn reminder_texts(offer: &RenewalOffer, link: &str) -> (String, String, String) {
+    let seasons = format!("{}-{}", offer.season - 1, offer.season);
+    let price = format_price(offer.price_in_cents);
+    let subject = format!("Renouvellement de votre licence {seasons}");
+    let email = format!(
+        "Bonjour {},\n\n\
+        Votre licence n'a pas encore été renouvelée pour la saison {seasons}.\n\
+        Le renouvellement vous coûtera {price} (licence et assurance).\n\n\
+        Pour renouveler votre licence : {link}\n",
+        offer.first_name
+    );
+    let sms = format!("Pensez à renouveler votre licence {seasons} ({price}) : {link}");
+    (subject, email, sms)
+}
+
+/// Reminds the members of last season that did not renew their licence yet.
+///
+/// Members stop receiving reminders once their licence for the season shows up in MyFFME
+/// (after the member sync), or after a few reminders.
+pub async fn run_renewal_campaign(snapshot: &Arc<Snapshot>, today: u32) -> Option<()> {
+    let season = current_season(None);
+    if !is_campaign_open(season, today) {
+        return Some(());
+    }
+    let now = now();
+    let mut sent = 0;
+    let mut renewed = 0;
+    for (_, user) in snapshot.list::<User>("acc/") {
+        let Some(metadata) = user
+            .metadata
+            .as_ref()
+            .and_then(|it| serde_json::from_value::<Metadata>(it.clone()).ok())
+        else {
+            continue;
+        };
+        let user_id = user.id.to_string();
+        let key = RenewalReminder::key(season, &user_id);
+        let reminder = snapshot.get::<RenewalReminder>(&key);
+        if metadata.latest_license_season == Some(season) {
+            if let Some(mut reminder) = reminder.filter(|it| it.renewed.is_none()) {
+                let before = reminder.clone();
+                reminder.renewed = Some(now);
+                Snapshot::set_and_wait_for_update(&key, &reminder).await?;
+                record(&Actor::Job, &key, Some(&before), &reminder).await;
+                renewed += 1;
+            }
+            continue;
+        }
+        if metadata.latest_license_season != Some(season - 1) {
+            continue;
+        }
+        let before = reminder.clone();
+        let mut reminder = reminder.unwrap_or_else(|| RenewalReminder {
+            user_id,
+            season,
+            token: (0..24).map(|_| fastrand::alphanumeric()).collect(),
+            sent: vec![],
+            opened: None,
+            renewed: None,
+        });
+        if !reminder.is_due(now) {
+            continue;
+        }
+        let link = format!(
+            "https://www.{}/renewal?token={}",
+            *DOMAIN_APEX, reminder.token
+        );
+        let (subject, email, sms) =
+            reminder_texts(&offer(snapshot, &user, &metadata, season), &link);
+        if !notify_member(snapshot, &user, &subject, &email, &sms).await {
+            warn!(
+                "failed to send renewal reminder to {} {}",
+                user.first_name, user.last_name
+            );
+            continue;
+        }
+        reminder.sent.push(now);
+        Snapshot::set_and_wait_for_update(&key, &reminder).await?;
+        record(&Actor::Job, &key, before.as_ref(), &reminder).await;
+        sent += 1;
+    }
+    info!("renewal campaign: {sent} reminder(s) sent, {renewed} renewal(s)");
+    Some(())
+}
+
+/// Records that the member opened the link and returns their renewal offer.
+pub(crate) async fn open_renewal_link(snapshot: &Snapshot, token: &str) -> Option<RenewalOffer> {
+    let season = current_season(None);
+    let (key, mut reminder) = snapshot
+        .list::<RenewalReminder>(&format!("rnw/{season}_"))
+        .find(|(_, it)| it.token == token)?;
+    let user = snapshot.get::<User>(&format!("acc/{}", reminder.user_id))?;
+    let metadata = user
+        .metadata
+        .as_ref()
+        .and_then(|it| serde_json::from_value::<Metadata>(it.clone()).ok())
+        .unwrap_or_default();
+    if reminder.opened.is_none() {
+        let before = reminder.clone();
+        reminder.opened = Some(now());
+        Snapshot::set_and_wait_for_update(&key, &reminder).await?;
+        // the link is only sent to the member
+        record(
+            &Actor::Member(reminder.user_id.clone()),
+            &key,
+            Some(&before),
+            &reminder,
+        )
+        .await;
+    }
+    Some(offer(snapshot, &user, &metadata, season))
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct CampaignStatus {
+    season: u16,
+    reminded: usize,
+    opened: usize,
+    renewed: usize,
+    reminders: Vec<ReminderStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ReminderStatus {
+    user_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    first_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_name: Option<String>,
+    sent: Vec<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    opened: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    renewed: Option<u32>,
+}
+
+pub(crate) fn campaign_status(snapshot: &Snapshot) -> CampaignStatus {
+    let season = current_season(None);
+    let reminders = snapshot
+        .list::<RenewalReminder>(&format!("rnw/{season}_"))
+        .map(|(_, it)| {
+            let user = snapshot.get::<User>(&format!("acc/{}", it.user_id));
+            ReminderStatus {
+                first_name: user.as_ref().map(|it| it.first_name.clone()),
+                last_name: user.map(|it| it.last_name),
+                user_id: it.user_id,
+                sent: it.sent,
+                opened: it.opened,
+                renewed: it.renewed,
+            }
+        })
+        .collect::<Vec<_>>();
+    CampaignStatus {
+        season,
+        reminded: reminders.iter().filter(|it| !it.sent.is_empty()).count(),
+        opened: reminders.iter().filter(|it| it.opened.is_some()).count(),
+        renewed: reminders.iter().filter(|it| it.renewed.is_some()).count(),
+        reminders,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reminders() {
+        let mut reminder = RenewalReminder {
+            user_id: "1".to_string(),
+            season: 2026,
+            token: "abc".to_string(),
+            sent: vec![],
+            opened: None,
+            renewed: None,
+        };
+        assert_eq!("rnw/2026_1", RenewalReminder::key(2026, "1"));
+        assert!(reminder.is_due(1_750_000_000));
+        reminder.sent.push(1_750_000_000);
+        assert!(!reminder.is_due(1_750_000_000 + 86_400));
+        assert!(reminder.is_due(1_750_000_000 + REMINDER_INTERVAL_SECONDS));
+        reminder.sent.push(1_751_000_000);
+        reminder.sent.push(1_752_000_000);
+        assert!(!reminder.is_due(1_760_000_000));
+        reminder.sent.pop();
+        reminder.renewed = Some(1_753_000_000);
+        assert!(!reminder.is_due(1_760_000_000));
+        let (subject, email, sms) = reminder_texts(
+            &RenewalOffer {
+                first_name: "Alice".to_string(),
+                season: 2026,
+                license_type: LicenseType::Adult,
+                insurance_level: InsuranceLevel::Base,
+                price_in_cents: 12_050,
+            },
+            "https://www.example.com/renewal?token=abc",
+        );
+        assert_eq!("Renouvellement de votre licence 2025-2026", subject);
+        assert!(email.starts_with("Bonjour Alice,"));
+        assert!(email.contains("120,50 €"));
+        assert_eq!(
+            "Pensez à renouveler votre licence 2025-2026 (120,50 €) : \
+            https://www.example.com/renewal?token=abc",
+            sms
+        );
+    }
+}
@@ -0,0 +1,376 @@
+use crate::audit::{record_changes, Actor, Change};
+use crate::chrome::{update_chrome_version, USERAGENT_VALIDITY_SECONDS};
+use crate::guardian::run_age_out;
+use crate::lifecycle::archive_alumni;
+use crate::medical_certificate::remind_medical_certificates;
+use crate::mycompet::calendar::{today, update_competition_calendar};
+use crate::mycompet::registration::alert_ineligible_registrations;
+use crate::mycompet::update_competition_results;
+use crate::myffme::{add_missing_users, renew_myffme_bearer_token, update_users_metadata};
+use crate::order::update_prices;
+use crate::renewal::run_renewal_campaign;
+use crate::season::is_during_competition_period;
+use crate::status::{record_prices_update, record_sync, SyncKind};
+use pinboard::Pinboard;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::LazyLock;
+use std::time::{Duration, Instant, SystemTime};
+use tiered_server::store::snapshot;
+use tokio::sync::{watch, Notify};
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+/// Delay before retrying a failed job, doubled after each consecutive failure.
+const RETRY_SECONDS: u32 = 600;
+
+/// How long running jobs are given to complete when the server stops.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum JobName {
+    ChromeVersion,
+    MyffmeToken,
+    Prices,
+    MemberSync,
+    CompetitionResults,
+    CompetitionCalendar,
+    CompetitionRegistrations,
+    RenewalCampaign,
+    MedicalCertificates,
+    AgeOut,
+    Archive,
+}
+
+impl JobName {
+    const ALL: [JobName; 11] = [
+        JobName::ChromeVersion,
+        JobName::MyffmeToken,
+        JobName::Prices,
+        JobName::MemberSync,
+        JobName::CompetitionResults,
+        JobName::CompetitionCalendar,
+        JobName::CompetitionRegistrations,
+        JobName::RenewalCampaign,
+        JobName::MedicalCertificates,
+        JobName::AgeOut,
+        JobName::Archive,
+    ];
+
+    pub(crate) fn from_path_segment(segment: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|it| it.as_str() == segment)
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            JobName::ChromeVersion => "chrome_version",
+            JobName::MyffmeToken => "myffme_token",
+            JobName::Prices => "prices",
+            JobName::MemberSync => "member_sync",
+            JobName::CompetitionResults => "competition_results",
+            JobName::CompetitionCalendar => "competition_calendar",
+            JobName::CompetitionRegistrations => "competition_registrations",
+            JobName::RenewalCampaign => "renewal_campaign",
+            JobName::MedicalCertificates => "medical_certificates",
+            JobName::AgeOut => "age_out",
+            JobName::Archive => "archive",
+        }
+    }
+
+    fn interval_seconds(self) -> u32 {
+        match self {
+            JobName::ChromeVersion => USERAGENT_VALIDITY_SECONDS,
+            // renew well before the token expires (10h)
+            JobName::MyffmeToken => 30_000,
+            JobName::Prices => 86_400,
+            JobName::MemberSync => 86_400,
+            JobName::CompetitionResults => {
+                if is_during_competition_period(None) {
+                    86_400
+                } else {
+                    604_800
+                }
+            }
+            JobName::CompetitionCalendar => 86_400,
+            JobName::CompetitionRegistrations => 86_400,
+            JobName::RenewalCampaign => 86_400,
+            JobName::MedicalCertificates => 86_400,
+            JobName::AgeOut => 86_400,
+            JobName::Archive => 86_400,
+        }
+    }
+
+    /// Zero means that the job runs (and is awaited) before the server starts.
+    fn startup_delay_seconds(self) -> u32 {
+        match self {
+            JobName::ChromeVersion | JobName::MyffmeToken | JobName::Prices => 0,
+            JobName::MemberSync => 600,
+            JobName::CompetitionResults => 1_800,
+            JobName::CompetitionCalendar => 2_400,
+            JobName::CompetitionRegistrations => 3_000,
+            JobName::RenewalCampaign => 3_600,
+            JobName::MedicalCertificates => 4_200,
+            JobName::AgeOut => 4_800,
+            JobName::Archive => 5_400,
+        }
+    }
+
+    async fn run(self) -> bool {
+        match self {
+            JobName::ChromeVersion => update_chrome_version(now()).await,
+            JobName::MyffmeToken => renew_myffme_bearer_token(None).await.is_some(),
+            JobName::Prices => {
+                let success = update_prices().await.is_some();
+                record_prices_update(success);
+                success
+            }
+            JobName::MemberSync => {
+                let result = add_missing_users(&snapshot(), false).await;
+                record_sync(SyncKind::AddMissingUsers, &result);
+                if result.is_err() {
+                    return false;
+                }
+                let result = update_users_metadata(&snapshot(), false).await;
+                record_sync(SyncKind::UpdateUsersMetadata, &result);
+                result.is_ok()
+            }
+            JobName::CompetitionResults => update_competition_results(&snapshot()).await.is_some(),
+            JobName::CompetitionCalendar => update_competition_calendar().await.is_some(),
+            JobName::CompetitionRegistrations => {
+                let timestamp = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs() as u32;
+                alert_ineligible_registrations(&snapshot(), today(timestamp))
+                    .await
+                    .is_some()
+            }
+            JobName::RenewalCampaign => {
+                let timestamp = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs() as u32;
+                run_renewal_campaign(&snapshot(), today(timestamp))
+                    .await
+                    .is_some()
+            }
+            JobName::MedicalCertificates => {
+                let timestamp = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs() as u32;
+                remind_medical_certificates(&snapshot(), today(timestamp))
+                    .await
+                    .is_some()
+            }
+            JobName::AgeOut => run_age_out(&snapshot(), now()).await.is_some(),
+            JobName::Archive => archive_alumni(&snapshot(), now()).await.is_some(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct JobRun {
+    timestamp: u32,
+    success: bool,
+    duration_in_millis: u32,
+}
+
+struct Job {
+    /// Watched by the scheduled task, to run the job as soon as it is resumed.
+    paused: watch::Sender<bool>,
+    running: AtomicBool,
+    consecutive_failures: AtomicU32,
+    next_run: AtomicU32,
+    last_run: Pinboard<JobRun>,
+    trigger: Notify,
+}
+
+impl Job {
+    fn new() -> Self {
+        Self {
+            paused: watch::channel(false).0,
+            running: AtomicBool::new(false),
+            consecutive_failures: AtomicU32::new(0),
+            next_run: AtomicU32::new(0),
+            last_run: Pinboard::new_empty(),
+            trigger: Notify::new(),
+        }
+    }
+}
+
+static JOBS: LazyLock<[Job; JobName::ALL.len()]> =
+    LazyLock::new(|| JobName::ALL.map(|_| Job::new()));
+
+static SHUTDOWN: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::channel(false).0);
+
+#[derive(Serialize)]
+pub(crate) struct JobState {
+    name: JobName,
+    interval_in_seconds: u32,
+    paused: bool,
+    running: bool,
+    consecutive_failures: u32,
+    next_run: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_run: Option<JobRun>,
+}
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32
+}
+
+fn jitter(seconds: u32) -> u32 {
+    let spread = seconds / 10;
+    seconds - spread + fastrand::u32(0..=2 * spread)
+}
+
+fn retry_delay_seconds(consecutive_failures: u32, interval_seconds: u32) -> u32 {
+    RETRY_SECONDS
+        .saturating_mul(1 << consecutive_failures.saturating_sub(1).min(16))
+        .min(interval_seconds)
+}
+
+/// Runs the job and returns the delay until its next run.
+async fn run(name: JobName) -> u32 {
+    let job = &JOBS[name as usize];
+    job.running.store(true, Ordering::Relaxed);
+    info!("running job {}", name.as_str());
+    let t0 = Instant::now();
+    let success = name.run().await;
+    job.last_run.set(JobRun {
+        timestamp: now(),
+        success,
+        duration_in_millis: t0.elapsed().as_millis() as u32,
+    });
+    job.running.store(false, Ordering::Relaxed);
+    if success {
+        job.consecutive_failures.store(0, Ordering::Relaxed);
+        jitter(name.interval_seconds())
+    } else {
+        let failures = job.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        warn!(
+            "job {} failed ({failures} consecutive failures)",
+            name.as_str()
+        );
+        jitter(retry_delay_seconds(failures, name.interval_seconds()))
+    }
+}
+
+async fn schedule(name: JobName, mut delay: u32) {
+    let job = &JOBS[name as usize];
+    let mut shutdown = SHUTDOWN.subscribe();
+    let mut paused = job.paused.subscribe();
+    while !*shutdown.borrow() {
+        job.next_run.store(now() + delay, Ordering::Relaxed);
+        let triggered = tokio::select! {
+            _ = sleep(Duration::from_secs(delay as u64)) => false,
+            _ = job.trigger.notified() => true,
+            _ = shutdown.changed() => break,
+        };
+        // the run that was due while paused happens when the job is resumed
+        if !triggered && *paused.borrow_and_update() {
+            tokio::select! {
+                _ = paused.wait_for(|it| !*it) => {}
+                _ = job.trigger.notified() => {}
+                _ = shutdown.changed() => break,
+            }
+        }
+        delay = run(name).await;
+    }
+    info!("job {} stopped", name.as_str());
+}
+
+/// Stops scheduling jobs, and gives the running ones some time to complete.
+pub async fn stop_scheduler() {
+    info!("stopping jobs");
+    SHUTDOWN.send_replace(true);
+    let t0 = Instant::now();
+    while JOBS.iter().any(|it| it.running.load(Ordering::Relaxed))
+        && t0.elapsed() < SHUTDOWN_GRACE_PERIOD
+    {
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Runs the startup jobs, then schedules every job on the current runtime.
+pub async fn start_scheduler() {
+    for name in JobName::ALL {
+        let delay = match name.startup_delay_seconds() {
+            0 => run(name).await,
+            it => it,
+        };
+        tokio::spawn(schedule(name, delay));
+    }
+}
+
+/// Runs the job as soon as possible, even if it is paused.
+pub(crate) fn trigger_job(name: JobName) -> JobState {
+    JOBS[name as usize].trigger.notify_one();
+    job_state(name)
+}
+
+/// Pauses or resumes the job, and records the change in the audit log.
+pub(crate) async fn pause_job(actor: &Actor, name: JobName, paused: bool) -> JobState {
+    let before = JOBS[name as usize].paused.send_replace(paused);
+    if before != paused {
+        record_changes(
+            actor,
+            &format!("job/{}", name.as_str()),
+            vec![Change {
+                path: "/paused".to_string(),
+                before: Some(before.into()),
+                after: Some(paused.into()),
+            }],
+        )
+        .await;
+    }
+    job_state(name)
+}
+
+pub(crate) fn job_state(name: JobName) -> JobState {
+    let job = &JOBS[name as usize];
+    JobState {
+        name,
+        interval_in_seconds: name.interval_seconds(),
+        paused: *job.paused.borrow(),
+        running: job.running.load(Ordering::Relaxed),
+        consecutive_failures: job.consecutive_failures.load(Ordering::Relaxed),
+        next_run: job.next_run.load(Ordering::Relaxed),
+        last_run: job.last_run.get_ref().map(|it| JobRun::clone(&it)),
+    }
+}
+
+pub(crate) fn job_states() -> Vec<JobState> {
+    JobName::ALL.into_iter().map(job_state).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_names() {
+        for name in JobName::ALL {
+            assert_eq!(Some(name), JobName::from_path_segment(name.as_str()));
+            assert_eq!(
+                serde_json::to_value(name).unwrap().as_str(),
+                Some(name.as_str())
+            );
+        }
+        assert_eq!(None, JobName::from_path_segment("unknown"));
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(600, retry_delay_seconds(1, 86_400));
+        assert_eq!(1_200, retry_delay_seconds(2, 86_400));
+        assert_eq!(4_800, retry_delay_seconds(4, 86_400));
+        assert_eq!(30_000, retry_delay_seconds(8, 30_000));
+        assert_eq!(86_400, retry_delay_seconds(100, 86_400));
+    }
+}
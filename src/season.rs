@@ -52,6 +52,43 @@ pub fn is_during_discount_period(timestamp: Option<u32>) -> bool {
         && current_year_elapsed_seconds < seconds_between_jan_and_august
 }
 
+/// The season of a date (yyyymmdd), seasons start in august.
+pub fn season_of_date(date: u32) -> u16 {
+    let year = (date / 1_00_00) as u16;
+    if date / 1_00 % 1_00 >= 8 {
+        year + 1
+    } else {
+        year
+    }
+}
+
+/// Competitions are held from october to june, there are almost none in the summer.
+pub fn is_during_competition_period(timestamp: Option<u32>) -> bool {
+    let year_2020_utc_start_timestamp = 1577836800_u32;
+    let elapsed = timestamp.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as u32
+    }) - year_2020_utc_start_timestamp;
+    // can be off by 1 but won't change the result
+    let years = elapsed / APPROXIMATE_NUMBER_OF_SECS_IN_YEAR;
+    let current_year_elapsed_seconds = elapsed - years * APPROXIMATE_NUMBER_OF_SECS_IN_YEAR;
+    let years = years as u16;
+    let seconds_between_jan_and_july = if years % 4 == 0 {
+        15_724_800
+    } else {
+        15_638_400
+    };
+    let seconds_between_jan_and_october = if years % 4 == 0 {
+        23_673_600
+    } else {
+        23_587_200
+    };
+    current_year_elapsed_seconds < seconds_between_jan_and_july
+        || current_year_elapsed_seconds > seconds_between_jan_and_october
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -123,4 +160,36 @@ mod tests {
         ));
         assert!(is_during_discount_period(Some(date.timestamp() as u32)));
     }
+
+    #[test]
+    fn test_season_of_date() {
+        assert_eq!(2025, season_of_date(20250118));
+        assert_eq!(2025, season_of_date(20250731));
+        assert_eq!(2026, season_of_date(20250801));
+        assert_eq!(2026, season_of_date(20251231));
+    }
+
+    #[test]
+    fn test_is_during_competition_period() {
+        let date = Utc.from_utc_datetime(&NaiveDateTime::from(
+            NaiveDate::from_ymd_opt(2021, 3, 12).unwrap(),
+        ));
+        assert!(is_during_competition_period(Some(date.timestamp() as u32)));
+        let date = Utc.from_utc_datetime(&NaiveDateTime::from(
+            NaiveDate::from_ymd_opt(2022, 11, 20).unwrap(),
+        ));
+        assert!(is_during_competition_period(Some(date.timestamp() as u32)));
+        let date = Utc.from_utc_datetime(&NaiveDateTime::from(
+            NaiveDate::from_ymd_opt(2024, 6, 29).unwrap(),
+        ));
+        assert!(is_during_competition_period(Some(date.timestamp() as u32)));
+        let date = Utc.from_utc_datetime(&NaiveDateTime::from(
+            NaiveDate::from_ymd_opt(2024, 7, 14).unwrap(),
+        ));
+        assert!(!is_during_competition_period(Some(date.timestamp() as u32)));
+        let date = Utc.from_utc_datetime(&NaiveDateTime::from(
+            NaiveDate::from_ymd_opt(2023, 9, 15).unwrap(),
+        ));
+        assert!(!is_during_competition_period(Some(date.timestamp() as u32)));
+    }
 }
@@ -0,0 +1,442 @@
+use crate::chrome::{CHROME_VERSION, USERAGENT_VALIDITY_SECONDS};
+use crate::hello_asso::{HELLO_ASSO_AUTHORIZATION, HELLO_ASSO_AUTHORIZATION_VALIDITY_SECONDS};
+use crate::myffme::{MYFFME_AUTHORIZATION, MYFFME_AUTHORIZATION_VALIDITY_SECONDS};
+use pinboard::Pinboard;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::LazyLock;
+use std::time::SystemTime;
+use tracing::warn;
+
+pub(crate) struct Failures {
+    pub(crate) chrome_version: AtomicU32,
+    pub(crate) myffme_authorization: AtomicU32,
+    pub(crate) hello_asso_authorization: AtomicU32,
+    pub(crate) prices: AtomicU32,
+    pub(crate) sync: AtomicU32,
+    pub(crate) scraper_drift: AtomicU32,
+}
+
+impl Failures {
+    const fn new() -> Self {
+        Self {
+            chrome_version: AtomicU32::new(0),
+            myffme_authorization: AtomicU32::new(0),
+            hello_asso_authorization: AtomicU32::new(0),
+            prices: AtomicU32::new(0),
+            sync: AtomicU32::new(0),
+            scraper_drift: AtomicU32::new(0),
+        }
+    }
+}
+
+pub(crate) static FAILURES: Failures = Failures::new();
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SyncKind {
+    AddMissingUsers,
+    UpdateUsersMetadata,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ScrapedPage {
+    CompetitionResults,
+    CompetitionCalendar,
+}
+
+/// What was found while parsing a scraped table.
+///
+/// Rows are skipped when they can't be used at all, and flagged when some of their cells
+/// could not be parsed.
+#[derive(Debug, Default)]
+pub(crate) struct ScrapedTable {
+    pub(crate) found_headers: Vec<&'static str>,
+    pub(crate) skipped_rows: u32,
+    pub(crate) flagged_rows: u32,
+}
+
+#[derive(Clone)]
+pub(crate) struct ScraperReport {
+    pub(crate) timestamp: u32,
+    /// Column headers found on the latest page.
+    pub(crate) found_headers: Vec<&'static str>,
+    /// Required headers missing from the latest page.
+    pub(crate) missing_headers: Vec<&'static str>,
+    /// When headers started to be missing.
+    pub(crate) drift_since: Option<u32>,
+    pub(crate) skipped_rows: u32,
+    pub(crate) flagged_rows: u32,
+}
+
+#[derive(Clone)]
+pub(crate) struct SyncReport {
+    pub(crate) timestamp: u32,
+    pub(crate) success: bool,
+    pub(crate) output: Option<String>,
+}
+
+/// The latest reports of the background tasks, kept in memory.
+struct Reports {
+    failures: &'static Failures,
+    /// Timestamp of the last successful price update.
+    prices_update: Pinboard<u32>,
+    add_missing_users: Pinboard<SyncReport>,
+    update_users_metadata: Pinboard<SyncReport>,
+    competition_results: Pinboard<ScraperReport>,
+    competition_calendar: Pinboard<ScraperReport>,
+}
+
+static REPORTS: LazyLock<Reports> = LazyLock::new(|| Reports::new(&FAILURES));
+
+impl Reports {
+    fn new(failures: &'static Failures) -> Self {
+        Self {
+            failures,
+            prices_update: Pinboard::new_empty(),
+            add_missing_users: Pinboard::new_empty(),
+            update_users_metadata: Pinboard::new_empty(),
+            competition_results: Pinboard::new_empty(),
+            competition_calendar: Pinboard::new_empty(),
+        }
+    }
+
+    fn scraper_report(&self, page: ScrapedPage) -> &Pinboard<ScraperReport> {
+        match page {
+            ScrapedPage::CompetitionResults => &self.competition_results,
+            ScrapedPage::CompetitionCalendar => &self.competition_calendar,
+        }
+    }
+}
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32
+}
+
+pub(crate) fn record_prices_update(success: bool) {
+    REPORTS.record_prices_update(success)
+}
+
+pub(crate) fn record_sync(kind: SyncKind, result: &Result<Option<String>, String>) {
+    REPORTS.record_sync(kind, result)
+}
+
+/// Records the column headers found on a scraped page to detect changes of the page layout.
+pub(crate) fn record_scraped_page(
+    page: ScrapedPage,
+    required_headers: &[&'static str],
+    table: &ScrapedTable,
+) {
+    REPORTS.record_scraped_page(page, required_headers, table)
+}
+
+pub(crate) fn status() -> Status {
+    REPORTS.status()
+}
+
+impl Reports {
+    fn record_prices_update(&self, success: bool) {
+        if success {
+            self.prices_update.set(now());
+        } else {
+            self.failures.prices.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn record_sync(&self, kind: SyncKind, result: &Result<Option<String>, String>) {
+        let report = match result {
+            Ok(output) => SyncReport {
+                timestamp: now(),
+                success: true,
+                output: output.clone(),
+            },
+            Err(err) => {
+                self.failures.sync.fetch_add(1, Ordering::Relaxed);
+                SyncReport {
+                    timestamp: now(),
+                    success: false,
+                    output: Some(err.clone()),
+                }
+            }
+        };
+        match kind {
+            SyncKind::AddMissingUsers => self.add_missing_users.set(report),
+            SyncKind::UpdateUsersMetadata => self.update_users_metadata.set(report),
+        }
+    }
+
+    fn record_scraped_page(
+        &self,
+        page: ScrapedPage,
+        required_headers: &[&'static str],
+        table: &ScrapedTable,
+    ) {
+        let found_headers = table.found_headers.clone();
+        let (skipped_rows, flagged_rows) = (table.skipped_rows, table.flagged_rows);
+        let report = self.scraper_report(page);
+        let previous = report.get_ref().map(|it| ScraperReport::clone(&it));
+        let missing_headers = required_headers
+            .iter()
+            .filter(|it| !found_headers.contains(*it))
+            .copied()
+            .collect::<Vec<_>>();
+        let now = now();
+        let drift_since = if missing_headers.is_empty() {
+            None
+        } else if let Some(since) = previous.as_ref().and_then(|it| it.drift_since) {
+            Some(since)
+        } else {
+            warn!(
+                "{page:?} page is missing headers: {}",
+                missing_headers.join(", ")
+            );
+            self.failures.scraper_drift.fetch_add(1, Ordering::Relaxed);
+            Some(now)
+        };
+        if skipped_rows > 0 || flagged_rows > 0 {
+            warn!("{page:?} page: {skipped_rows} row(s) skipped, {flagged_rows} row(s) flagged");
+        }
+        report.set(ScraperReport {
+            timestamp: now,
+            found_headers,
+            missing_headers,
+            drift_since,
+            skipped_rows,
+            flagged_rows,
+        });
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct Status {
+    timestamp: u32,
+    myffme_authorization: Option<TimestampedState>,
+    hello_asso_authorization: Option<TimestampedState>,
+    chrome_version: Option<ChromeVersionState>,
+    prices: Option<TimestampedState>,
+    sync: Vec<SyncState>,
+    scrapers: Vec<ScraperState>,
+    failures: FailureCounts,
+}
+
+#[derive(Serialize)]
+struct TimestampedState {
+    timestamp: u32,
+    age_in_seconds: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expired: Option<bool>,
+}
+
+impl TimestampedState {
+    fn new(now: u32, timestamp: u32, validity_seconds: Option<u32>) -> Self {
+        let age_in_seconds = now.saturating_sub(timestamp);
+        Self {
+            timestamp,
+            age_in_seconds,
+            expired: validity_seconds.map(|it| age_in_seconds > it),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ChromeVersionState {
+    version: u16,
+    #[serde(flatten)]
+    state: TimestampedState,
+}
+
+#[derive(Serialize)]
+struct SyncState {
+    kind: SyncKind,
+    success: bool,
+    #[serde(flatten)]
+    state: TimestampedState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    report: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ScraperState {
+    page: ScrapedPage,
+    #[serde(flatten)]
+    state: TimestampedState,
+    found_headers: Vec<&'static str>,
+    missing_headers: Vec<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    drift_since: Option<u32>,
+    skipped_rows: u32,
+    flagged_rows: u32,
+}
+
+#[derive(Serialize)]
+struct FailureCounts {
+    chrome_version: u32,
+    myffme_authorization: u32,
+    hello_asso_authorization: u32,
+    prices: u32,
+    sync: u32,
+    scraper_drift: u32,
+}
+
+impl Reports {
+    fn status(&self) -> Status {
+        let now = now();
+        let failures = self.failures;
+        let sync = [
+            (SyncKind::AddMissingUsers, &self.add_missing_users),
+            (SyncKind::UpdateUsersMetadata, &self.update_users_metadata),
+        ]
+        .into_iter()
+        .filter_map(|(kind, report)| {
+            report.get_ref().map(|it| SyncState {
+                kind,
+                success: it.success,
+                state: TimestampedState::new(now, it.timestamp, None),
+                report: it.output.clone(),
+            })
+        })
+        .collect();
+        let scrapers = [
+            ScrapedPage::CompetitionResults,
+            ScrapedPage::CompetitionCalendar,
+        ]
+        .into_iter()
+        .filter_map(|page| {
+            self.scraper_report(page).get_ref().map(|it| ScraperState {
+                page,
+                state: TimestampedState::new(now, it.timestamp, None),
+                found_headers: it.found_headers.clone(),
+                missing_headers: it.missing_headers.clone(),
+                drift_since: it.drift_since,
+                skipped_rows: it.skipped_rows,
+                flagged_rows: it.flagged_rows,
+            })
+        })
+        .collect();
+        Status {
+            timestamp: now,
+            myffme_authorization: MYFFME_AUTHORIZATION.get_ref().map(|it| {
+                TimestampedState::new(
+                    now,
+                    it.timestamp,
+                    Some(MYFFME_AUTHORIZATION_VALIDITY_SECONDS),
+                )
+            }),
+            hello_asso_authorization: HELLO_ASSO_AUTHORIZATION.get_ref().map(|it| {
+                TimestampedState::new(
+                    now,
+                    it.timestamp,
+                    Some(HELLO_ASSO_AUTHORIZATION_VALIDITY_SECONDS),
+                )
+            }),
+            chrome_version: CHROME_VERSION.get_ref().map(|it| ChromeVersionState {
+                version: it.chrome_version,
+                state: TimestampedState::new(now, it.timestamp, Some(USERAGENT_VALIDITY_SECONDS)),
+            }),
+            prices: self
+                .prices_update
+                .get_ref()
+                .map(|it| TimestampedState::new(now, *it, None)),
+            sync,
+            scrapers,
+            failures: FailureCounts {
+                chrome_version: failures.chrome_version.load(Ordering::Relaxed),
+                myffme_authorization: failures.myffme_authorization.load(Ordering::Relaxed),
+                hello_asso_authorization: failures.hello_asso_authorization.load(Ordering::Relaxed),
+                prices: failures.prices.load(Ordering::Relaxed),
+                sync: failures.sync.load(Ordering::Relaxed),
+                scraper_drift: failures.scraper_drift.load(Ordering::Relaxed),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each test has its own reports, the global ones are shared with the other tests.
+    fn reports() -> Reports {
+        Reports::new(Box::leak(Box::new(Failures::new())))
+    }
+
+    #[test]
+    fn test_status() {
+        let reports = reports();
+        reports.record_prices_update(true);
+        reports.record_sync(SyncKind::UpdateUsersMetadata, &Err("failed".to_string()));
+        let status = serde_json::to_value(reports.status()).unwrap();
+        assert!(status["prices"]["timestamp"].as_u64().is_some());
+        assert!(status["prices"].get("expired").is_none());
+        let sync = status["sync"].as_array().unwrap();
+        assert_eq!(1, sync.len());
+        assert_eq!("update_users_metadata", sync[0]["kind"]);
+        assert_eq!(Some(false), sync[0]["success"].as_bool());
+        assert_eq!("failed", sync[0]["report"]);
+        assert_eq!(Some(1), status["failures"]["sync"].as_u64());
+    }
+
+    #[test]
+    fn test_record_scraped_page() {
+        let reports = reports();
+        let page = ScrapedPage::CompetitionCalendar;
+        reports.record_scraped_page(
+            page,
+            &["Date", "Nom"],
+            &ScrapedTable {
+                found_headers: vec!["Date", "Nom", "Lieu"],
+                ..Default::default()
+            },
+        );
+        let report = ScraperReport::clone(&reports.scraper_report(page).get_ref().unwrap());
+        assert!(report.missing_headers.is_empty());
+        assert_eq!(None, report.drift_since);
+        reports.record_scraped_page(
+            page,
+            &["Date", "Nom"],
+            &ScrapedTable {
+                found_headers: vec!["Date", "Lieu"],
+                skipped_rows: 1,
+                flagged_rows: 2,
+            },
+        );
+        let report = ScraperReport::clone(&reports.scraper_report(page).get_ref().unwrap());
+        assert_eq!(vec!["Nom"], report.missing_headers);
+        assert!(report.drift_since.is_some());
+        assert_eq!(1, report.skipped_rows);
+        assert_eq!(2, report.flagged_rows);
+        reports.record_scraped_page(
+            page,
+            &["Date", "Nom"],
+            &ScrapedTable {
+                found_headers: vec!["Date", "Nom"],
+                ..Default::default()
+            },
+        );
+        let report = ScraperReport::clone(&reports.scraper_report(page).get_ref().unwrap());
+        // optional headers that are no longer found are not a drift
+        assert!(report.missing_headers.is_empty());
+        assert_eq!(None, report.drift_since);
+        reports.record_scraped_page(
+            page,
+            &["Date", "Nom"],
+            &ScrapedTable {
+                found_headers: vec!["Nom"],
+                ..Default::default()
+            },
+        );
+        let status = serde_json::to_value(reports.status()).unwrap();
+        let scrapers = status["scrapers"].as_array().unwrap();
+        let calendar = scrapers
+            .iter()
+            .find(|it| it["page"] == "competition_calendar")
+            .unwrap();
+        assert_eq!("Nom", calendar["found_headers"][0]);
+        assert_eq!("Date", calendar["missing_headers"][0]);
+        assert_eq!(Some(2), status["failures"]["scraper_drift"].as_u64());
+    }
+}
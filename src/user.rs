@@ -1,6 +1,12 @@
+use crate::conflict::Contacts;
 use crate::emergency_contact::EmergencyContact;
+use crate::lifecycle::Lifecycle;
+use crate::medical_certificate::MedicalCertificate;
 use crate::myffme::address::Address;
-use crate::myffme::{CompetitionResult, Gender, LicenseType, MedicalCertificateStatus, Structure};
+use crate::myffme::{
+    CompetitionResult, Gender, LicenseRecord, LicenseType, MedicalCertificateStatus, Structure,
+};
+use crate::order::{InsuranceLevel, InsuranceOption};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize, Default)]
@@ -12,16 +18,71 @@ pub struct Metadata {
     pub license_type: Option<LicenseType>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub medical_certificate_status: Option<MedicalCertificateStatus>,
+    /// Medical certificate or health questionnaire of the latest licence.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub medical_certificate: Option<MedicalCertificate>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub latest_license_season: Option<u16>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub latest_structure: Option<Structure>,
+    /// Insurance level of the latest licence.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub insurance_level: Option<InsuranceLevel>,
+    /// Insurance options of the latest licence.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub insurance_options: Option<Vec<InsuranceOption>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub competition_results: Option<Vec<CompetitionResult>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub emergency_contacts: Option<Vec<EmergencyContact>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub address: Option<Address>,
+    /// Licences of every season, oldest first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub license_history: Option<Vec<LicenseRecord>>,
+    /// Ids of the minors this account is a guardian of.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guardian_of: Option<Vec<String>>,
+    /// MyFFME users an admin unlinked from this account, they are not matched again automatically.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unlinked_myffme_user_ids: Option<Vec<String>>,
+    /// Where the member stands with the club, computed from the licence history.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lifecycle: Option<Lifecycle>,
+    /// When the account was anonymised.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archived: Option<u32>,
+    /// Contacts that were the same locally and in MyFFME after the last sync.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub synced_contacts: Option<Contacts>,
+}
+
+/// Account with the given names, date of birth and metadata, without any identification method.
+#[cfg(test)]
+pub(crate) fn test_user(
+    first_name: &str,
+    last_name: &str,
+    date_of_birth: u32,
+    metadata: Metadata,
+) -> tiered_server::user::User {
+    tiered_server::user::User {
+        id: tiered_server::user::User::new_id(0),
+        identification: vec![],
+        last_name: last_name.to_string(),
+        normalized_last_name: last_name.to_lowercase(),
+        first_name: first_name.to_string(),
+        normalized_first_name: first_name.to_lowercase(),
+        date_of_birth,
+        admin: false,
+        metadata: Some(serde_json::to_value(metadata).unwrap()),
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn test_email(address: &str) -> tiered_server::user::IdentificationMethod {
+    tiered_server::user::IdentificationMethod::Email(tiered_server::user::Email::from(
+        address.to_string(),
+    ))
 }
 
 #[cfg(test)]